@@ -8,11 +8,14 @@
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
+use std::slice;
 
 use miette::{bail, ensure, miette, Result};
 use rand::prelude::*;
+use smartstring::{LazyCompact, SmartString};
 
-use crate::data::value::DataValue;
+use crate::data::functions::{op_to_string, stable_hash, with_rng};
+use crate::data::value::{DataValue, Num};
 
 pub(crate) struct Aggregation {
     pub(crate) name: &'static str,
@@ -35,6 +38,11 @@ impl Clone for Aggregation {
 pub(crate) trait NormalAggrObj: Send + Sync {
     fn set(&mut self, value: &DataValue) -> Result<()>;
     fn get(&self) -> Result<DataValue>;
+    /// Whether further values can change the result. Once this is false the driver may stop
+    /// calling `set`, so later values are neither checked nor counted.
+    fn wants_more(&self) -> bool {
+        true
+    }
 }
 
 pub(crate) trait MeetAggrObj: Send + Sync {
@@ -89,6 +97,10 @@ impl NormalAggrObj for AggrAnd {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::from(self.accum))
     }
+
+    fn wants_more(&self) -> bool {
+        self.accum
+    }
 }
 
 pub(crate) struct MeetAggrAnd;
@@ -129,6 +141,10 @@ impl NormalAggrObj for AggrOr {
     fn get(&self) -> Result<DataValue> {
         Ok(DataValue::from(self.accum))
     }
+
+    fn wants_more(&self) -> bool {
+        !self.accum
+    }
 }
 
 pub(crate) struct MeetAggrOr;
@@ -214,6 +230,72 @@ impl NormalAggrObj for AggrCountUnique {
     }
 }
 
+define_aggr!(AGGR_APPROX_COUNT_UNIQUE, false);
+
+/// Estimates the number of distinct non-null values with a HyperLogLog sketch of
+/// `2^precision` registers, using the hashes of `hash`. The standard error is about
+/// `1.04 / sqrt(2^precision)`, e.g. 1.6% for the default precision of 12.
+pub(crate) struct AggrApproxCountUnique {
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+impl AggrApproxCountUnique {
+    const DEFAULT_PRECISION: u32 = 12;
+
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let precision = match args {
+            [] => Self::DEFAULT_PRECISION,
+            [DataValue::Num(Num::Int(p))] if (4..=16).contains(p) => *p as u32,
+            [v] => bail!(
+                "the precision for 'approx_count_unique' must be an integer between 4 and 16, got {:?}",
+                v
+            ),
+            _ => bail!(
+                "'approx_count_unique' takes at most one argument besides the aggregated variable"
+            ),
+        };
+        Ok(Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        })
+    }
+}
+
+impl NormalAggrObj for AggrApproxCountUnique {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        if *value == DataValue::Null {
+            return Ok(());
+        }
+        let hash = stable_hash(value);
+        let idx = (hash >> (64 - self.precision)) as usize;
+        // the position of the first set bit of the remaining bits, counting from 1
+        let rank = ((hash << self.precision).leading_zeros() + 1).min(65 - self.precision) as u8;
+        if rank > self.registers[idx] {
+            self.registers[idx] = rank;
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1. + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let mut estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        // linear counting is more accurate for small cardinalities
+        if estimate <= 2.5 * m && zeros > 0 {
+            estimate = m * (m / zeros as f64).ln();
+        }
+        Ok(DataValue::from(estimate.round() as i64))
+    }
+}
+
 define_aggr!(AGGR_UNION, true);
 
 #[derive(Default)]
@@ -385,6 +467,149 @@ impl NormalAggrObj for AggrCollect {
     }
 }
 
+define_aggr!(AGGR_ARRAY_AGG, false);
+define_aggr!(AGGR_ARRAY_AGG_DISTINCT, false);
+
+enum ArrayAggOrder {
+    Input,
+    Asc,
+    Desc,
+}
+
+/// Unlike `collect`, nulls are skipped unless asked for, and the result can be sorted
+pub(crate) struct AggrArrayAgg {
+    order: ArrayAggOrder,
+    keep_nulls: bool,
+    seen: Option<BTreeSet<DataValue>>,
+    accum: Vec<DataValue>,
+}
+
+impl AggrArrayAgg {
+    fn new(distinct: bool, args: &[DataValue]) -> Result<Self> {
+        let name = if distinct {
+            "array_agg_distinct"
+        } else {
+            "array_agg"
+        };
+        ensure!(
+            args.len() <= 2,
+            "'{}' takes at most two arguments besides the aggregated variable",
+            name
+        );
+        let order = match args.first() {
+            None | Some(DataValue::Null) => ArrayAggOrder::Input,
+            Some(DataValue::Str(s)) if s == "asc" => ArrayAggOrder::Asc,
+            Some(DataValue::Str(s)) if s == "desc" => ArrayAggOrder::Desc,
+            Some(v) => bail!(
+                "the ordering argument to '{}' must be 'asc', 'desc' or null, got {:?}",
+                name,
+                v
+            ),
+        };
+        let keep_nulls = match args.get(1) {
+            None => false,
+            Some(DataValue::Bool(b)) => *b,
+            Some(v) => bail!(
+                "the argument to '{}' for keeping nulls must be a boolean, got {:?}",
+                name,
+                v
+            ),
+        };
+        Ok(Self {
+            order,
+            keep_nulls,
+            seen: distinct.then(BTreeSet::new),
+            accum: vec![],
+        })
+    }
+}
+
+impl NormalAggrObj for AggrArrayAgg {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        if *value == DataValue::Null && !self.keep_nulls {
+            return Ok(());
+        }
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(value.clone()) {
+                return Ok(());
+            }
+        }
+        self.accum.push(value.clone());
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let mut ret = self.accum.clone();
+        match self.order {
+            ArrayAggOrder::Input => {}
+            ArrayAggOrder::Asc => ret.sort(),
+            ArrayAggOrder::Desc => ret.sort_by(|a, b| b.cmp(a)),
+        }
+        Ok(DataValue::List(ret))
+    }
+}
+
+define_aggr!(AGGR_GROUP_CONCAT, false);
+define_aggr!(AGGR_GROUP_CONCAT_DISTINCT, false);
+
+/// Values that are not strings are converted as by `to_string`, nulls are skipped
+pub(crate) struct AggrGroupConcat {
+    separator: SmartString<LazyCompact>,
+    seen: Option<BTreeSet<DataValue>>,
+    accum: Option<String>,
+}
+
+impl AggrGroupConcat {
+    fn new(distinct: bool, args: &[DataValue]) -> Result<Self> {
+        let name = if distinct {
+            "group_concat_distinct"
+        } else {
+            "group_concat"
+        };
+        let separator = match args {
+            [] => ",".into(),
+            [DataValue::Str(s)] => s.clone(),
+            [v] => bail!("the separator for '{}' must be a string, got {:?}", name, v),
+            _ => bail!(
+                "'{}' takes at most one argument besides the aggregated variable",
+                name
+            ),
+        };
+        Ok(Self {
+            separator,
+            seen: distinct.then(BTreeSet::new),
+            accum: None,
+        })
+    }
+}
+
+impl NormalAggrObj for AggrGroupConcat {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        if *value == DataValue::Null {
+            return Ok(());
+        }
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(value.clone()) {
+                return Ok(());
+            }
+        }
+        let s = op_to_string(slice::from_ref(value))?;
+        let s = s.get_str().unwrap();
+        match &mut self.accum {
+            None => self.accum = Some(s.to_string()),
+            Some(accum) => {
+                accum.push_str(&self.separator);
+                accum.push_str(s);
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.accum.as_deref().unwrap_or_default()))
+    }
+}
+
 define_aggr!(AGGR_CHOICE_RAND, false);
 
 pub(crate) struct AggrChoiceRand {
@@ -405,7 +630,7 @@ impl NormalAggrObj for AggrChoiceRand {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         self.count += 1;
         let prob = 1. / (self.count as f64);
-        let rd = thread_rng().gen::<f64>();
+        let rd = with_rng(|rng| rng.gen::<f64>());
         if rd < prob {
             self.value = value.clone();
         }
@@ -417,6 +642,53 @@ impl NormalAggrObj for AggrChoiceRand {
     }
 }
 
+define_aggr!(AGGR_SAMPLE, false);
+
+/// A uniform random sample of at most `n` values, by reservoir sampling. The values are
+/// in no particular order.
+pub(crate) struct AggrSample {
+    n: usize,
+    seen: usize,
+    sample: Vec<DataValue>,
+}
+
+impl AggrSample {
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let n = match args {
+            [DataValue::Num(Num::Int(n))] if *n > 0 => *n as usize,
+            [v] => bail!(
+                "the argument to 'sample' must be a positive integer, got {:?}",
+                v
+            ),
+            _ => bail!("'sample' requires exactly one argument besides the aggregated variable"),
+        };
+        Ok(Self {
+            n,
+            seen: 0,
+            sample: vec![],
+        })
+    }
+}
+
+impl NormalAggrObj for AggrSample {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.seen += 1;
+        if self.sample.len() < self.n {
+            self.sample.push(value.clone());
+        } else {
+            let idx = with_rng(|rng| rng.gen_range(0..self.seen));
+            if idx < self.n {
+                self.sample[idx] = value.clone();
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::List(self.sample.clone()))
+    }
+}
+
 define_aggr!(AGGR_COUNT, false);
 
 #[derive(Default)]
@@ -436,63 +708,128 @@ impl NormalAggrObj for AggrCount {
 }
 
 define_aggr!(AGGR_VARIANCE, false);
+define_aggr!(AGGR_VARIANCE_POP, false);
+define_aggr!(AGGR_STD_DEV, false);
+define_aggr!(AGGR_STD_DEV_POP, false);
 
+/// Uses Welford's online algorithm, which does not lose precision
+/// when the variance is small compared to the mean.
+/// Nulls are skipped, and null is returned if there are too few values:
+/// at least two are required for the sample variants, and one for the population variants.
 #[derive(Default)]
 pub(crate) struct AggrVariance {
+    name: &'static str,
+    population: bool,
+    std_dev: bool,
     count: i64,
-    sum: f64,
-    sum_sq: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl AggrVariance {
+    fn new(name: &'static str, population: bool, std_dev: bool) -> Self {
+        Self {
+            name,
+            population,
+            std_dev,
+            ..Default::default()
+        }
+    }
 }
 
 impl NormalAggrObj for AggrVariance {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         match value {
+            DataValue::Null => {}
             DataValue::Num(n) => {
                 let f = n.get_float();
-                self.sum += f;
-                self.sum_sq += f * f;
                 self.count += 1;
+                let delta = f - self.mean;
+                self.mean += delta / self.count as f64;
+                self.m2 += delta * (f - self.mean);
             }
-            v => bail!("cannot compute 'variance': encountered value {:?}", v),
+            v => bail!("cannot compute '{}': encountered value {:?}", self.name, v),
         }
         Ok(())
     }
 
     fn get(&self) -> Result<DataValue> {
-        let ct = self.count as f64;
-        Ok(DataValue::from(
-            (self.sum_sq - self.sum * self.sum / ct) / (ct - 1.),
-        ))
+        let dof = if self.population {
+            self.count
+        } else {
+            self.count - 1
+        };
+        if dof < 1 {
+            return Ok(DataValue::Null);
+        }
+        let var = self.m2 / dof as f64;
+        Ok(DataValue::from(if self.std_dev { var.sqrt() } else { var }))
     }
 }
 
-define_aggr!(AGGR_STD_DEV, false);
+define_aggr!(AGGR_PERCENTILE, false);
+define_aggr!(AGGR_MEDIAN, false);
 
-#[derive(Default)]
-pub(crate) struct AggrStdDev {
-    count: i64,
-    sum: f64,
-    sum_sq: f64,
+/// Interpolates linearly between the two closest ranks: for `n` values sorted
+/// in ascending order, the fraction `p` corresponds to the fractional index `p * (n - 1)`.
+/// Nulls are skipped, and null is returned if there are no values.
+pub(crate) struct AggrPercentile {
+    name: &'static str,
+    fraction: f64,
+    accum: Vec<f64>,
+}
+
+impl AggrPercentile {
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let fraction = match args {
+            [DataValue::Num(n)] => n.get_float(),
+            [v] => bail!("the argument to 'percentile' must be a number, got {:?}", v),
+            _ => {
+                bail!("'percentile' requires exactly one argument besides the aggregated variable")
+            }
+        };
+        ensure!(
+            (0. ..=1.).contains(&fraction),
+            "the argument to 'percentile' must be between 0 and 1, got {}",
+            fraction
+        );
+        Ok(Self {
+            name: "percentile",
+            fraction,
+            accum: vec![],
+        })
+    }
+
+    fn median() -> Self {
+        Self {
+            name: "median",
+            fraction: 0.5,
+            accum: vec![],
+        }
+    }
 }
 
-impl NormalAggrObj for AggrStdDev {
+impl NormalAggrObj for AggrPercentile {
     fn set(&mut self, value: &DataValue) -> Result<()> {
         match value {
-            DataValue::Num(n) => {
-                let f = n.get_float();
-                self.sum += f;
-                self.sum_sq += f * f;
-                self.count += 1;
-            }
-            v => bail!("cannot compute 'std_dev': encountered value {:?}", v),
+            DataValue::Null => {}
+            DataValue::Num(n) => self.accum.push(n.get_float()),
+            v => bail!("cannot compute '{}': encountered value {:?}", self.name, v),
         }
         Ok(())
     }
 
     fn get(&self) -> Result<DataValue> {
-        let ct = self.count as f64;
-        let var = (self.sum_sq - self.sum * self.sum / ct) / (ct - 1.);
-        Ok(DataValue::from(var.sqrt()))
+        if self.accum.is_empty() {
+            return Ok(DataValue::Null);
+        }
+        let mut sorted = self.accum.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = self.fraction * (sorted.len() - 1) as f64;
+        let lo = rank.floor() as usize;
+        let hi = rank.ceil() as usize;
+        let ret = sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64);
+        Ok(DataValue::from(ret))
     }
 }
 
@@ -756,6 +1093,139 @@ impl NormalAggrObj for AggrLatestBy {
     }
 }
 
+define_aggr!(AGGR_FIRST, false);
+define_aggr!(AGGR_LAST, false);
+
+/// Without arguments, the first or last non-null value in input order is kept.
+/// With `true` as argument, each value must be a list `[value, key]`, and the value
+/// with the smallest (for `first`) or largest (for `last`) key is kept instead.
+pub(crate) struct AggrFirstLast {
+    last: bool,
+    keyed: bool,
+    found: DataValue,
+    key: Option<DataValue>,
+}
+
+impl AggrFirstLast {
+    fn new(last: bool, args: &[DataValue]) -> Result<Self> {
+        let name = if last { "last" } else { "first" };
+        let keyed = match args {
+            [] => false,
+            [DataValue::Bool(b)] => *b,
+            [v] => bail!(
+                "the argument to '{}' for ordering by key must be a boolean, got {:?}",
+                name,
+                v
+            ),
+            _ => bail!(
+                "'{}' takes at most one argument besides the aggregated variable",
+                name
+            ),
+        };
+        Ok(Self {
+            last,
+            keyed,
+            found: DataValue::Null,
+            key: None,
+        })
+    }
+}
+
+impl NormalAggrObj for AggrFirstLast {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let (value, key) = if self.keyed {
+            match value {
+                DataValue::List(l) if l.len() == 2 => (&l[0], Some(&l[1])),
+                v => bail!(
+                    "'{}' ordered by key requires lists of exactly two items, got {:?}",
+                    if self.last { "last" } else { "first" },
+                    v
+                ),
+            }
+        } else {
+            (value, None)
+        };
+        if *value == DataValue::Null {
+            return Ok(());
+        }
+        let replace = match (&self.key, key) {
+            (Some(prev), Some(key)) => {
+                if self.last {
+                    key >= prev
+                } else {
+                    key < prev
+                }
+            }
+            _ => self.last || self.found == DataValue::Null,
+        };
+        if replace {
+            self.found = value.clone();
+            self.key = key.cloned();
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(self.found.clone())
+    }
+
+    fn wants_more(&self) -> bool {
+        self.last || self.keyed || self.found == DataValue::Null
+    }
+}
+
+define_aggr!(AGGR_ARG_MAX, false);
+define_aggr!(AGGR_ARG_MIN, false);
+
+/// Each value must be a list `[value, key]`, and the value with the largest (for `arg_max`)
+/// or smallest (for `arg_min`) key is kept. Of values with equal keys the first one seen is
+/// kept, and values with null keys are ignored.
+pub(crate) struct AggrArgMaxMin {
+    max: bool,
+    found: DataValue,
+    key: Option<DataValue>,
+}
+
+impl AggrArgMaxMin {
+    fn new(max: bool) -> Self {
+        Self {
+            max,
+            found: DataValue::Null,
+            key: None,
+        }
+    }
+}
+
+impl NormalAggrObj for AggrArgMaxMin {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let (value, key) = match value {
+            DataValue::List(l) if l.len() == 2 => (&l[0], &l[1]),
+            v => bail!(
+                "'{}' requires lists of exactly two items, got {:?}",
+                if self.max { "arg_max" } else { "arg_min" },
+                v
+            ),
+        };
+        if *key == DataValue::Null {
+            return Ok(());
+        }
+        let replace = match &self.key {
+            None => true,
+            Some(prev) if self.max => key > prev,
+            Some(prev) => key < prev,
+        };
+        if replace {
+            self.found = value.clone();
+            self.key = Some(key.clone());
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(self.found.clone())
+    }
+}
+
 define_aggr!(AGGR_SMALLEST_BY, false);
 
 pub(crate) struct AggrSmallestBy {
@@ -963,6 +1433,10 @@ impl NormalAggrObj for AggrChoice {
     fn get(&self) -> Result<DataValue> {
         Ok(self.found.clone())
     }
+
+    fn wants_more(&self) -> bool {
+        self.found == DataValue::Null
+    }
 }
 
 pub(crate) struct MeetAggrChoice;
@@ -1155,6 +1629,50 @@ impl NormalAggrObj for AggrBitXor {
     }
 }
 
+define_aggr!(AGGR_BIT_AND_INT, false);
+define_aggr!(AGGR_BIT_OR_INT, false);
+
+/// Like `bit_and` and `bit_or` but for integers, e.g. flag masks. Nulls are skipped, and
+/// without values the result is the identity: all ones for `bit_and_int`, zero for
+/// `bit_or_int`.
+pub(crate) struct AggrBitInt {
+    and: bool,
+    res: i64,
+}
+
+impl AggrBitInt {
+    fn new(and: bool) -> Self {
+        Self {
+            and,
+            res: if and { -1 } else { 0 },
+        }
+    }
+}
+
+impl NormalAggrObj for AggrBitInt {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::Null => {}
+            DataValue::Num(Num::Int(i)) if self.and => self.res &= *i,
+            DataValue::Num(Num::Int(i)) => self.res |= *i,
+            v => bail!(
+                "cannot apply '{}' to {:?}",
+                if self.and {
+                    "bit_and_int"
+                } else {
+                    "bit_or_int"
+                },
+                v
+            ),
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.res))
+    }
+}
+
 pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
     Some(match name {
         "and" => &AGGR_AND,
@@ -1165,23 +1683,39 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
         "intersection" => &AGGR_INTERSECTION,
         "count" => &AGGR_COUNT,
         "count_unique" => &AGGR_COUNT_UNIQUE,
+        "approx_count_unique" => &AGGR_APPROX_COUNT_UNIQUE,
         "variance" => &AGGR_VARIANCE,
         "std_dev" => &AGGR_STD_DEV,
+        "variance_pop" => &AGGR_VARIANCE_POP,
+        "std_dev_pop" => &AGGR_STD_DEV_POP,
         "sum" => &AGGR_SUM,
         "product" => &AGGR_PRODUCT,
         "min" => &AGGR_MIN,
         "max" => &AGGR_MAX,
         "mean" => &AGGR_MEAN,
+        "percentile" => &AGGR_PERCENTILE,
+        "median" => &AGGR_MEDIAN,
         "choice" => &AGGR_CHOICE,
         "collect" => &AGGR_COLLECT,
+        "array_agg" => &AGGR_ARRAY_AGG,
+        "array_agg_distinct" => &AGGR_ARRAY_AGG_DISTINCT,
+        "group_concat" => &AGGR_GROUP_CONCAT,
+        "group_concat_distinct" => &AGGR_GROUP_CONCAT_DISTINCT,
         "shortest" => &AGGR_SHORTEST,
         "min_cost" => &AGGR_MIN_COST,
         "bit_and" => &AGGR_BIT_AND,
         "bit_or" => &AGGR_BIT_OR,
         "bit_xor" => &AGGR_BIT_XOR,
+        "bit_and_int" => &AGGR_BIT_AND_INT,
+        "bit_or_int" => &AGGR_BIT_OR_INT,
         "latest_by" => &AGGR_LATEST_BY,
         "smallest_by" => &AGGR_SMALLEST_BY,
+        "arg_max" => &AGGR_ARG_MAX,
+        "arg_min" => &AGGR_ARG_MIN,
+        "first" => &AGGR_FIRST,
+        "last" => &AGGR_LAST,
         "choice_rand" => &AGGR_CHOICE_RAND,
+        "sample" => &AGGR_SAMPLE,
         _ => return None,
     })
 }
@@ -1212,17 +1746,32 @@ impl Aggregation {
             name if name == AGGR_COUNT.name => Box::new(AggrCount::default()),
             name if name == AGGR_GROUP_COUNT.name => Box::new(AggrGroupCount::default()),
             name if name == AGGR_COUNT_UNIQUE.name => Box::new(AggrCountUnique::default()),
+            name if name == AGGR_APPROX_COUNT_UNIQUE.name => {
+                Box::new(AggrApproxCountUnique::new(args)?)
+            }
             name if name == AGGR_SUM.name => Box::new(AggrSum::default()),
             name if name == AGGR_PRODUCT.name => Box::new(AggrProduct::default()),
             name if name == AGGR_MIN.name => Box::new(AggrMin::default()),
             name if name == AGGR_MAX.name => Box::new(AggrMax::default()),
             name if name == AGGR_MEAN.name => Box::new(AggrMean::default()),
-            name if name == AGGR_VARIANCE.name => Box::new(AggrVariance::default()),
-            name if name == AGGR_STD_DEV.name => Box::new(AggrStdDev::default()),
+            name if name == AGGR_VARIANCE.name => {
+                Box::new(AggrVariance::new("variance", false, false))
+            }
+            name if name == AGGR_VARIANCE_POP.name => {
+                Box::new(AggrVariance::new("variance_pop", true, false))
+            }
+            name if name == AGGR_STD_DEV.name => {
+                Box::new(AggrVariance::new("std_dev", false, true))
+            }
+            name if name == AGGR_STD_DEV_POP.name => {
+                Box::new(AggrVariance::new("std_dev_pop", true, true))
+            }
             name if name == AGGR_CHOICE.name => Box::new(AggrChoice::default()),
             name if name == AGGR_BIT_AND.name => Box::new(AggrBitAnd::default()),
             name if name == AGGR_BIT_OR.name => Box::new(AggrBitOr::default()),
             name if name == AGGR_BIT_XOR.name => Box::new(AggrBitXor::default()),
+            name if name == AGGR_BIT_AND_INT.name => Box::new(AggrBitInt::new(true)),
+            name if name == AGGR_BIT_OR_INT.name => Box::new(AggrBitInt::new(false)),
             name if name == AGGR_UNIQUE.name => Box::new(AggrUnique::default()),
             name if name == AGGR_UNION.name => Box::new(AggrUnion::default()),
             name if name == AGGR_INTERSECTION.name => Box::new(AggrIntersection::default()),
@@ -1230,7 +1779,10 @@ impl Aggregation {
             name if name == AGGR_MIN_COST.name => Box::new(AggrMinCost::default()),
             name if name == AGGR_LATEST_BY.name => Box::new(AggrLatestBy::default()),
             name if name == AGGR_SMALLEST_BY.name => Box::new(AggrSmallestBy::default()),
+            name if name == AGGR_ARG_MAX.name => Box::new(AggrArgMaxMin::new(true)),
+            name if name == AGGR_ARG_MIN.name => Box::new(AggrArgMaxMin::new(false)),
             name if name == AGGR_CHOICE_RAND.name => Box::new(AggrChoiceRand::default()),
+            name if name == AGGR_SAMPLE.name => Box::new(AggrSample::new(args)?),
             name if name == AGGR_COLLECT.name => Box::new({
                 if args.is_empty() {
                     AggrCollect::default()
@@ -1249,6 +1801,18 @@ impl Aggregation {
                     AggrCollect::new(arg as usize)
                 }
             }),
+            name if name == AGGR_ARRAY_AGG.name => Box::new(AggrArrayAgg::new(false, args)?),
+            name if name == AGGR_ARRAY_AGG_DISTINCT.name => {
+                Box::new(AggrArrayAgg::new(true, args)?)
+            }
+            name if name == AGGR_GROUP_CONCAT.name => Box::new(AggrGroupConcat::new(false, args)?),
+            name if name == AGGR_GROUP_CONCAT_DISTINCT.name => {
+                Box::new(AggrGroupConcat::new(true, args)?)
+            }
+            name if name == AGGR_FIRST.name => Box::new(AggrFirstLast::new(false, args)?),
+            name if name == AGGR_LAST.name => Box::new(AggrFirstLast::new(true, args)?),
+            name if name == AGGR_PERCENTILE.name => Box::new(AggrPercentile::new(args)?),
+            name if name == AGGR_MEDIAN.name => Box::new(AggrPercentile::median()),
             _ => unreachable!(),
         });
         Ok(())
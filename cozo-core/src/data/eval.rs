@@ -0,0 +1,366 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::thread::LocalKey;
+
+use miette::{bail, Diagnostic, Result};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use thiserror::Error;
+
+use crate::data::expr::{Expr, PartialEvalMemo, UnboundVariableError, STRICT_FLOATS};
+use crate::data::functions::{
+    json_to_value, with_collation, with_numeric_promotion, Collation, NumericPromotion, COLLATION,
+    MAX_INT_RANGE_LEN, NUMERIC_PROMOTION, RNG_SEED, SEEDED_RNG,
+};
+use crate::data::json::JsonValue;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+
+/// Sets a thread-local mode until dropped, restoring the previous value also when unwinding
+pub(crate) struct ModeGuard<T: 'static> {
+    mode: &'static LocalKey<Cell<T>>,
+    prev: Option<T>,
+}
+
+impl<T> ModeGuard<T> {
+    pub(crate) fn set(mode: &'static LocalKey<Cell<T>>, val: T) -> Self {
+        let prev = mode.with(|cell| cell.replace(val));
+        Self {
+            mode,
+            prev: Some(prev),
+        }
+    }
+}
+
+impl<T> Drop for ModeGuard<T> {
+    fn drop(&mut self) {
+        if let Some(prev) = self.prev.take() {
+            self.mode.with(|cell| cell.set(prev))
+        }
+    }
+}
+
+/// The modes of evaluation for a whole query. They are taken from the thread running the
+/// query, and put in effect on each thread that evaluates its rules.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EvalModes {
+    pub(crate) strict_floats: bool,
+    pub(crate) numeric_promotion: NumericPromotion,
+    pub(crate) collation: Collation,
+    pub(crate) rng_seed: Option<u64>,
+    pub(crate) max_int_range_len: usize,
+}
+
+/// Restores the modes in effect before [EvalModes::install] when dropped
+pub(crate) struct EvalModesGuard {
+    _strict_floats: ModeGuard<bool>,
+    _numeric_promotion: ModeGuard<NumericPromotion>,
+    _collation: ModeGuard<Collation>,
+    _rng_seed: ModeGuard<Option<u64>>,
+    _seeded_rng: ModeGuard<Option<StdRng>>,
+    _max_int_range_len: ModeGuard<usize>,
+}
+
+impl EvalModes {
+    /// The modes in effect on the current thread
+    pub(crate) fn current() -> Self {
+        Self {
+            strict_floats: STRICT_FLOATS.with(Cell::get),
+            numeric_promotion: NUMERIC_PROMOTION.with(Cell::get),
+            collation: COLLATION.with(Cell::get),
+            rng_seed: RNG_SEED.with(Cell::get),
+            max_int_range_len: MAX_INT_RANGE_LEN.with(Cell::get),
+        }
+    }
+    /// Puts the modes in effect on the current thread until the guard is dropped.
+    /// A seeded RNG starts over from its seed.
+    #[must_use]
+    pub(crate) fn install(&self) -> EvalModesGuard {
+        EvalModesGuard {
+            _strict_floats: ModeGuard::set(&STRICT_FLOATS, self.strict_floats),
+            _numeric_promotion: ModeGuard::set(&NUMERIC_PROMOTION, self.numeric_promotion),
+            _collation: ModeGuard::set(&COLLATION, self.collation),
+            _rng_seed: ModeGuard::set(&RNG_SEED, self.rng_seed),
+            _seeded_rng: ModeGuard::set(&SEEDED_RNG, self.rng_seed.map(StdRng::seed_from_u64)),
+            _max_int_range_len: ModeGuard::set(&MAX_INT_RANGE_LEN, self.max_int_range_len),
+        }
+    }
+}
+
+/// Resolves variables by name, for evaluating expressions outside of rule bodies
+pub trait ExprEvalContext {
+    /// The value bound to `var`, `None` if the variable is unknown to the context
+    fn resolve(&self, var: &Symbol) -> Option<DataValue>;
+}
+
+impl ExprEvalContext for BTreeMap<String, DataValue> {
+    fn resolve(&self, var: &Symbol) -> Option<DataValue> {
+        self.get(&var.name as &str).cloned()
+    }
+}
+
+/// Resolves variables from the fields of a JSON object, converted in the same way as
+/// JSON values inside queries: numbers stay ints when they are integral, and arrays and
+/// nested objects stay JSON
+impl ExprEvalContext for JsonValue {
+    fn resolve(&self, var: &Symbol) -> Option<DataValue> {
+        self.get(&var.name as &str).map(json_to_value)
+    }
+}
+
+/// A local scope layered over another one: variables are resolved by `inner` first,
+/// then by `outer` if `inner` does not know them
+pub struct ChainedContext<'a> {
+    inner: &'a dyn ExprEvalContext,
+    outer: &'a dyn ExprEvalContext,
+}
+
+impl<'a> ChainedContext<'a> {
+    /// Layers `inner` over `outer`
+    pub fn new(inner: &'a dyn ExprEvalContext, outer: &'a dyn ExprEvalContext) -> Self {
+        Self { inner, outer }
+    }
+}
+
+impl ExprEvalContext for ChainedContext<'_> {
+    fn resolve(&self, var: &Symbol) -> Option<DataValue> {
+        self.inner.resolve(var).or_else(|| self.outer.resolve(var))
+    }
+}
+
+/// Supplies defaults for the variables that `inner` does not resolve,
+/// either by name, or a fallback for all other variables
+pub struct WithDefaults<'a> {
+    inner: &'a dyn ExprEvalContext,
+    defaults: BTreeMap<String, DataValue>,
+    fallback: Option<DataValue>,
+}
+
+impl<'a> WithDefaults<'a> {
+    /// Resolves variables by `inner`, without defaults yet
+    pub fn new(inner: &'a dyn ExprEvalContext) -> Self {
+        Self {
+            inner,
+            defaults: Default::default(),
+            fallback: None,
+        }
+    }
+    /// Sets the default for the variable `name`
+    pub fn with_default(mut self, name: &str, val: DataValue) -> Self {
+        self.defaults.insert(name.to_string(), val);
+        self
+    }
+    /// Sets the default for variables without a default of their own
+    pub fn with_fallback(mut self, val: DataValue) -> Self {
+        self.fallback = Some(val);
+        self
+    }
+}
+
+impl ExprEvalContext for WithDefaults<'_> {
+    fn resolve(&self, var: &Symbol) -> Option<DataValue> {
+        self.inner
+            .resolve(var)
+            .or_else(|| self.defaults.get(&var.name as &str).cloned())
+            .or_else(|| self.fallback.clone())
+    }
+}
+
+/// Resolves positions in a row, for evaluating expressions whose bindings have been filled
+pub trait RowEvalContext {
+    /// The value at position `idx` of the row, `None` if there is no such value
+    fn resolve(&self, idx: usize) -> Option<DataValue>;
+    /// Whether positions without a value are null, e.g. for rows written before columns
+    /// were added, instead of an error
+    fn missing_is_null(&self) -> bool {
+        false
+    }
+    /// How strings in the row are ordered when compared
+    fn collation(&self) -> Collation {
+        Collation::Codepoint
+    }
+    /// How arithmetic on values of the row mixes integers and floats
+    fn numeric_promotion(&self) -> NumericPromotion {
+        NumericPromotion::Float
+    }
+}
+
+/// A sparse row holding values at arbitrary positions, collected from `(position, value)` pairs
+#[derive(Debug, Default)]
+pub struct MapRowContext {
+    values: BTreeMap<usize, DataValue>,
+    missing_is_null: bool,
+    collation: Collation,
+    numeric_promotion: NumericPromotion,
+}
+
+impl MapRowContext {
+    /// Makes positions without a value evaluate to null
+    pub fn with_missing_as_null(mut self) -> Self {
+        self.missing_is_null = true;
+        self
+    }
+    /// Compares strings under `collation`
+    pub fn collated(mut self, collation: Collation) -> Self {
+        self.collation = collation;
+        self
+    }
+    /// Mixes integers and floats in arithmetic following `promotion`
+    pub fn with_promotion(mut self, promotion: NumericPromotion) -> Self {
+        self.numeric_promotion = promotion;
+        self
+    }
+}
+
+impl FromIterator<(usize, DataValue)> for MapRowContext {
+    fn from_iter<T: IntoIterator<Item = (usize, DataValue)>>(iter: T) -> Self {
+        Self {
+            values: iter.into_iter().collect(),
+            missing_is_null: false,
+            collation: Collation::Codepoint,
+            numeric_promotion: NumericPromotion::Float,
+        }
+    }
+}
+
+impl RowEvalContext for MapRowContext {
+    fn resolve(&self, idx: usize) -> Option<DataValue> {
+        self.values.get(&idx).cloned()
+    }
+    fn missing_is_null(&self) -> bool {
+        self.missing_is_null
+    }
+    fn collation(&self) -> Collation {
+        self.collation
+    }
+    fn numeric_promotion(&self) -> NumericPromotion {
+        self.numeric_promotion
+    }
+}
+
+/// How [SortKey]s order their values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortOrder {
+    /// Largest values first
+    pub descending: bool,
+    /// Nulls after all other values, regardless of the direction
+    pub nulls_last: bool,
+}
+
+/// A value to sort rows by. Values compare in the total order of [DataValue], except for
+/// the direction and the placement of nulls given by the [SortOrder]. Keys are only
+/// meaningfully compared with keys of the same order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    /// The value to sort by
+    pub val: DataValue,
+    /// How the value is ordered
+    pub order: SortOrder,
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&self.val, &other.val) {
+            (DataValue::Null, DataValue::Null) => Ordering::Equal,
+            (DataValue::Null, _) if self.order.nulls_last => Ordering::Greater,
+            (DataValue::Null, _) => Ordering::Less,
+            (_, DataValue::Null) if self.order.nulls_last => Ordering::Less,
+            (_, DataValue::Null) => Ordering::Greater,
+            (a, b) if self.order.descending => b.cmp(a),
+            (a, b) => a.cmp(b),
+        }
+    }
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("The row has no value at position {0}")]
+#[diagnostic(code(eval::unresolved_tuple_idx))]
+struct UnresolvedTupleIdxError(usize);
+
+impl Expr {
+    /// Evaluates the expression with all its variables resolved by `ctx`
+    pub fn eval_in_context(&self, ctx: &dyn ExprEvalContext) -> Result<DataValue> {
+        let mut binding_map = BTreeMap::new();
+        let mut values = vec![];
+        for var in self.bindings() {
+            let val = ctx
+                .resolve(&var)
+                .ok_or_else(|| UnboundVariableError(var.name.to_string(), var.span))?;
+            binding_map.insert(var, values.len());
+            values.push(val);
+        }
+        let mut expr = self.clone();
+        expr.fill_binding_indices(&binding_map)?;
+        expr.eval(values)
+    }
+    /// Replaces the variables resolved by `ctx` with their values and folds the result,
+    /// variables unknown to `ctx` are left as they are. Folded applications are shared
+    /// through `memo`.
+    // `DataValue` only has interior mutability inside regex caches, which does not affect ordering
+    #[allow(clippy::mutable_key_type)]
+    pub(crate) fn partial_eval_in_context(
+        &mut self,
+        ctx: &dyn ExprEvalContext,
+        memo: &mut PartialEvalMemo,
+    ) -> Result<()> {
+        for var in self.bindings() {
+            if let Some(val) = ctx.resolve(&var) {
+                self.substitute_const(&var, &val);
+            }
+        }
+        self.partial_eval_memoized(memo)
+    }
+    /// Runs the rewriting passes in the order they depend on each other: the variables resolved
+    /// by `ctx` are substituted, the result is folded, and then comparisons are normalized,
+    /// which only recognizes constants once they have been folded.
+    /// Preparing an already prepared expression leaves it unchanged.
+    #[allow(clippy::mutable_key_type)]
+    pub(crate) fn prepare(
+        mut self,
+        ctx: &dyn ExprEvalContext,
+        memo: &mut PartialEvalMemo,
+    ) -> Result<Expr> {
+        self.partial_eval_in_context(ctx, memo)?;
+        self.normalize_comparisons();
+        Ok(self)
+    }
+    /// Evaluates the expression with the positions of its bindings resolved by `row`.
+    /// Positions the row has no value for are errors, unless the row treats them as null.
+    /// Strings are compared and numbers combined as configured by the row.
+    pub fn eval_in_row(&self, row: &dyn RowEvalContext) -> Result<DataValue> {
+        let indices = self.binding_indices();
+        let mut values = vec![DataValue::Null; indices.last().map(|i| i + 1).unwrap_or(0)];
+        for idx in indices {
+            values[idx] = match row.resolve(idx) {
+                Some(val) => val,
+                None if row.missing_is_null() => DataValue::Null,
+                None => bail!(UnresolvedTupleIdxError(idx)),
+            };
+        }
+        with_collation(row.collation(), || {
+            with_numeric_promotion(row.numeric_promotion(), || self.eval(values))
+        })
+    }
+    /// The key for sorting `row` by this expression
+    pub fn sort_key(&self, row: &dyn RowEvalContext, order: SortOrder) -> Result<SortKey> {
+        Ok(SortKey {
+            val: self.eval_in_row(row)?,
+            order,
+        })
+    }
+}
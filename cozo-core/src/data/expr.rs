@@ -6,22 +6,26 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cell::Cell;
 use std::cmp::{max, min};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::mem;
+use std::sync::{Arc, OnceLock, RwLock};
 
 use itertools::Itertools;
-use miette::{bail, miette, Diagnostic, Result};
+use lazy_static::lazy_static;
+use miette::{bail, ensure, miette, Diagnostic, Result};
 use serde::de::{Error, Visitor};
 use serde::{Deserializer, Serializer};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::eval::ModeGuard;
 use crate::data::functions::*;
 use crate::data::relation::NullableColType;
 use crate::data::symb::Symbol;
-use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::data::value::{DataValue, Num, LARGEST_UTF_CHAR};
 use crate::parse::expr::expr2bytecode;
 use crate::parse::SourceSpan;
 
@@ -57,12 +61,51 @@ pub enum Bytecode {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// unchanged, registers an error handler
+    TryBegin {
+        catch_to: usize,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// unchanged, removes the latest error handler
+    TryEnd {
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// pop 1 and combine it with the top as by `and` (if `on` is false) or `or` (if `on` is true),
+    /// jumping if the result is decided
+    ShortCircuit {
+        on: bool,
+        jump_to: usize,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// unchanged if the top is not null, otherwise pop 1
+    JumpIfNotNull {
+        jump_to: usize,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// pop 1, binds the value to a local slot
+    Bind {
+        slot: usize,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// pop 1, runs `body` for each element of the list with the element bound to `slot`, push 1
+    HigherOrder {
+        op: HigherOrderOp,
+        slot: usize,
+        body: Vec<Bytecode>,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
 }
 
 #[derive(Error, Diagnostic, Debug)]
 #[error("The variable '{0}' is unbound")]
 #[diagnostic(code(eval::unbound))]
-struct UnboundVariableError(String, #[label] SourceSpan);
+pub(crate) struct UnboundVariableError(pub(crate) String, #[label] pub(crate) SourceSpan);
 
 #[derive(Error, Diagnostic, Debug)]
 #[error("The tuple bound by variable '{0}' is too short: index is {1}, length is {2}")]
@@ -88,7 +131,21 @@ pub fn eval_bytecode(
     stack: &mut Vec<DataValue>,
 ) -> Result<DataValue> {
     stack.clear();
+    // values bound by `let`: (slot, value), later entries shadow earlier ones
+    let mut locals: Vec<(usize, DataValue)> = vec![];
+    run_bytecode(bytecodes, bindings.as_ref(), stack, &mut locals)
+}
+
+/// Runs the bytecodes to the end and pops the result, leaving the rest of the stack untouched
+fn run_bytecode(
+    bytecodes: &[Bytecode],
+    bindings: &[DataValue],
+    stack: &mut Vec<DataValue>,
+    locals: &mut Vec<(usize, DataValue)>,
+) -> Result<DataValue> {
     let mut pointer = 0;
+    // error handlers registered by `try`: (where to jump, stack length to restore)
+    let mut handlers: Vec<(usize, usize)> = vec![];
     // for (i, c) in bytecodes.iter().enumerate() {
     //     println!("{i}  {c:?}");
     // }
@@ -98,60 +155,503 @@ pub fn eval_bytecode(
         if pointer == bytecodes.len() {
             break;
         }
-        let current_instruction = &bytecodes[pointer];
-        // println!("{current_instruction:?}");
-        match current_instruction {
-            Bytecode::Binding { var, tuple_pos, .. } => match tuple_pos {
-                None => {
-                    bail!(UnboundVariableError(var.name.to_string(), var.span))
+        match eval_bytecode_step(bytecodes, pointer, bindings, stack, &mut handlers, locals) {
+            Ok(next) => pointer = next,
+            Err(err) => match handlers.pop() {
+                Some((catch_to, stack_len)) => {
+                    stack.truncate(stack_len);
+                    pointer = catch_to;
                 }
-                Some(i) => {
-                    let val = bindings
-                        .as_ref()
+                None => return Err(err),
+            },
+        }
+    }
+    Ok(stack.pop().unwrap())
+}
+
+fn eval_bytecode_step(
+    bytecodes: &[Bytecode],
+    pointer: usize,
+    bindings: &[DataValue],
+    stack: &mut Vec<DataValue>,
+    handlers: &mut Vec<(usize, usize)>,
+    locals: &mut Vec<(usize, DataValue)>,
+) -> Result<usize> {
+    let current_instruction = &bytecodes[pointer];
+    // println!("{current_instruction:?}");
+    Ok(match current_instruction {
+        Bytecode::Binding { var, tuple_pos, .. } => match tuple_pos {
+            None => {
+                bail!(UnboundVariableError(var.name.to_string(), var.span))
+            }
+            Some(i) => {
+                let val = match locals.iter().rev().find(|(slot, _)| slot == i) {
+                    Some((_, val)) => val.clone(),
+                    None => bindings
                         .get(*i)
                         .ok_or_else(|| {
-                            TupleTooShortError(
-                                var.name.to_string(),
-                                *i,
-                                bindings.as_ref().len(),
-                                var.span,
-                            )
+                            TupleTooShortError(var.name.to_string(), *i, bindings.len(), var.span)
                         })?
-                        .clone();
-                    stack.push(val);
-                    pointer += 1;
+                        .clone(),
+                };
+                stack.push(val);
+                pointer + 1
+            }
+        },
+        Bytecode::Const { val, .. } => {
+            stack.push(val.clone());
+            pointer + 1
+        }
+        Bytecode::Apply { op, arity, span } => {
+            let frame_start = stack.len() - *arity;
+            let args_frame = &stack[frame_start..];
+            let result = op.call(args_frame, *span)?;
+            stack.truncate(frame_start);
+            stack.push(result);
+            pointer + 1
+        }
+        Bytecode::JumpIfFalse { jump_to, span } => {
+            let val = stack.pop().unwrap();
+            let cond = val
+                .get_bool()
+                .ok_or_else(|| PredicateTypeError(*span, val))?;
+            if cond {
+                pointer + 1
+            } else {
+                *jump_to
+            }
+        }
+        Bytecode::Goto { jump_to, .. } => *jump_to,
+        Bytecode::ShortCircuit { on, jump_to, span } => {
+            let val = stack.pop().unwrap();
+            if combine_junction(stack.last_mut().unwrap(), val, *on, *span)? {
+                *jump_to
+            } else {
+                pointer + 1
+            }
+        }
+        Bytecode::JumpIfNotNull { jump_to, .. } => {
+            if *stack.last().unwrap() != DataValue::Null {
+                *jump_to
+            } else {
+                stack.pop();
+                pointer + 1
+            }
+        }
+        Bytecode::TryBegin { catch_to, .. } => {
+            handlers.push((*catch_to, stack.len()));
+            pointer + 1
+        }
+        Bytecode::TryEnd { .. } => {
+            handlers.pop();
+            pointer + 1
+        }
+        Bytecode::Bind { slot, .. } => {
+            let val = stack.pop().unwrap();
+            locals.push((*slot, val));
+            pointer + 1
+        }
+        Bytecode::HigherOrder {
+            op,
+            slot,
+            body,
+            span,
+        } => {
+            let val = stack.pop().unwrap();
+            let items = match val {
+                DataValue::Null => {
+                    stack.push(DataValue::Null);
+                    return Ok(pointer + 1);
+                }
+                DataValue::List(l) => l,
+                v => bail!(HigherOrderTypeError(op.name(), v, *span)),
+            };
+            let mut acc = op.initial();
+            let locals_len = locals.len();
+            for item in items {
+                locals.push((*slot, item));
+                let result = run_bytecode(body, bindings, stack, locals);
+                locals.truncate(locals_len + 1);
+                let (_, item) = locals.pop().unwrap();
+                if op.combine(&mut acc, item, result?, *span)? {
+                    break;
+                }
+            }
+            stack.push(acc);
+            pointer + 1
+        }
+    })
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("'{0}' requires a list, got {1:?}")]
+#[diagnostic(code(eval::higher_order_not_list))]
+pub(crate) struct HigherOrderTypeError(
+    pub(crate) &'static str,
+    pub(crate) DataValue,
+    #[label] pub(crate) SourceSpan,
+);
+
+/// Combines the next value of an `and` (if `on` is false) or an `or` (if `on` is true) into the
+/// accumulated value with three-valued logic, returning whether the result is decided
+fn combine_junction(
+    acc: &mut DataValue,
+    val: DataValue,
+    on: bool,
+    span: SourceSpan,
+) -> Result<bool> {
+    match val {
+        DataValue::Bool(b) if b == on => {
+            *acc = val;
+            Ok(true)
+        }
+        DataValue::Bool(_) => Ok(false),
+        DataValue::Null => {
+            *acc = DataValue::Null;
+            Ok(false)
+        }
+        v => bail!(PredicateTypeError(span, v)),
+    }
+}
+
+enum EvalTask<'a> {
+    /// push the value of the expression
+    Eval(&'a Expr),
+    /// pop the arguments of the `Apply`, push the result
+    Apply(&'a Expr),
+    /// pop the value of condition `idx` of the `Cond`, continue with its value or the next clause
+    CondBranch(&'a Expr, usize),
+    /// the current `Try` clause has succeeded
+    TryEnd,
+    /// pop the value of argument `idx` of the `And` or `Or`, combine it with the accumulated value
+    /// and continue with the next argument unless the result is decided
+    JunctionStep(&'a Expr, usize),
+    /// the value of argument `idx` of the `Coalesce` is on top, continue with the next one if it is null
+    CoalesceNext(&'a Expr, usize),
+    /// pop the value of the `Let`, bind it and evaluate the body
+    LetBind(&'a Expr),
+    /// the body of the `Let` has been evaluated
+    LetEnd,
+    /// pop the list of the higher-order expression and start applying the lambda
+    HigherOrderBegin(&'a Expr),
+    /// pop the result of the lambda for the last element, combine it and continue with the rest
+    HigherOrderStep(&'a Expr, std::vec::IntoIter<DataValue>),
+}
+
+/// Where to resume when a clause of a `Try` fails
+struct TryHandler<'a> {
+    expr: &'a Expr,
+    clause: usize,
+    tasks_len: usize,
+    values_len: usize,
+    locals_len: usize,
+}
+
+/// Evaluates expression trees using explicit stacks, so that deeply nested
+/// expressions cannot overflow the native stack
+struct TreeEvaluator<'a> {
+    bindings: &'a [DataValue],
+    tasks: Vec<EvalTask<'a>>,
+    values: Vec<DataValue>,
+    /// values bound by `let`: (slot, value), later entries shadow earlier ones
+    locals: Vec<(usize, DataValue)>,
+    handlers: Vec<TryHandler<'a>>,
+}
+
+impl<'a> TreeEvaluator<'a> {
+    fn new(bindings: &'a [DataValue]) -> Self {
+        Self {
+            bindings,
+            tasks: vec![],
+            values: vec![],
+            locals: vec![],
+            handlers: vec![],
+        }
+    }
+    fn run(mut self, expr: &'a Expr) -> Result<DataValue> {
+        self.tasks.push(EvalTask::Eval(expr));
+        while let Some(task) = self.tasks.pop() {
+            if let Err(err) = self.step(task) {
+                let handler = match self.handlers.pop() {
+                    Some(handler) => handler,
+                    None => return Err(err),
+                };
+                self.tasks.truncate(handler.tasks_len);
+                self.values.truncate(handler.values_len);
+                self.locals.truncate(handler.locals_len);
+                self.begin_try_clause(handler.expr, handler.clause + 1);
+            }
+        }
+        Ok(self.values.pop().unwrap())
+    }
+    fn begin_try_clause(&mut self, expr: &'a Expr, clause: usize) {
+        let Expr::Try { clauses, .. } = expr else {
+            unreachable!()
+        };
+        // errors in the last clause are not caught
+        if clause + 1 < clauses.len() {
+            self.handlers.push(TryHandler {
+                expr,
+                clause,
+                tasks_len: self.tasks.len(),
+                values_len: self.values.len(),
+                locals_len: self.locals.len(),
+            });
+            self.tasks.push(EvalTask::TryEnd);
+        }
+        self.tasks.push(EvalTask::Eval(&clauses[clause]));
+    }
+    fn step(&mut self, task: EvalTask<'a>) -> Result<()> {
+        match task {
+            EvalTask::Eval(expr) => match expr {
+                Expr::Binding { var, tuple_pos } => {
+                    let i = match tuple_pos {
+                        None => bail!(UnboundVariableError(var.name.to_string(), var.span)),
+                        Some(i) => *i,
+                    };
+                    let val = match self.locals.iter().rev().find(|(slot, _)| *slot == i) {
+                        Some((_, val)) => val.clone(),
+                        None => self
+                            .bindings
+                            .get(i)
+                            .ok_or_else(|| {
+                                TupleTooShortError(
+                                    var.name.to_string(),
+                                    i,
+                                    self.bindings.len(),
+                                    var.span,
+                                )
+                            })?
+                            .clone(),
+                    };
+                    self.values.push(val);
+                }
+                Expr::Const { val, .. } => self.values.push(val.clone()),
+                Expr::Apply { args, .. } => {
+                    self.tasks.push(EvalTask::Apply(expr));
+                    self.tasks.extend(args.iter().rev().map(EvalTask::Eval));
+                }
+                Expr::Cond { clauses, .. } => match clauses.first() {
+                    None => self.values.push(DataValue::Null),
+                    Some((cond, _)) => {
+                        self.tasks.push(EvalTask::CondBranch(expr, 0));
+                        self.tasks.push(EvalTask::Eval(cond));
+                    }
+                },
+                Expr::Try { .. } => self.begin_try_clause(expr, 0),
+                Expr::Coalesce { args, .. } => match args.first() {
+                    None => self.values.push(DataValue::Null),
+                    Some(arg) => {
+                        if args.len() > 1 {
+                            self.tasks.push(EvalTask::CoalesceNext(expr, 0));
+                        }
+                        self.tasks.push(EvalTask::Eval(arg));
+                    }
+                },
+                Expr::And { args, .. } | Expr::Or { args, .. } => {
+                    self.values
+                        .push(DataValue::from(matches!(expr, Expr::And { .. })));
+                    if let Some(arg) = args.first() {
+                        self.tasks.push(EvalTask::JunctionStep(expr, 0));
+                        self.tasks.push(EvalTask::Eval(arg));
+                    }
+                }
+                Expr::Let { value, .. } => {
+                    self.tasks.push(EvalTask::LetBind(expr));
+                    self.tasks.push(EvalTask::Eval(value));
+                }
+                Expr::HigherOrder { list, .. } => {
+                    self.tasks.push(EvalTask::HigherOrderBegin(expr));
+                    self.tasks.push(EvalTask::Eval(list));
                 }
             },
-            Bytecode::Const { val, .. } => {
-                stack.push(val.clone());
-                pointer += 1;
-            }
-            Bytecode::Apply { op, arity, span } => {
-                let frame_start = stack.len() - *arity;
-                let args_frame = &stack[frame_start..];
-                let result = (op.inner)(args_frame)
-                    .map_err(|err| EvalRaisedError(*span, err.to_string()))?;
-                stack.truncate(frame_start);
-                stack.push(result);
-                pointer += 1;
-            }
-            Bytecode::JumpIfFalse { jump_to, span } => {
-                let val = stack.pop().unwrap();
-                let cond = val
+            EvalTask::Apply(expr) => {
+                let Expr::Apply { op, args, span } = expr else {
+                    unreachable!()
+                };
+                let frame_start = self.values.len() - args.len();
+                let result = op.call(&self.values[frame_start..], *span)?;
+                self.values.truncate(frame_start);
+                self.values.push(result);
+            }
+            EvalTask::CondBranch(expr, idx) => {
+                let Expr::Cond { clauses, .. } = expr else {
+                    unreachable!()
+                };
+                let cond_val = self.values.pop().unwrap();
+                let (cond, val) = &clauses[idx];
+                if cond_val
                     .get_bool()
-                    .ok_or_else(|| PredicateTypeError(*span, val))?;
-                if cond {
-                    pointer += 1;
+                    .ok_or_else(|| PredicateTypeError(cond.span(), cond_val))?
+                {
+                    self.tasks.push(EvalTask::Eval(val));
+                } else if let Some((next_cond, _)) = clauses.get(idx + 1) {
+                    self.tasks.push(EvalTask::CondBranch(expr, idx + 1));
+                    self.tasks.push(EvalTask::Eval(next_cond));
                 } else {
-                    pointer = *jump_to;
+                    self.values.push(DataValue::Null);
                 }
             }
-            Bytecode::Goto { jump_to, .. } => {
-                pointer = *jump_to;
+            EvalTask::TryEnd => {
+                self.handlers.pop();
+            }
+            EvalTask::JunctionStep(expr, idx) => {
+                let (args, on) = match expr {
+                    Expr::And { args, .. } => (args, false),
+                    Expr::Or { args, .. } => (args, true),
+                    _ => unreachable!(),
+                };
+                let val = self.values.pop().unwrap();
+                let decided =
+                    combine_junction(self.values.last_mut().unwrap(), val, on, args[idx].span())?;
+                if !decided {
+                    if let Some(next) = args.get(idx + 1) {
+                        self.tasks.push(EvalTask::JunctionStep(expr, idx + 1));
+                        self.tasks.push(EvalTask::Eval(next));
+                    }
+                }
+            }
+            EvalTask::CoalesceNext(expr, idx) => {
+                let Expr::Coalesce { args, .. } = expr else {
+                    unreachable!()
+                };
+                if *self.values.last().unwrap() == DataValue::Null {
+                    self.values.pop();
+                    let next = idx + 1;
+                    if next + 1 < args.len() {
+                        self.tasks.push(EvalTask::CoalesceNext(expr, next));
+                    }
+                    self.tasks.push(EvalTask::Eval(&args[next]));
+                }
+            }
+            EvalTask::LetBind(expr) => {
+                let Expr::Let { body, slot, .. } = expr else {
+                    unreachable!()
+                };
+                let val = self.values.pop().unwrap();
+                // without a slot, references in the body are unbound as well
+                if let Some(slot) = slot {
+                    self.locals.push((*slot, val));
+                    self.tasks.push(EvalTask::LetEnd);
+                }
+                self.tasks.push(EvalTask::Eval(body));
+            }
+            EvalTask::LetEnd => {
+                self.locals.pop();
+            }
+            EvalTask::HigherOrderBegin(expr) => {
+                let Expr::HigherOrder { op, span, .. } = expr else {
+                    unreachable!()
+                };
+                match self.values.pop().unwrap() {
+                    DataValue::Null => self.values.push(DataValue::Null),
+                    DataValue::List(l) => {
+                        self.values.push(op.initial());
+                        self.next_element(expr, l.into_iter());
+                    }
+                    v => bail!(HigherOrderTypeError(op.name(), v, *span)),
+                }
+            }
+            EvalTask::HigherOrderStep(expr, items) => {
+                let Expr::HigherOrder { op, span, .. } = expr else {
+                    unreachable!()
+                };
+                let result = self.values.pop().unwrap();
+                let (_, item) = self.locals.pop().unwrap();
+                if !op.combine(self.values.last_mut().unwrap(), item, result, *span)? {
+                    self.next_element(expr, items);
+                }
             }
         }
+        Ok(())
+    }
+    /// Binds the next element, if any, and evaluates the lambda for it
+    fn next_element(&mut self, expr: &'a Expr, mut items: std::vec::IntoIter<DataValue>) {
+        let Expr::HigherOrder { lambda, .. } = expr else {
+            unreachable!()
+        };
+        if let Some(item) = items.next() {
+            // without a slot, references in the body are unbound and the value is never looked up
+            self.locals.push((lambda.slot.unwrap_or(usize::MAX), item));
+            self.tasks.push(EvalTask::HigherOrderStep(expr, items));
+            self.tasks.push(EvalTask::Eval(&lambda.body));
+        }
+    }
+}
+
+/// A function of one parameter, which can only appear as an argument of a higher-order expression
+#[derive(Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct Lambda {
+    /// The parameter name
+    pub var: Symbol,
+    /// The expression in which the parameter is bound
+    pub body: Box<Expr>,
+    /// The position assigned to the parameter, beyond those of the tuple
+    pub slot: Option<usize>,
+}
+
+/// The ways a higher-order expression combines the results of its lambda
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum HigherOrderOp {
+    /// The list of the results for each element
+    Map,
+    /// The list of the elements for which the result is true. Elements for which
+    /// the result is null are excluded as well.
+    Filter,
+    /// Whether the result is true for some element, with three-valued logic as for `or`
+    Any,
+    /// Whether the result is true for every element, with three-valued logic as for `and`
+    All,
+}
+
+impl HigherOrderOp {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "map" => HigherOrderOp::Map,
+            "filter" => HigherOrderOp::Filter,
+            "any" => HigherOrderOp::Any,
+            "all" => HigherOrderOp::All,
+            _ => return None,
+        })
+    }
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            HigherOrderOp::Map => "map",
+            HigherOrderOp::Filter => "filter",
+            HigherOrderOp::Any => "any",
+            HigherOrderOp::All => "all",
+        }
+    }
+    /// The value for the empty list
+    fn initial(&self) -> DataValue {
+        match self {
+            HigherOrderOp::Map | HigherOrderOp::Filter => DataValue::List(vec![]),
+            HigherOrderOp::Any => DataValue::from(false),
+            HigherOrderOp::All => DataValue::from(true),
+        }
+    }
+    /// Combines the result of the lambda for the next element into the accumulated value,
+    /// returning whether the result is decided so that the remaining elements can be skipped
+    fn combine(
+        &self,
+        acc: &mut DataValue,
+        item: DataValue,
+        result: DataValue,
+        span: SourceSpan,
+    ) -> Result<bool> {
+        match (self, acc) {
+            (HigherOrderOp::Map, DataValue::List(l)) => l.push(result),
+            (HigherOrderOp::Filter, DataValue::List(l)) => match result {
+                DataValue::Bool(true) => l.push(item),
+                DataValue::Bool(false) | DataValue::Null => {}
+                v => bail!(PredicateTypeError(span, v)),
+            },
+            (HigherOrderOp::Any, acc) => return combine_junction(acc, result, true, span),
+            (HigherOrderOp::All, acc) => return combine_junction(acc, result, false, span),
+            _ => unreachable!(),
+        }
+        Ok(false)
     }
-    Ok(stack.pop().unwrap())
 }
 
 /// Expression can be evaluated to yield a DataValue
@@ -190,6 +690,80 @@ pub enum Expr {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// Error recovery: the value of the first clause that evaluates without error
+    Try {
+        /// Clauses to evaluate in order, errors of the last one are propagated
+        clauses: Vec<Expr>,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// Conjunction with three-valued logic: false if any argument is false, otherwise null
+    /// if any argument is null. Arguments after the first false one are not evaluated.
+    And {
+        /// Arguments to evaluate in order, each should evaluate to a boolean or null
+        args: Vec<Expr>,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// Disjunction with three-valued logic: true if any argument is true, otherwise null
+    /// if any argument is null. Arguments after the first true one are not evaluated.
+    Or {
+        /// Arguments to evaluate in order, each should evaluate to a boolean or null
+        args: Vec<Expr>,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// The value of the first argument that is not null, later arguments are not evaluated
+    Coalesce {
+        /// Arguments to evaluate in order
+        args: Vec<Expr>,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// Local binding: the value is evaluated once and can be referred to in the body
+    Let {
+        /// The local variable name
+        var: Symbol,
+        /// The bound value
+        value: Box<Expr>,
+        /// The expression in which the variable is bound
+        body: Box<Expr>,
+        /// The position assigned to the local variable, beyond those of the tuple
+        slot: Option<usize>,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+    /// Applies a lambda to each element of a list, e.g. `map(xs, x -> x * 2)` or
+    /// `filter(xs, x -> x > 0)`. A null list gives null.
+    HigherOrder {
+        /// How the results of the lambda are combined
+        op: HigherOrderOp,
+        /// The list to iterate over
+        list: Box<Expr>,
+        /// The lambda applied to each element
+        lambda: Lambda,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
+}
+
+/// Dropping is iterative, so that deeply nested expressions cannot overflow the stack.
+/// Expressions therefore cannot be destructured by value: match on a reference and take
+/// the parts out with [mem::take] instead.
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        self.take_children(&mut stack);
+        while let Some(mut expr) = stack.pop() {
+            expr.take_children(&mut stack);
+        }
+    }
 }
 
 impl Debug for Expr {
@@ -223,10 +797,81 @@ impl Display for Expr {
                 }
                 writer.finish()
             }
+            Expr::Try { clauses, .. } => {
+                let mut writer = f.debug_tuple("try");
+                for clause in clauses {
+                    writer.field(clause);
+                }
+                writer.finish()
+            }
+            Expr::And { args, .. } | Expr::Or { args, .. } | Expr::Coalesce { args, .. } => {
+                let name = match self {
+                    Expr::And { .. } => "and",
+                    Expr::Or { .. } => "or",
+                    _ => "coalesce",
+                };
+                let mut writer = f.debug_tuple(name);
+                for arg in args {
+                    writer.field(arg);
+                }
+                writer.finish()
+            }
+            Expr::Let {
+                var, value, body, ..
+            } => f
+                .debug_tuple("let")
+                .field(var)
+                .field(value)
+                .field(body)
+                .finish(),
+            Expr::HigherOrder {
+                op, list, lambda, ..
+            } => write!(
+                f,
+                "{}({:?}, {} -> {:?})",
+                op.name(),
+                list,
+                lambda.var.name,
+                lambda.body
+            ),
         }
     }
 }
 
+/// Results of folding side-effect-free ops applied to constant arguments, keyed by the op name
+/// and the arguments
+pub(crate) type PartialEvalMemo = BTreeMap<(&'static str, Vec<DataValue>), DataValue>;
+
+/// Shares one allocation among structurally equal expressions, e.g. the repeated comparisons
+/// in wide disjunctions of generated queries. Expressions are equal here if they have the
+/// same [Expr::encode]ing, so source spans are ignored.
+#[derive(Default)]
+pub struct ExprInterner {
+    interned: BTreeMap<Vec<u8>, Arc<Expr>>,
+}
+
+impl ExprInterner {
+    /// The shared copy of `expr`
+    pub fn intern(&mut self, expr: Expr) -> Arc<Expr> {
+        self.interned
+            .entry(expr.encode())
+            .or_insert_with(|| Arc::new(expr))
+            .clone()
+    }
+}
+
+/// The deepest nesting of expressions that queries may use. Deeper expressions are rejected
+/// when they are compiled, as the recursive evaluation of them could overflow the stack.
+pub const MAX_EXPR_DEPTH: usize = 500;
+
+/// The largest number of conjunctions [Expr::into_dnf] produces
+pub const MAX_DNF_TERMS: usize = 64;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Expression is nested more than {0} levels deep")]
+#[diagnostic(code(eval::depth_exceeded))]
+pub(crate) struct DepthExceededError(pub(crate) usize, #[label] pub(crate) SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Found value {1:?} where a boolean value is expected")]
 #[diagnostic(code(eval::predicate_not_bool))]
@@ -243,16 +888,74 @@ struct BadEntityId(DataValue, #[label] SourceSpan);
 #[diagnostic(code(eval::throw))]
 struct EvalRaisedError(#[label] SourceSpan, #[help] String);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Cannot decode expression: {0}")]
+#[diagnostic(code(deser::expr))]
+struct ExprDeserError(String);
+
 impl Expr {
+    /// Takes the expression out, leaving a null constant in its place
+    pub(crate) fn take(&mut self) -> Expr {
+        let span = self.span();
+        mem::replace(
+            self,
+            Expr::Const {
+                val: DataValue::Null,
+                span,
+            },
+        )
+    }
+    /// Moves the subexpressions into `out`, leaving childless expressions in their place
+    fn take_children(&mut self, out: &mut Vec<Expr>) {
+        match self {
+            Expr::Binding { .. } | Expr::Const { .. } => {}
+            Expr::Apply { args, .. } => out.extend(mem::take(args).into_vec()),
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in mem::take(clauses) {
+                    out.push(cond);
+                    out.push(val);
+                }
+            }
+            Expr::Try { clauses: args, .. }
+            | Expr::And { args, .. }
+            | Expr::Or { args, .. }
+            | Expr::Coalesce { args, .. } => out.append(args),
+            Expr::Let { value, body, .. } => {
+                out.push(value.take());
+                out.push(body.take());
+            }
+            Expr::HigherOrder { list, lambda, .. } => {
+                out.push(list.take());
+                out.push(lambda.body.take());
+            }
+        }
+    }
     pub(crate) fn compile(&self) -> Vec<Bytecode> {
         let mut collector = vec![];
         expr2bytecode(self, &mut collector);
         collector
     }
+    /// Encodes the expression in a compact binary form, e.g. for caching compiled queries.
+    /// Ops are encoded by name. Source spans are not kept.
+    pub fn encode(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).expect("expressions are always serializable")
+    }
+    /// Decodes an expression produced by [Expr::encode]
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        Ok(rmp_serde::from_slice(data).map_err(|e| ExprDeserError(e.to_string()))?)
+    }
     pub(crate) fn span(&self) -> SourceSpan {
         match self {
             Expr::Binding { var, .. } => var.span,
-            Expr::Const { span, .. } | Expr::Apply { span, .. } | Expr::Cond { span, .. } => *span,
+            Expr::Const { span, .. }
+            | Expr::Apply { span, .. }
+            | Expr::Cond { span, .. }
+            | Expr::Try { span, .. }
+            | Expr::And { span, .. }
+            | Expr::Or { span, .. }
+            | Expr::Coalesce { span, .. }
+            | Expr::Let { span, .. }
+            | Expr::HigherOrder { span, .. } => *span,
         }
     }
     pub(crate) fn get_binding(&self) -> Option<&Symbol> {
@@ -269,6 +972,9 @@ impl Expr {
             None
         }
     }
+    pub(crate) fn is_const(&self) -> bool {
+        matches!(self, Expr::Const { .. })
+    }
     pub(crate) fn build_equate(exprs: Vec<Expr>, span: SourceSpan) -> Self {
         Expr::Apply {
             op: &OP_EQ,
@@ -276,12 +982,27 @@ impl Expr {
             span,
         }
     }
+    /// Nested conjunctions are flattened
     pub(crate) fn build_and(exprs: Vec<Expr>, span: SourceSpan) -> Self {
-        Expr::Apply {
-            op: &OP_AND,
-            args: exprs.into(),
-            span,
-        }
+        let args = exprs
+            .into_iter()
+            .flat_map(|mut expr| match &mut expr {
+                Expr::And { args, .. } => mem::take(args),
+                _ => vec![expr],
+            })
+            .collect();
+        Expr::And { args, span }
+    }
+    /// Nested disjunctions are flattened
+    pub(crate) fn build_or(exprs: Vec<Expr>, span: SourceSpan) -> Self {
+        let args = exprs
+            .into_iter()
+            .flat_map(|mut expr| match &mut expr {
+                Expr::Or { args, .. } => mem::take(args),
+                _ => vec![expr],
+            })
+            .collect();
+        Expr::Or { args, span }
     }
     pub(crate) fn build_is_in(exprs: Vec<Expr>, span: SourceSpan) -> Self {
         Expr::Apply {
@@ -298,9 +1019,13 @@ impl Expr {
         }
     }
     pub(crate) fn to_conjunction(&self) -> Vec<Self> {
+        self.split_conjuncts().into_iter().cloned().collect()
+    }
+    /// The operands of the top-level conjunctions, without descending into other expressions
+    pub(crate) fn split_conjuncts(&self) -> Vec<&Self> {
         match self {
-            Expr::Apply { op, args, .. } if **op == OP_AND => args.to_vec(),
-            v => vec![v.clone()],
+            Expr::And { args, .. } => args.iter().flat_map(|arg| arg.split_conjuncts()).collect(),
+            v => vec![v],
         }
     }
     pub(crate) fn fill_binding_indices(
@@ -332,6 +1057,36 @@ impl Expr {
                     val.fill_binding_indices(binding_map)?;
                 }
             }
+            Expr::Try { clauses, .. }
+            | Expr::And { args: clauses, .. }
+            | Expr::Or { args: clauses, .. }
+            | Expr::Coalesce { args: clauses, .. } => {
+                for clause in clauses {
+                    clause.fill_binding_indices(binding_map)?;
+                }
+            }
+            Expr::Let {
+                var,
+                value,
+                body,
+                slot,
+                ..
+            } => {
+                value.fill_binding_indices(binding_map)?;
+                let local_slot = binding_map.values().max().map(|i| i + 1).unwrap_or(0);
+                let mut local_map = binding_map.clone();
+                local_map.insert(var.clone(), local_slot);
+                body.fill_binding_indices(&local_map)?;
+                *slot = Some(local_slot);
+            }
+            Expr::HigherOrder { list, lambda, .. } => {
+                list.fill_binding_indices(binding_map)?;
+                let local_slot = binding_map.values().max().map(|i| i + 1).unwrap_or(0);
+                let mut local_map = binding_map.clone();
+                local_map.insert(lambda.var.clone(), local_slot);
+                lambda.body.fill_binding_indices(&local_map)?;
+                lambda.slot = Some(local_slot);
+            }
         }
         Ok(())
     }
@@ -360,61 +1115,573 @@ impl Expr {
                     cond.do_binding_indices(coll);
                     val.do_binding_indices(coll)
                 }
-            } // Expr::Try { clauses, .. } => {
-              //     for clause in clauses {
-              //         clause.do_binding_indices(coll)
-              //     }
-              // }
+            }
+            Expr::Try { clauses, .. }
+            | Expr::And { args: clauses, .. }
+            | Expr::Or { args: clauses, .. }
+            | Expr::Coalesce { args: clauses, .. } => {
+                for clause in clauses {
+                    clause.do_binding_indices(coll)
+                }
+            }
+            Expr::Let {
+                value, body, slot, ..
+            } => {
+                value.do_binding_indices(coll);
+                let mut body_coll = BTreeSet::default();
+                body.do_binding_indices(&mut body_coll);
+                if let Some(slot) = slot {
+                    body_coll.remove(slot);
+                }
+                coll.extend(body_coll);
+            }
+            Expr::HigherOrder { list, lambda, .. } => {
+                list.do_binding_indices(coll);
+                let mut body_coll = BTreeSet::default();
+                lambda.body.do_binding_indices(&mut body_coll);
+                if let Some(slot) = &lambda.slot {
+                    body_coll.remove(slot);
+                }
+                coll.extend(body_coll);
+            }
         }
     }
-    pub(crate) fn eval_to_const(mut self) -> Result<DataValue> {
+    pub(crate) fn eval_to_const(self) -> Result<DataValue> {
         #[derive(Error, Diagnostic, Debug)]
         #[error("Expression contains unevaluated constant")]
         #[diagnostic(code(eval::not_constant))]
         struct NotConstError;
 
+        if self.bindings().is_empty() {
+            // side-effecting ops are not folded, but are still evaluated here
+            let mut expr = self;
+            expr.partial_eval()?;
+            return expr.eval(vec![]);
+        }
+        match self.partial_eval_to_value()? {
+            Some(val) => Ok(val),
+            None => bail!(NotConstError),
+        }
+    }
+    /// Folds the expression, returning its value if it is entirely constant
+    pub(crate) fn partial_eval_to_value(mut self) -> Result<Option<DataValue>> {
         self.partial_eval()?;
+        Ok(match &mut self {
+            Expr::Const { val, .. } => Some(mem::replace(val, DataValue::Null)),
+            _ => None,
+        })
+    }
+    /// Folds the expression, failing if it is nested more than [MAX_EXPR_DEPTH] levels deep
+    pub(crate) fn partial_eval(&mut self) -> Result<()> {
+        self.check_depth(MAX_EXPR_DEPTH)?;
+        self.do_partial_eval(&mut None)
+    }
+    /// Folds the expression, reusing the results of applications already folded into `memo`.
+    /// The same memo can be used across expressions.
+    // `DataValue` only has interior mutability inside regex caches, which does not affect ordering
+    #[allow(clippy::mutable_key_type)]
+    pub(crate) fn partial_eval_memoized(&mut self, memo: &mut PartialEvalMemo) -> Result<()> {
+        self.check_depth(MAX_EXPR_DEPTH)?;
+        self.do_partial_eval(&mut Some(memo))
+    }
+    /// Checks the nesting depth without recursion, so that it cannot itself overflow the stack
+    pub(crate) fn check_depth(&self, max_depth: usize) -> Result<()> {
+        let mut stack = vec![(self, 0)];
+        while let Some((expr, depth)) = stack.pop() {
+            ensure!(
+                depth <= max_depth,
+                DepthExceededError(max_depth, expr.span())
+            );
+            match expr {
+                Expr::Binding { .. } | Expr::Const { .. } => {}
+                Expr::Apply { args, .. } => stack.extend(args.iter().map(|arg| (arg, depth + 1))),
+                Expr::Cond { clauses, .. } => {
+                    for (cond, val) in clauses {
+                        stack.push((cond, depth + 1));
+                        stack.push((val, depth + 1));
+                    }
+                }
+                Expr::Try { clauses, .. }
+                | Expr::And { args: clauses, .. }
+                | Expr::Or { args: clauses, .. }
+                | Expr::Coalesce { args: clauses, .. } => {
+                    stack.extend(clauses.iter().map(|clause| (clause, depth + 1)))
+                }
+                Expr::Let { value, body, .. } => {
+                    stack.push((value, depth + 1));
+                    stack.push((body, depth + 1));
+                }
+                Expr::HigherOrder { list, lambda, .. } => {
+                    stack.push((list, depth + 1));
+                    stack.push((&lambda.body, depth + 1));
+                }
+            }
+        }
+        Ok(())
+    }
+    fn do_partial_eval(&mut self, memo: &mut Option<&mut PartialEvalMemo>) -> Result<()> {
+        // each kind of expression is folded in a separate function, keeping the recursive frames small
         match self {
-            Expr::Const { val, .. } => Ok(val),
-            _ => bail!(NotConstError),
+            Expr::Binding { .. } | Expr::Const { .. } => Ok(()),
+            Expr::Try { .. } => self.partial_eval_try(memo),
+            Expr::Cond { .. } => self.partial_eval_cond(memo),
+            Expr::And { .. } | Expr::Or { .. } => self.partial_eval_junction(memo),
+            Expr::Coalesce { .. } => self.partial_eval_coalesce(memo),
+            Expr::Let { .. } => self.partial_eval_let(memo),
+            Expr::HigherOrder { .. } => self.partial_eval_higher_order(memo),
+            Expr::Apply { .. } => self.partial_eval_apply(memo),
         }
     }
-    pub(crate) fn partial_eval(&mut self) -> Result<()> {
-        if let Expr::Apply { args, span, .. } = self {
-            let span = *span;
-            let mut all_evaluated = true;
-            for arg in args.iter_mut() {
-                arg.partial_eval()?;
-                all_evaluated = all_evaluated && matches!(arg, Expr::Const { .. });
-            }
-            if all_evaluated {
-                let result = self.eval(&vec![])?;
-                mem::swap(self, &mut Expr::Const { val: result, span });
-            }
-            // nested not's can accumulate during conversion to normal form
-            if let Expr::Apply {
-                op: op1,
-                args: arg1,
-                ..
-            } = self
-            {
-                if op1.name == OP_NEGATE.name {
-                    if let Some(Expr::Apply {
-                        op: op2,
-                        args: arg2,
-                        ..
-                    }) = arg1.first()
-                    {
-                        if op2.name == OP_NEGATE.name {
-                            let mut new_self = arg2[0].clone();
-                            mem::swap(self, &mut new_self);
+    fn partial_eval_try(&mut self, memo: &mut Option<&mut PartialEvalMemo>) -> Result<()> {
+        let Expr::Try { clauses, .. } = self else {
+            unreachable!()
+        };
+        // clauses that fail during constant folding will always fail, and are dropped
+        let total = clauses.len();
+        let mut kept = vec![];
+        for (i, mut clause) in mem::take(clauses).into_iter().enumerate() {
+            if !kept.is_empty() {
+                kept.push(clause);
+            } else if i == total - 1 {
+                clause.do_partial_eval(memo)?;
+                kept.push(clause);
+            } else if clause.do_partial_eval(memo).is_ok() {
+                if clause.is_const() {
+                    *self = clause;
+                    return Ok(());
+                }
+                kept.push(clause);
+            }
+        }
+        if kept.len() == 1 {
+            *self = kept.pop().unwrap();
+        } else {
+            *clauses = kept;
+        }
+        Ok(())
+    }
+    fn partial_eval_cond(&mut self, memo: &mut Option<&mut PartialEvalMemo>) -> Result<()> {
+        let Expr::Cond { clauses, .. } = self else {
+            unreachable!()
+        };
+        // errors during folding are left for evaluation, since the clause may not be reached
+        let mut kept = vec![];
+        for (mut cond, mut val) in mem::take(clauses) {
+            cond.partial_eval_or_keep(memo);
+            match cond.get_const() {
+                Some(DataValue::Bool(false)) => continue,
+                Some(DataValue::Bool(true)) => {
+                    val.partial_eval_or_keep(memo);
+                    if kept.is_empty() {
+                        *self = val;
+                        return Ok(());
+                    }
+                    // later clauses are never reached
+                    kept.push((cond, val));
+                    break;
+                }
+                _ => {
+                    val.partial_eval_or_keep(memo);
+                    kept.push((cond, val));
+                }
+            }
+        }
+        if kept.is_empty() {
+            *self = Expr::Const {
+                val: DataValue::Null,
+                span: self.span(),
+            };
+        } else {
+            *clauses = kept;
+        }
+        Ok(())
+    }
+    fn partial_eval_junction(&mut self, memo: &mut Option<&mut PartialEvalMemo>) -> Result<()> {
+        let (args, on, span) = match self {
+            Expr::And { args, span } => (args, false, *span),
+            Expr::Or { args, span } => (args, true, *span),
+            _ => unreachable!(),
+        };
+        // errors during folding are left for evaluation, unless the argument is always reached
+        let mut kept = vec![];
+        for mut arg in mem::take(args) {
+            if kept.is_empty() {
+                arg.do_partial_eval(memo)?;
+            } else {
+                arg.partial_eval_or_keep(memo);
+            }
+            match arg.get_const() {
+                // does not affect the result
+                Some(DataValue::Bool(b)) if *b != on => continue,
+                Some(DataValue::Bool(_)) => {
+                    if kept.is_empty() {
+                        *self = arg;
+                        return Ok(());
+                    }
+                    // later arguments are never reached
+                    kept.push(arg);
+                    break;
+                }
+                _ => kept.push(arg),
+            }
+        }
+        if kept.is_empty() {
+            *self = Expr::Const {
+                val: DataValue::from(!on),
+                span,
+            };
+        } else {
+            let all_const = kept.iter().all(|arg| arg.is_const());
+            *args = kept;
+            // e.g. nulls, which do not decide the result but still affect it
+            if all_const {
+                *self = Expr::Const {
+                    val: self.eval([])?,
+                    span,
+                };
+            }
+        }
+        Ok(())
+    }
+    fn partial_eval_coalesce(&mut self, memo: &mut Option<&mut PartialEvalMemo>) -> Result<()> {
+        let Expr::Coalesce { args, span } = self else {
+            unreachable!()
+        };
+        let span = *span;
+        // errors during folding are left for evaluation, unless the argument is always reached
+        let mut kept = vec![];
+        for mut arg in mem::take(args) {
+            if kept.is_empty() {
+                arg.do_partial_eval(memo)?;
+            } else {
+                arg.partial_eval_or_keep(memo);
+            }
+            if arg.get_const() == Some(&DataValue::Null) {
+                continue;
+            }
+            let last_reached = arg.is_known_non_null();
+            kept.push(arg);
+            if last_reached {
+                break;
+            }
+        }
+        match kept.len() {
+            0 => {
+                *self = Expr::Const {
+                    val: DataValue::Null,
+                    span,
+                }
+            }
+            1 => *self = kept.pop().unwrap(),
+            _ => *args = kept,
+        }
+        Ok(())
+    }
+    fn partial_eval_let(&mut self, memo: &mut Option<&mut PartialEvalMemo>) -> Result<()> {
+        let Expr::Let {
+            var, value, body, ..
+        } = self
+        else {
+            unreachable!()
+        };
+        value.do_partial_eval(memo)?;
+        if let Expr::Const { val, .. } = &**value {
+            body.substitute_const(var, val);
+            body.do_partial_eval(memo)?;
+            *self = mem::replace(
+                &mut **body,
+                Expr::Const {
+                    val: DataValue::Null,
+                    span: Default::default(),
+                },
+            );
+        } else {
+            body.do_partial_eval(memo)?;
+        }
+        Ok(())
+    }
+    fn partial_eval_higher_order(&mut self, memo: &mut Option<&mut PartialEvalMemo>) -> Result<()> {
+        let Expr::HigherOrder {
+            op,
+            list,
+            lambda,
+            span,
+        } = self
+        else {
+            unreachable!()
+        };
+        let span = *span;
+        list.do_partial_eval(memo)?;
+        match list.get_const() {
+            Some(DataValue::Null) => {
+                *self = Expr::Const {
+                    val: DataValue::Null,
+                    span,
+                };
+                return Ok(());
+            }
+            // folded completely if the lambda folds to a constant for every element
+            Some(DataValue::List(items)) => {
+                let mut acc = op.initial();
+                let mut all_folded = true;
+                for item in items {
+                    let mut body = (*lambda.body).clone();
+                    body.substitute_const(&lambda.var, item);
+                    body.do_partial_eval(memo)?;
+                    match &mut body {
+                        Expr::Const { val, .. } => {
+                            let val = mem::replace(val, DataValue::Null);
+                            if op.combine(&mut acc, item.clone(), val, span)? {
+                                break;
+                            }
+                        }
+                        _ => {
+                            all_folded = false;
+                            break;
                         }
                     }
                 }
+                if all_folded {
+                    *self = Expr::Const { val: acc, span };
+                    return Ok(());
+                }
             }
+            _ => {}
         }
+        // errors are left for evaluation, since the lambda is not called for empty lists
+        lambda.body.partial_eval_or_keep(memo);
         Ok(())
     }
+    fn partial_eval_apply(&mut self, memo: &mut Option<&mut PartialEvalMemo>) -> Result<()> {
+        let Expr::Apply { op, args, span } = self else {
+            unreachable!()
+        };
+        let span = *span;
+        let op: &'static Op = op;
+        op.check_arity(args.len(), span)?;
+        let mut all_evaluated = !op.has_side_effect();
+        for arg in args.iter_mut() {
+            arg.do_partial_eval(memo)?;
+            all_evaluated = all_evaluated && arg.is_const();
+        }
+        if all_evaluated {
+            let result = match memo {
+                None => self.eval([])?,
+                Some(memo) => {
+                    let key = (
+                        op.name,
+                        args.iter()
+                            .map(|arg| arg.get_const().unwrap().clone())
+                            .collect_vec(),
+                    );
+                    match memo.get(&key) {
+                        Some(val) => val.clone(),
+                        None => {
+                            let val = self.eval([])?;
+                            memo.insert(key, val.clone());
+                            val
+                        }
+                    }
+                }
+            };
+            *self = Expr::Const { val: result, span };
+        } else if op.name == OP_IS_NULL.name && args[0].is_known_non_null() {
+            *self = Expr::Const {
+                val: DataValue::from(false),
+                span,
+            };
+        } else if let Some(elem) = Self::fold_list_access(op, args, span) {
+            *self = elem;
+        }
+        // nested not's can accumulate during conversion to normal form
+        if let Expr::Apply {
+            op: op1,
+            args: arg1,
+            ..
+        } = self
+        {
+            if op1.name == OP_NEGATE.name {
+                if let Some(Expr::Apply {
+                    op: op2,
+                    args: arg2,
+                    ..
+                }) = arg1.first()
+                {
+                    if op2.name == OP_NEGATE.name {
+                        let mut new_self = arg2[0].clone();
+                        mem::swap(self, &mut new_self);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// `get` and `maybe_get` of a constant index into a list literal resolve to the element,
+    /// even if it is not constant, as long as the other elements cannot fail or have side
+    /// effects. `maybe_get` of an index out of bounds resolves to null.
+    fn fold_list_access(op: &Op, args: &[Expr], span: SourceSpan) -> Option<Expr> {
+        if op.name != OP_GET.name && op.name != OP_MAYBE_GET.name {
+            return None;
+        }
+        let Expr::Apply {
+            op: list_op,
+            args: elems,
+            ..
+        } = &args[0]
+        else {
+            return None;
+        };
+        let Some(DataValue::Num(Num::Int(i))) = args[1].get_const() else {
+            return None;
+        };
+        if list_op.name != OP_LIST.name {
+            return None;
+        }
+        let idx = get_index(*i, elems.len()).ok();
+        let skipped_are_safe = elems
+            .iter()
+            .enumerate()
+            .all(|(j, elem)| Some(j) == idx || elem.cannot_fail());
+        if !skipped_are_safe {
+            return None;
+        }
+        match idx {
+            Some(idx) => Some(elems[idx].clone()),
+            None if op.name == OP_MAYBE_GET.name => Some(Expr::Const {
+                val: DataValue::Null,
+                span,
+            }),
+            // the error is left for evaluation
+            None => None,
+        }
+    }
+    /// Whether evaluating the expression can neither fail nor have side effects
+    fn cannot_fail(&self) -> bool {
+        match self {
+            Expr::Const { .. } | Expr::Binding { .. } => true,
+            Expr::Apply { op, args, .. } => {
+                op.name == OP_LIST.name && args.iter().all(|arg| arg.cannot_fail())
+            }
+            _ => false,
+        }
+    }
+    /// Whether the expression always evaluates to a non-null value, without error
+    fn is_known_non_null(&self) -> bool {
+        match self {
+            Expr::Const { val, .. } => *val != DataValue::Null,
+            _ => self.is_known_number(),
+        }
+    }
+    fn is_known_number(&self) -> bool {
+        match self {
+            Expr::Const { val, .. } => matches!(val, DataValue::Num(_)),
+            Expr::Apply { op, args, .. } => {
+                if [OP_RAND_FLOAT.name, OP_NOW.name].contains(&op.name) {
+                    return true;
+                }
+                // arithmetic on numbers can only fail in the strict modes
+                let lenient = !STRICT_FLOATS.with(Cell::get)
+                    && NUMERIC_PROMOTION.with(Cell::get) == NumericPromotion::Float;
+                lenient
+                    && [
+                        OP_ADD.name,
+                        OP_SUB.name,
+                        OP_MUL.name,
+                        OP_DIV.name,
+                        OP_MINUS.name,
+                    ]
+                    .contains(&op.name)
+                    && args.iter().all(|arg| arg.is_known_number())
+            }
+            _ => false,
+        }
+    }
+    fn partial_eval_or_keep(&mut self, memo: &mut Option<&mut PartialEvalMemo>) {
+        let mut folded = self.clone();
+        if folded.do_partial_eval(memo).is_ok() {
+            *self = folded;
+        }
+    }
+    /// Replaces free occurrences of `var` with the constant `val`
+    pub(crate) fn substitute_const(&mut self, var: &Symbol, val: &DataValue) {
+        match self {
+            Expr::Binding { var: v, .. } => {
+                if v == var {
+                    *self = Expr::Const {
+                        val: val.clone(),
+                        span: v.span,
+                    }
+                }
+            }
+            Expr::Const { .. } => {}
+            Expr::Apply { args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.substitute_const(var, val)
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, expr) in clauses {
+                    cond.substitute_const(var, val);
+                    expr.substitute_const(var, val)
+                }
+            }
+            Expr::Try { clauses, .. }
+            | Expr::And { args: clauses, .. }
+            | Expr::Or { args: clauses, .. }
+            | Expr::Coalesce { args: clauses, .. } => {
+                for clause in clauses {
+                    clause.substitute_const(var, val)
+                }
+            }
+            Expr::Let {
+                var: v,
+                value,
+                body,
+                ..
+            } => {
+                value.substitute_const(var, val);
+                // the inner binding shadows the outer one
+                if v != var {
+                    body.substitute_const(var, val)
+                }
+            }
+            Expr::HigherOrder { list, lambda, .. } => {
+                list.substitute_const(var, val);
+                if lambda.var != *var {
+                    lambda.body.substitute_const(var, val)
+                }
+            }
+        }
+    }
+    /// Rebuilds the expression bottom-up, replacing every node with the result of `rule`
+    /// applied to it. Each node is visited exactly once, after its children have been
+    /// rewritten.
+    pub fn rewrite(mut self, rule: &mut impl FnMut(Expr) -> Expr) -> Expr {
+        let mut rewrite_child = |child: &mut Expr| *child = child.take().rewrite(rule);
+        match &mut self {
+            Expr::Binding { .. } | Expr::Const { .. } => {}
+            Expr::Apply { args, .. } => args.iter_mut().for_each(rewrite_child),
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    rewrite_child(cond);
+                    rewrite_child(val);
+                }
+            }
+            Expr::Try { clauses: args, .. }
+            | Expr::And { args, .. }
+            | Expr::Or { args, .. }
+            | Expr::Coalesce { args, .. } => args.iter_mut().for_each(rewrite_child),
+            Expr::Let { value, body, .. } => {
+                rewrite_child(value);
+                rewrite_child(body);
+            }
+            Expr::HigherOrder { list, lambda, .. } => {
+                rewrite_child(list);
+                rewrite_child(&mut lambda.body);
+            }
+        }
+        rule(self)
+    }
     pub(crate) fn bindings(&self) -> BTreeSet<Symbol> {
         let mut ret = BTreeSet::new();
         self.collect_bindings(&mut ret);
@@ -437,134 +1704,494 @@ impl Expr {
                     val.collect_bindings(coll)
                 }
             }
+            Expr::Try { clauses, .. }
+            | Expr::And { args: clauses, .. }
+            | Expr::Or { args: clauses, .. }
+            | Expr::Coalesce { args: clauses, .. } => {
+                for clause in clauses {
+                    clause.collect_bindings(coll)
+                }
+            }
+            Expr::Let {
+                var, value, body, ..
+            } => {
+                value.collect_bindings(coll);
+                let mut body_coll = BTreeSet::new();
+                body.collect_bindings(&mut body_coll);
+                body_coll.remove(var);
+                coll.extend(body_coll);
+            }
+            Expr::HigherOrder { list, lambda, .. } => {
+                list.collect_bindings(coll);
+                let mut body_coll = BTreeSet::new();
+                lambda.body.collect_bindings(&mut body_coll);
+                body_coll.remove(&lambda.var);
+                coll.extend(body_coll);
+            }
         }
     }
     pub(crate) fn eval(&self, bindings: impl AsRef<[DataValue]>) -> Result<DataValue> {
+        TreeEvaluator::new(bindings.as_ref()).run(self)
+    }
+    /// Renders the expression as an indented tree, one node per line, for debugging
+    pub fn explain(&self) -> String {
+        let mut out = String::new();
+        self.explain_into(&mut out, 0);
+        out
+    }
+    fn explain_into(&self, out: &mut String, depth: usize) {
+        use std::fmt::Write;
+
+        let indent = "  ".repeat(depth);
         match self {
-            Expr::Binding { var, tuple_pos, .. } => match tuple_pos {
-                None => {
-                    bail!(UnboundVariableError(var.name.to_string(), var.span))
-                }
-                Some(i) => Ok(bindings
-                    .as_ref()
-                    .get(*i)
-                    .ok_or_else(|| {
-                        TupleTooShortError(
-                            var.name.to_string(),
-                            *i,
-                            bindings.as_ref().len(),
-                            var.span,
-                        )
-                    })?
-                    .clone()),
+            Expr::Binding { var, tuple_pos } => match tuple_pos {
+                Some(pos) => writeln!(out, "{indent}Binding {} @{pos}", var.name),
+                None => writeln!(out, "{indent}Binding {} (unresolved)", var.name),
             },
-            Expr::Const { val, .. } => Ok(val.clone()),
+            Expr::Const { val, .. } => writeln!(out, "{indent}Const {val}"),
             Expr::Apply { op, args, .. } => {
-                let args: Box<[DataValue]> = args
-                    .iter()
-                    .map(|v| v.eval(bindings.as_ref()))
-                    .try_collect()?;
-                Ok((op.inner)(&args)
-                    .map_err(|err| EvalRaisedError(self.span(), err.to_string()))?)
+                let name = op.name.strip_prefix("OP_").unwrap().to_lowercase();
+                writeln!(out, "{indent}Apply {name}").unwrap();
+                for arg in args.iter() {
+                    arg.explain_into(out, depth + 1);
+                }
+                Ok(())
             }
             Expr::Cond { clauses, .. } => {
+                writeln!(out, "{indent}Cond").unwrap();
                 for (cond, val) in clauses {
-                    let cond_val = cond.eval(bindings.as_ref())?;
-                    let cond_val = cond_val
-                        .get_bool()
-                        .ok_or_else(|| PredicateTypeError(cond.span(), cond_val))?;
-
-                    if cond_val {
-                        return val.eval(bindings.as_ref());
+                    writeln!(out, "{indent}  When").unwrap();
+                    cond.explain_into(out, depth + 2);
+                    writeln!(out, "{indent}  Then").unwrap();
+                    val.explain_into(out, depth + 2);
+                }
+                Ok(())
+            }
+            Expr::Try { clauses, .. } => {
+                writeln!(out, "{indent}Try").unwrap();
+                for clause in clauses {
+                    clause.explain_into(out, depth + 1);
+                }
+                Ok(())
+            }
+            Expr::And { args, .. } | Expr::Or { args, .. } | Expr::Coalesce { args, .. } => {
+                let name = match self {
+                    Expr::And { .. } => "And",
+                    Expr::Or { .. } => "Or",
+                    _ => "Coalesce",
+                };
+                writeln!(out, "{indent}{name}").unwrap();
+                for arg in args {
+                    arg.explain_into(out, depth + 1);
+                }
+                Ok(())
+            }
+            Expr::Let {
+                var,
+                value,
+                body,
+                slot,
+                ..
+            } => {
+                match slot {
+                    Some(slot) => writeln!(out, "{indent}Let {} @{slot}", var.name),
+                    None => writeln!(out, "{indent}Let {} (unresolved)", var.name),
+                }
+                .unwrap();
+                value.explain_into(out, depth + 1);
+                body.explain_into(out, depth + 1);
+                Ok(())
+            }
+            Expr::HigherOrder {
+                op, list, lambda, ..
+            } => {
+                let name = op.name();
+                match lambda.slot {
+                    Some(slot) => writeln!(
+                        out,
+                        "{indent}HigherOrder {name} {} @{slot}",
+                        lambda.var.name
+                    ),
+                    None => writeln!(
+                        out,
+                        "{indent}HigherOrder {name} {} (unresolved)",
+                        lambda.var.name
+                    ),
+                }
+                .unwrap();
+                list.explain_into(out, depth + 1);
+                lambda.body.explain_into(out, depth + 1);
+                Ok(())
+            }
+        }
+        .unwrap()
+    }
+    /// Structural equality ignoring source spans, where the arguments to commutative operators
+    /// may appear in any order
+    pub fn is_equivalent(&self, other: &Expr) -> bool {
+        match (self, other) {
+            (Expr::Binding { var: a, .. }, Expr::Binding { var: b, .. }) => a.name == b.name,
+            (Expr::Const { val: a, .. }, Expr::Const { val: b, .. }) => a == b,
+            (
+                Expr::Apply {
+                    op: op_a, args: a, ..
+                },
+                Expr::Apply {
+                    op: op_b, args: b, ..
+                },
+            ) => {
+                if op_a.name != op_b.name || a.len() != b.len() {
+                    return false;
+                }
+                let commutative = [
+                    OP_ADD.name,
+                    OP_MUL.name,
+                    OP_EQ.name,
+                    OP_NEQ.name,
+                    OP_AND.name,
+                    OP_OR.name,
+                ]
+                .contains(&op_a.name);
+                if !commutative {
+                    return a.iter().zip(b.iter()).all(|(x, y)| x.is_equivalent(y));
+                }
+                Self::all_equivalent_unordered(a, b)
+            }
+            (Expr::And { args: a, .. }, Expr::And { args: b, .. })
+            | (Expr::Or { args: a, .. }, Expr::Or { args: b, .. }) => {
+                a.len() == b.len() && Self::all_equivalent_unordered(a, b)
+            }
+            (Expr::Cond { clauses: a, .. }, Expr::Cond { clauses: b, .. }) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|((c1, v1), (c2, v2))| c1.is_equivalent(c2) && v1.is_equivalent(v2))
+            }
+            (Expr::Try { clauses: a, .. }, Expr::Try { clauses: b, .. })
+            | (Expr::Coalesce { args: a, .. }, Expr::Coalesce { args: b, .. }) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.is_equivalent(y))
+            }
+            (
+                Expr::Let {
+                    var: var_a,
+                    value: value_a,
+                    body: body_a,
+                    ..
+                },
+                Expr::Let {
+                    var: var_b,
+                    value: value_b,
+                    body: body_b,
+                    ..
+                },
+            ) => {
+                var_a.name == var_b.name
+                    && value_a.is_equivalent(value_b)
+                    && body_a.is_equivalent(body_b)
+            }
+            (
+                Expr::HigherOrder {
+                    op: op_a,
+                    list: list_a,
+                    lambda: lambda_a,
+                    ..
+                },
+                Expr::HigherOrder {
+                    op: op_b,
+                    list: list_b,
+                    lambda: lambda_b,
+                    ..
+                },
+            ) => {
+                op_a == op_b
+                    && lambda_a.var.name == lambda_b.var.name
+                    && list_a.is_equivalent(list_b)
+                    && lambda_a.body.is_equivalent(&lambda_b.body)
+            }
+            _ => false,
+        }
+    }
+    fn all_equivalent_unordered(a: &[Expr], b: &[Expr]) -> bool {
+        // equivalence is transitive, so greedy matching suffices
+        let mut unmatched = b.iter().collect_vec();
+        for x in a.iter() {
+            match unmatched.iter().position(|y| x.is_equivalent(y)) {
+                Some(i) => {
+                    unmatched.swap_remove(i);
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+    /// Rewrites comparisons between a constant and a variable so that the variable is on the left,
+    /// e.g. `1 < x` becomes `x > 1`
+    pub(crate) fn normalize_comparisons(&mut self) {
+        match self {
+            Expr::Binding { .. } | Expr::Const { .. } => {}
+            Expr::Apply { op, args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.normalize_comparisons();
+                }
+                let flipped = match op.name {
+                    n if n == OP_GT.name => &OP_LT,
+                    n if n == OP_GE.name => &OP_LE,
+                    n if n == OP_LT.name => &OP_GT,
+                    n if n == OP_LE.name => &OP_GE,
+                    _ => return,
+                };
+                if args.len() == 2 && args[0].is_const() && args[1].get_binding().is_some() {
+                    args.swap(0, 1);
+                    *op = flipped;
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.normalize_comparisons();
+                    val.normalize_comparisons();
+                }
+            }
+            Expr::Try { clauses, .. }
+            | Expr::And { args: clauses, .. }
+            | Expr::Or { args: clauses, .. }
+            | Expr::Coalesce { args: clauses, .. } => {
+                for clause in clauses {
+                    clause.normalize_comparisons();
+                }
+            }
+            Expr::Let { value, body, .. } => {
+                value.normalize_comparisons();
+                body.normalize_comparisons();
+            }
+            Expr::HigherOrder { list, lambda, .. } => {
+                list.normalize_comparisons();
+                lambda.body.normalize_comparisons();
+            }
+        }
+    }
+    /// Rewrites a boolean expression into a disjunction of conjunctions, pushing negations
+    /// inwards by De Morgan's laws and distributing conjunctions over disjunctions. Both hold
+    /// in three-valued logic, so nulls propagate as before, though an operand that was
+    /// skipped by short-circuiting may now be evaluated and fail. The original expression is
+    /// returned if the result would have more than [MAX_DNF_TERMS] conjunctions, or if
+    /// it has side effects, as distributing evaluates some parts more than once.
+    pub fn into_dnf(self) -> Expr {
+        if self.has_side_effect() {
+            return self;
+        }
+        let span = self.span();
+        let Some(mut terms) = self.dnf_terms(false) else {
+            return self;
+        };
+        let mut disjuncts = terms
+            .drain(..)
+            .map(|mut literals| {
+                if literals.len() == 1 {
+                    literals.pop().unwrap()
+                } else {
+                    Expr::And {
+                        args: literals,
+                        span,
                     }
                 }
-                Ok(DataValue::Null)
+            })
+            .collect_vec();
+        if disjuncts.len() == 1 {
+            disjuncts.pop().unwrap()
+        } else {
+            Expr::Or {
+                args: disjuncts,
+                span,
+            }
+        }
+    }
+    /// The conjunctions of literals making up the expression, or its negation if `negated`
+    fn dnf_terms(&self, negated: bool) -> Option<Vec<Vec<Expr>>> {
+        match self {
+            // negating null is an error, so De Morgan's laws only apply if no operand is null
+            Expr::And { .. } | Expr::Or { .. } if negated && !self.is_strict_boolean() => {
+                Some(vec![vec![self.clone().negate(self.span())]])
+            }
+            Expr::And { args, .. } | Expr::Or { args, .. }
+                if matches!(self, Expr::And { .. }) != negated =>
+            {
+                let mut terms = vec![vec![]];
+                for arg in args {
+                    let arg_terms = arg.dnf_terms(negated)?;
+                    if terms.len() * arg_terms.len() > MAX_DNF_TERMS {
+                        return None;
+                    }
+                    terms = terms
+                        .iter()
+                        .flat_map(|term| {
+                            arg_terms.iter().map(move |arg_term| {
+                                term.iter().chain(arg_term).cloned().collect_vec()
+                            })
+                        })
+                        .collect();
+                }
+                Some(terms)
+            }
+            Expr::And { args, .. } | Expr::Or { args, .. } => {
+                let mut terms = vec![];
+                for arg in args {
+                    terms.extend(arg.dnf_terms(negated)?);
+                    if terms.len() > MAX_DNF_TERMS {
+                        return None;
+                    }
+                }
+                Some(terms)
+            }
+            // likewise for dropping double negation
+            Expr::Apply { op, args, .. }
+                if op.name == OP_NEGATE.name && (!negated || args[0].is_strict_boolean()) =>
+            {
+                args[0].dnf_terms(!negated)
+            }
+            literal => {
+                let literal = if negated {
+                    literal.clone().negate(literal.span())
+                } else {
+                    literal.clone()
+                };
+                Some(vec![vec![literal]])
+            }
+        }
+    }
+    /// Whether the expression evaluates to a boolean, never null, unless there is an error
+    fn is_strict_boolean(&self) -> bool {
+        match self {
+            Expr::And { args, .. } | Expr::Or { args, .. } => {
+                args.iter().all(|arg| arg.is_strict_boolean())
+            }
+            Expr::Apply { op, .. } => [
+                OP_NEGATE.name,
+                OP_EQ.name,
+                OP_NEQ.name,
+                OP_LT.name,
+                OP_LE.name,
+                OP_GT.name,
+                OP_GE.name,
+            ]
+            .contains(&op.name),
+            _ => false,
+        }
+    }
+    fn has_side_effect(&self) -> bool {
+        match self {
+            Expr::Binding { .. } | Expr::Const { .. } => false,
+            Expr::Apply { op, args, .. } => {
+                op.has_side_effect() || args.iter().any(|arg| arg.has_side_effect())
+            }
+            Expr::Cond { clauses, .. } => clauses
+                .iter()
+                .any(|(cond, val)| cond.has_side_effect() || val.has_side_effect()),
+            Expr::Try { clauses, .. }
+            | Expr::And { args: clauses, .. }
+            | Expr::Or { args: clauses, .. }
+            | Expr::Coalesce { args: clauses, .. } => {
+                clauses.iter().any(|clause| clause.has_side_effect())
+            }
+            Expr::Let { value, body, .. } => value.has_side_effect() || body.has_side_effect(),
+            Expr::HigherOrder { list, lambda, .. } => {
+                list.has_side_effect() || lambda.body.has_side_effect()
             }
         }
     }
     pub(crate) fn extract_bound(&self, target: &Symbol) -> Result<ValueRange> {
         Ok(match self {
-            Expr::Binding { .. } | Expr::Const { .. } | Expr::Cond { .. } => ValueRange::default(),
-            Expr::Apply { op, args, .. } => match op.name {
-                n if n == OP_GE.name || n == OP_GT.name => {
-                    if let Some(symb) = args[0].get_binding() {
-                        if let Some(val) = args[1].get_const() {
-                            if target == symb {
-                                let tar_val = match val.get_int() {
-                                    Some(i) => DataValue::from(i),
-                                    None => val.clone(),
-                                };
-                                return Ok(ValueRange::lower_bound(tar_val));
+            Expr::Binding { .. }
+            | Expr::Const { .. }
+            | Expr::Cond { .. }
+            | Expr::Try { .. }
+            | Expr::And { .. }
+            | Expr::Or { .. }
+            | Expr::Coalesce { .. }
+            | Expr::Let { .. }
+            | Expr::HigherOrder { .. } => ValueRange::default(),
+            Expr::Apply { op, args, span } => {
+                op.check_arity(args.len(), *span)?;
+                match op.name {
+                    n if n == OP_GE.name || n == OP_GT.name => {
+                        if let Some(symb) = args[0].get_binding() {
+                            if let Some(val) = range_bound_const(&args[1]) {
+                                if target == symb {
+                                    let tar_val = match val.get_int() {
+                                        Some(i) => DataValue::from(i),
+                                        None => val.clone(),
+                                    };
+                                    return Ok(ValueRange::lower_bound(tar_val));
+                                }
                             }
                         }
-                    }
-                    if let Some(symb) = args[1].get_binding() {
-                        if let Some(val) = args[0].get_const() {
-                            if target == symb {
-                                let tar_val = match val.get_float() {
-                                    Some(i) => DataValue::from(i),
-                                    None => val.clone(),
-                                };
-                                return Ok(ValueRange::upper_bound(tar_val));
+                        if let Some(symb) = args[1].get_binding() {
+                            if let Some(val) = range_bound_const(&args[0]) {
+                                if target == symb {
+                                    let tar_val = match val.get_float() {
+                                        Some(i) => DataValue::from(i),
+                                        None => val.clone(),
+                                    };
+                                    return Ok(ValueRange::upper_bound(tar_val));
+                                }
                             }
                         }
+                        ValueRange::default()
                     }
-                    ValueRange::default()
-                }
-                n if n == OP_LE.name || n == OP_LT.name => {
-                    if let Some(symb) = args[0].get_binding() {
-                        if let Some(val) = args[1].get_const() {
-                            if target == symb {
-                                let tar_val = match val.get_float() {
-                                    Some(i) => DataValue::from(i),
-                                    None => val.clone(),
-                                };
-
-                                return Ok(ValueRange::upper_bound(tar_val));
+                    n if n == OP_LE.name || n == OP_LT.name => {
+                        if let Some(symb) = args[0].get_binding() {
+                            if let Some(val) = range_bound_const(&args[1]) {
+                                if target == symb {
+                                    let tar_val = match val.get_float() {
+                                        Some(i) => DataValue::from(i),
+                                        None => val.clone(),
+                                    };
+
+                                    return Ok(ValueRange::upper_bound(tar_val));
+                                }
                             }
                         }
-                    }
-                    if let Some(symb) = args[1].get_binding() {
-                        if let Some(val) = args[0].get_const() {
-                            if target == symb {
-                                let tar_val = match val.get_int() {
-                                    Some(i) => DataValue::from(i),
-                                    None => val.clone(),
-                                };
-
-                                return Ok(ValueRange::lower_bound(tar_val));
+                        if let Some(symb) = args[1].get_binding() {
+                            if let Some(val) = range_bound_const(&args[0]) {
+                                if target == symb {
+                                    let tar_val = match val.get_int() {
+                                        Some(i) => DataValue::from(i),
+                                        None => val.clone(),
+                                    };
+
+                                    return Ok(ValueRange::lower_bound(tar_val));
+                                }
                             }
                         }
+                        ValueRange::default()
                     }
-                    ValueRange::default()
-                }
-                n if n == OP_STARTS_WITH.name => {
-                    if let Some(symb) = args[0].get_binding() {
-                        if let Some(val) = args[1].get_const() {
-                            if target == symb {
-                                let s = val.get_str().ok_or_else(|| {
-                                    #[derive(Debug, Error, Diagnostic)]
-                                    #[error("Cannot prefix scan with {0:?}")]
-                                    #[diagnostic(code(eval::bad_string_range_scan))]
-                                    #[diagnostic(help("A string argument is required"))]
-                                    struct StrRangeScanError(DataValue, #[label] SourceSpan);
-
-                                    StrRangeScanError(val.clone(), symb.span)
-                                })?;
-                                let lower = DataValue::from(s);
-                                // let lower = DataValue::Str(s.to_string());
-                                let mut upper = SmartString::from(s);
-                                // let mut upper = s.to_string();
-                                upper.push(LARGEST_UTF_CHAR);
-                                let upper = DataValue::Str(upper);
-                                return Ok(ValueRange::new(lower, upper));
+                    n if n == OP_STARTS_WITH.name => {
+                        if let Some(symb) = args[0].get_binding() {
+                            if let Some(val) = args[1].get_const() {
+                                if target == symb {
+                                    let s = val.get_str().ok_or_else(|| {
+                                        #[derive(Debug, Error, Diagnostic)]
+                                        #[error("Cannot prefix scan with {0:?}")]
+                                        #[diagnostic(code(eval::bad_string_range_scan))]
+                                        #[diagnostic(help("A string argument is required"))]
+                                        struct StrRangeScanError(DataValue, #[label] SourceSpan);
+
+                                        StrRangeScanError(val.clone(), symb.span)
+                                    })?;
+                                    let lower = DataValue::from(s);
+                                    // let lower = DataValue::Str(s.to_string());
+                                    let mut upper = SmartString::from(s);
+                                    // let mut upper = s.to_string();
+                                    upper.push(LARGEST_UTF_CHAR);
+                                    let upper = DataValue::Str(upper);
+                                    return Ok(ValueRange::new(lower, upper));
+                                }
                             }
                         }
+                        ValueRange::default()
                     }
-                    ValueRange::default()
+                    _ => ValueRange::default(),
                 }
-                _ => ValueRange::default(),
-            },
+            }
         })
     }
     pub(crate) fn to_var_list(&self) -> Result<Vec<SmartString<LazyCompact>>> {
@@ -589,6 +2216,13 @@ impl Expr {
     }
 }
 
+/// The constant side of a comparison, if it can bound a range of stored keys. Keys are
+/// stored in codepoint order, so strings compared under another collation cannot.
+fn range_bound_const(expr: &Expr) -> Option<&DataValue> {
+    expr.get_const()
+        .filter(|val| val.get_str().is_none() || current_collation() == Collation::Codepoint)
+}
+
 pub(crate) fn compute_bounds(
     filters: &[Expr],
     symbols: &[Symbol],
@@ -661,18 +2295,112 @@ pub struct Op {
     pub(crate) name: &'static str,
     pub(crate) min_arity: usize,
     pub(crate) vararg: bool,
-    pub(crate) inner: fn(&[DataValue]) -> Result<DataValue>,
+    pub(crate) side_effect: bool,
+    pub(crate) inner: OpImpl,
 }
 
-/// Used as `Arc<dyn CustomOp>`
+/// How an op is evaluated
+#[derive(Clone, Copy)]
+pub(crate) enum OpImpl {
+    Builtin(fn(&[DataValue]) -> Result<DataValue>),
+    /// Index into the registry of custom ops
+    Custom(usize),
+}
+
+/// A user-defined scalar function, made available to queries by [register_custom_op]
 pub trait CustomOp {
+    /// The name used to call the function, consisting of lowercase letters, digits and underscores
     fn name(&self) -> &'static str;
+    /// The minimum number of arguments, or the exact number if not `vararg`
     fn min_arity(&self) -> usize;
+    /// Whether the function accepts more than `min_arity` arguments
     fn vararg(&self) -> bool;
+    /// The type of the returned values
     fn return_type(&self) -> NullableColType;
+    /// Functions with side effects are never constant-folded
+    fn has_side_effect(&self) -> bool {
+        false
+    }
+    /// Evaluates the function. The number of arguments has already been checked.
     fn call(&self, args: &[DataValue]) -> Result<DataValue>;
 }
 
+/// The maximum number of custom ops that can be registered in a process
+const MAX_CUSTOM_OPS: usize = 1024;
+
+/// Expressions refer to ops as `&'static Op`, so the descriptors of custom ops are kept in
+/// static slots, filled in once on registration. The implementations are owned by the registry.
+struct CustomOpSlot {
+    name: OnceLock<String>,
+    op: OnceLock<Op>,
+}
+
+static CUSTOM_OP_SLOTS: [CustomOpSlot; MAX_CUSTOM_OPS] = [const {
+    CustomOpSlot {
+        name: OnceLock::new(),
+        op: OnceLock::new(),
+    }
+}; MAX_CUSTOM_OPS];
+
+#[derive(Default)]
+struct CustomOpRegistry {
+    by_name: BTreeMap<&'static str, usize>,
+    ops: Vec<Arc<dyn CustomOp + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref CUSTOM_OPS: RwLock<CustomOpRegistry> = Default::default();
+}
+
+/// Functions handled by the parser itself, which a custom op of the same name could never replace
+const SPECIAL_FORMS: &[&str] = &["cond", "if", "coalesce", "and", "or"];
+
+/// Registers a user-defined function for all databases in the process.
+/// Registrations cannot be overridden or removed, and built-in functions cannot be shadowed.
+pub fn register_custom_op(op: impl CustomOp + Send + Sync + 'static) -> Result<()> {
+    let name = op.name();
+    ensure!(
+        name.starts_with(|c: char| c.is_ascii_lowercase())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+        "invalid name for custom function: '{}'",
+        name
+    );
+    ensure!(
+        !SPECIAL_FORMS.contains(&name) && HigherOrderOp::from_name(name).is_none(),
+        "'{}' is a reserved name and cannot be used for a custom function",
+        name
+    );
+    let mut registry = CUSTOM_OPS.write().unwrap();
+    ensure!(
+        get_builtin_op(name).is_none() && !registry.by_name.contains_key(name),
+        "function '{}' already exists",
+        name
+    );
+    let idx = registry.ops.len();
+    ensure!(
+        idx < MAX_CUSTOM_OPS,
+        "cannot register more than {} custom functions",
+        MAX_CUSTOM_OPS
+    );
+    // slots are only filled in here, under the write lock, so both are still empty
+    let slot = &CUSTOM_OP_SLOTS[idx];
+    let op_name = slot
+        .name
+        .get_or_init(|| format!("OP_{}", name.to_ascii_uppercase()));
+    slot.op.get_or_init(|| Op {
+        name: op_name,
+        min_arity: op.min_arity(),
+        vararg: op.vararg(),
+        side_effect: op.has_side_effect(),
+        inner: OpImpl::Custom(idx),
+    });
+    registry.by_name.insert(name, idx);
+    registry.ops.push(Arc::new(op));
+    Ok(())
+}
+
 impl serde::Serialize for &'_ Op {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -704,8 +2432,128 @@ impl<'de> Visitor<'de> for OpVisitor {
     where
         E: Error,
     {
-        let name = v.strip_prefix("OP_").unwrap().to_ascii_lowercase();
-        get_op(&name).ok_or_else(|| E::custom(format!("op not found in serialized data: {v}")))
+        v.strip_prefix("OP_")
+            .and_then(|name| get_op(&name.to_ascii_lowercase()))
+            .ok_or_else(|| E::custom(format!("op not found in serialized data: {v}")))
+    }
+}
+
+/// The number of arguments an op accepts
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub(crate) fn accepts(&self, n: usize) -> bool {
+        match self {
+            Arity::Exact(m) => n == *m,
+            Arity::AtLeast(m) => n >= *m,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "exactly {n}"),
+            Arity::AtLeast(n) => write!(f, "at least {n}"),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Wrong number of arguments for function '{0}'")]
+#[diagnostic(code(eval::arity_mismatch))]
+struct ArityMismatchError(String, #[label] SourceSpan, #[help] String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'{0}' produced the invalid float {1} in strict float mode")]
+#[diagnostic(code(eval::invalid_float))]
+struct InvalidFloatError(String, f64, #[label] SourceSpan);
+
+thread_local! {
+    pub(crate) static STRICT_FLOATS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` in strict float mode: any function producing a float that is NaN or infinite from
+/// arguments that are not is an error, instead of returning the float. This applies to
+/// expressions evaluated within `f`, including by the queries run in it.
+pub fn with_strict_floats<R>(f: impl FnOnce() -> R) -> R {
+    let _strict = ModeGuard::set(&STRICT_FLOATS, true);
+    f()
+}
+
+impl Op {
+    pub(crate) fn arity(&self) -> Arity {
+        if self.vararg {
+            Arity::AtLeast(self.min_arity)
+        } else {
+            Arity::Exact(self.min_arity)
+        }
+    }
+    pub(crate) fn check_arity(&self, n: usize, span: SourceSpan) -> Result<()> {
+        let arity = self.arity();
+        ensure!(
+            arity.accepts(n),
+            ArityMismatchError(
+                self.name.strip_prefix("OP_").unwrap().to_lowercase(),
+                span,
+                format!("Need {arity} argument(s), got {n}")
+            )
+        );
+        Ok(())
+    }
+    /// Calls the op on `args`, with errors pointing at `span`
+    pub(crate) fn call(&self, args: &[DataValue], span: SourceSpan) -> Result<DataValue> {
+        let res = self
+            .apply(args)
+            .map_err(|err| EvalRaisedError(span, err.to_string()))?;
+        match res {
+            // the mode is only looked up for the rare invalid results
+            DataValue::Num(Num::Float(f)) if !f.is_finite() && STRICT_FLOATS.with(Cell::get) => {
+                let produced = !args
+                    .iter()
+                    .any(|arg| matches!(arg, DataValue::Num(Num::Float(a)) if !a.is_finite()));
+                ensure!(
+                    !produced,
+                    InvalidFloatError(
+                        self.name.strip_prefix("OP_").unwrap().to_lowercase(),
+                        f,
+                        span
+                    )
+                );
+                Ok(res)
+            }
+            res => Ok(res),
+        }
+    }
+    /// Calls the op on `args`, without the checks of strict float mode
+    pub(crate) fn apply(&self, args: &[DataValue]) -> Result<DataValue> {
+        // `Apply` can be constructed directly, bypassing the checks in the parser
+        ensure!(
+            self.arity().accepts(args.len()),
+            "'{}' requires {} argument(s), got {}",
+            self.name.strip_prefix("OP_").unwrap().to_lowercase(),
+            self.arity(),
+            args.len()
+        );
+        match self.inner {
+            OpImpl::Builtin(f) => f(args),
+            OpImpl::Custom(idx) => {
+                // release the lock before calling, so that the function may itself evaluate ops
+                let custom = CUSTOM_OPS.read().unwrap().ops.get(idx).cloned();
+                match custom {
+                    Some(custom) => custom.call(args),
+                    None => bail!("custom function '{}' is not registered", self.name),
+                }
+            }
+        }
+    }
+    /// Ops with side effects, e.g. random number generation, must not be constant-folded
+    pub(crate) fn has_side_effect(&self) -> bool {
+        self.side_effect
     }
 }
 
@@ -724,137 +2572,211 @@ impl Debug for Op {
 }
 
 pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
-    Some(match name {
-        "coalesce" => &OP_COALESCE,
-        "list" => &OP_LIST,
-        "add" => &OP_ADD,
-        "sub" => &OP_SUB,
-        "mul" => &OP_MUL,
-        "div" => &OP_DIV,
-        "minus" => &OP_MINUS,
-        "abs" => &OP_ABS,
-        "signum" => &OP_SIGNUM,
-        "floor" => &OP_FLOOR,
-        "ceil" => &OP_CEIL,
-        "round" => &OP_ROUND,
-        "mod" => &OP_MOD,
-        "max" => &OP_MAX,
-        "min" => &OP_MIN,
-        "pow" => &OP_POW,
-        "sqrt" => &OP_SQRT,
-        "exp" => &OP_EXP,
-        "exp2" => &OP_EXP2,
-        "ln" => &OP_LN,
-        "log2" => &OP_LOG2,
-        "log10" => &OP_LOG10,
-        "sin" => &OP_SIN,
-        "cos" => &OP_COS,
-        "tan" => &OP_TAN,
-        "asin" => &OP_ASIN,
-        "acos" => &OP_ACOS,
-        "atan" => &OP_ATAN,
-        "atan2" => &OP_ATAN2,
-        "sinh" => &OP_SINH,
-        "cosh" => &OP_COSH,
-        "tanh" => &OP_TANH,
-        "asinh" => &OP_ASINH,
-        "acosh" => &OP_ACOSH,
-        "atanh" => &OP_ATANH,
-        "eq" => &OP_EQ,
-        "neq" => &OP_NEQ,
-        "gt" => &OP_GT,
-        "ge" => &OP_GE,
-        "lt" => &OP_LT,
-        "le" => &OP_LE,
-        "or" => &OP_OR,
-        "and" => &OP_AND,
-        "negate" => &OP_NEGATE,
-        "bit_and" => &OP_BIT_AND,
-        "bit_or" => &OP_BIT_OR,
-        "bit_not" => &OP_BIT_NOT,
-        "bit_xor" => &OP_BIT_XOR,
-        "pack_bits" => &OP_PACK_BITS,
-        "unpack_bits" => &OP_UNPACK_BITS,
-        "concat" => &OP_CONCAT,
-        "str_includes" => &OP_STR_INCLUDES,
-        "lowercase" => &OP_LOWERCASE,
-        "uppercase" => &OP_UPPERCASE,
-        "trim" => &OP_TRIM,
-        "trim_start" => &OP_TRIM_START,
-        "trim_end" => &OP_TRIM_END,
-        "starts_with" => &OP_STARTS_WITH,
-        "ends_with" => &OP_ENDS_WITH,
-        "is_null" => &OP_IS_NULL,
-        "is_int" => &OP_IS_INT,
-        "is_float" => &OP_IS_FLOAT,
-        "is_num" => &OP_IS_NUM,
-        "is_string" => &OP_IS_STRING,
-        "is_list" => &OP_IS_LIST,
-        "is_bytes" => &OP_IS_BYTES,
-        "is_in" => &OP_IS_IN,
-        "is_finite" => &OP_IS_FINITE,
-        "is_infinite" => &OP_IS_INFINITE,
-        "is_nan" => &OP_IS_NAN,
-        "is_uuid" => &OP_IS_UUID,
-        "is_vec" => &OP_IS_VEC,
-        "length" => &OP_LENGTH,
-        "sorted" => &OP_SORTED,
-        "reverse" => &OP_REVERSE,
-        "append" => &OP_APPEND,
-        "prepend" => &OP_PREPEND,
-        "unicode_normalize" => &OP_UNICODE_NORMALIZE,
-        "haversine" => &OP_HAVERSINE,
-        "haversine_deg_input" => &OP_HAVERSINE_DEG_INPUT,
-        "deg_to_rad" => &OP_DEG_TO_RAD,
-        "rad_to_deg" => &OP_RAD_TO_DEG,
-        "get" => &OP_GET,
-        "maybe_get" => &OP_MAYBE_GET,
-        "chars" => &OP_CHARS,
-        "from_substrings" => &OP_FROM_SUBSTRINGS,
-        "slice" => &OP_SLICE,
-        "regex_matches" => &OP_REGEX_MATCHES,
-        "regex_replace" => &OP_REGEX_REPLACE,
-        "regex_replace_all" => &OP_REGEX_REPLACE_ALL,
-        "regex_extract" => &OP_REGEX_EXTRACT,
-        "regex_extract_first" => &OP_REGEX_EXTRACT_FIRST,
-        "encode_base64" => &OP_ENCODE_BASE64,
-        "decode_base64" => &OP_DECODE_BASE64,
-        "first" => &OP_FIRST,
-        "last" => &OP_LAST,
-        "chunks" => &OP_CHUNKS,
-        "chunks_exact" => &OP_CHUNKS_EXACT,
-        "windows" => &OP_WINDOWS,
-        "to_int" => &OP_TO_INT,
-        "to_float" => &OP_TO_FLOAT,
-        "to_string" => &OP_TO_STRING,
-        "l2_dist" => &OP_L2_DIST,
-        "l2_normalize" => &OP_L2_NORMALIZE,
-        "ip_dist" => &OP_IP_DIST,
-        "cos_dist" => &OP_COS_DIST,
-        "int_range" => &OP_INT_RANGE,
-        "rand_float" => &OP_RAND_FLOAT,
-        "rand_bernoulli" => &OP_RAND_BERNOULLI,
-        "rand_int" => &OP_RAND_INT,
-        "rand_choose" => &OP_RAND_CHOOSE,
-        "assert" => &OP_ASSERT,
-        "union" => &OP_UNION,
-        "intersection" => &OP_INTERSECTION,
-        "difference" => &OP_DIFFERENCE,
-        "to_uuid" => &OP_TO_UUID,
-        "to_bool" => &OP_TO_BOOL,
-        "to_unity" => &OP_TO_UNITY,
-        "rand_uuid_v1" => &OP_RAND_UUID_V1,
-        "rand_uuid_v4" => &OP_RAND_UUID_V4,
-        "uuid_timestamp" => &OP_UUID_TIMESTAMP,
-        "now" => &OP_NOW,
-        "format_timestamp" => &OP_FORMAT_TIMESTAMP,
-        "parse_timestamp" => &OP_PARSE_TIMESTAMP,
-        "vec" => &OP_VEC,
-        "rand_vec" => &OP_RAND_VEC,
-        _ => return None,
+    get_builtin_op(name).or_else(|| {
+        let idx = *CUSTOM_OPS.read().unwrap().by_name.get(name)?;
+        CUSTOM_OP_SLOTS[idx].op.get()
     })
 }
 
+fn get_builtin_op(name: &str) -> Option<&'static Op> {
+    BUILTIN_OPS_BY_NAME.get(name).copied()
+}
+
+/// A built-in function, as listed by [all_builtin_ops]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpInfo {
+    /// The name used to call the function in queries
+    pub name: &'static str,
+    /// The minimum number of arguments, or the exact number if not `vararg`
+    pub min_arity: usize,
+    /// Whether the function accepts more than `min_arity` arguments
+    pub vararg: bool,
+    /// Whether the function has side effects, e.g. generating random numbers
+    pub side_effect: bool,
+}
+
+/// All built-in functions, including aliases, in no particular order
+pub fn all_builtin_ops() -> Vec<OpInfo> {
+    BUILTIN_OPS
+        .iter()
+        .map(|(name, op)| OpInfo {
+            name,
+            min_arity: op.min_arity,
+            vararg: op.vararg,
+            side_effect: op.side_effect,
+        })
+        .collect()
+}
+
+lazy_static! {
+    static ref BUILTIN_OPS_BY_NAME: BTreeMap<&'static str, &'static Op> =
+        BUILTIN_OPS.iter().copied().collect();
+}
+
+static BUILTIN_OPS: &[(&str, &Op)] = &[
+    ("coalesce", &OP_COALESCE),
+    ("coalesce_empty", &OP_COALESCE_EMPTY),
+    ("null_if_empty", &OP_NULL_IF_EMPTY),
+    ("list", &OP_LIST),
+    ("add", &OP_ADD),
+    ("sub", &OP_SUB),
+    ("mul", &OP_MUL),
+    ("div", &OP_DIV),
+    ("int_div", &OP_INT_DIV),
+    ("minus", &OP_MINUS),
+    ("abs", &OP_ABS),
+    ("signum", &OP_SIGNUM),
+    ("floor", &OP_FLOOR),
+    ("ceil", &OP_CEIL),
+    ("round", &OP_ROUND),
+    ("round_sig", &OP_ROUND_SIG),
+    ("mod", &OP_MOD),
+    ("mod_floor", &OP_MOD_FLOOR),
+    ("gcd", &OP_GCD),
+    ("lcm", &OP_LCM),
+    ("bucket", &OP_BUCKET),
+    ("max", &OP_MAX),
+    ("min", &OP_MIN),
+    ("pow", &OP_POW),
+    ("nth_root", &OP_NTH_ROOT),
+    ("sqrt", &OP_SQRT),
+    ("exp", &OP_EXP),
+    ("exp2", &OP_EXP2),
+    ("ln", &OP_LN),
+    ("log2", &OP_LOG2),
+    ("log10", &OP_LOG10),
+    ("sin", &OP_SIN),
+    ("cos", &OP_COS),
+    ("tan", &OP_TAN),
+    ("asin", &OP_ASIN),
+    ("acos", &OP_ACOS),
+    ("atan", &OP_ATAN),
+    ("atan2", &OP_ATAN2),
+    ("sinh", &OP_SINH),
+    ("cosh", &OP_COSH),
+    ("tanh", &OP_TANH),
+    ("asinh", &OP_ASINH),
+    ("acosh", &OP_ACOSH),
+    ("atanh", &OP_ATANH),
+    ("eq", &OP_EQ),
+    ("neq", &OP_NEQ),
+    ("approx_eq", &OP_APPROX_EQ),
+    ("deep_eq", &OP_DEEP_EQ),
+    ("gt", &OP_GT),
+    ("ge", &OP_GE),
+    ("lt", &OP_LT),
+    ("le", &OP_LE),
+    ("between", &OP_BETWEEN),
+    ("or", &OP_OR),
+    ("and", &OP_AND),
+    ("xor", &OP_XOR),
+    ("negate", &OP_NEGATE),
+    ("bit_and", &OP_BIT_AND),
+    ("bit_or", &OP_BIT_OR),
+    ("bit_not", &OP_BIT_NOT),
+    ("bit_xor", &OP_BIT_XOR),
+    ("popcount", &OP_POPCOUNT),
+    ("leading_zeros", &OP_LEADING_ZEROS),
+    ("trailing_zeros", &OP_TRAILING_ZEROS),
+    ("pack_bits", &OP_PACK_BITS),
+    ("unpack_bits", &OP_UNPACK_BITS),
+    ("concat", &OP_CONCAT),
+    ("str_includes", &OP_STR_INCLUDES),
+    ("index_of", &OP_INDEX_OF),
+    ("byte_slice", &OP_BYTE_SLICE),
+    ("lowercase", &OP_LOWERCASE),
+    ("uppercase", &OP_UPPERCASE),
+    ("trim", &OP_TRIM),
+    ("trim_start", &OP_TRIM_START),
+    ("trim_end", &OP_TRIM_END),
+    ("edit_distance", &OP_EDIT_DISTANCE),
+    ("starts_with", &OP_STARTS_WITH),
+    ("ends_with", &OP_ENDS_WITH),
+    ("is_null", &OP_IS_NULL),
+    ("is_int", &OP_IS_INT),
+    ("is_float", &OP_IS_FLOAT),
+    ("is_num", &OP_IS_NUM),
+    ("is_string", &OP_IS_STRING),
+    ("is_list", &OP_IS_LIST),
+    ("is_bytes", &OP_IS_BYTES),
+    ("is_in", &OP_IS_IN),
+    ("is_finite", &OP_IS_FINITE),
+    ("is_infinite", &OP_IS_INFINITE),
+    ("is_nan", &OP_IS_NAN),
+    ("is_uuid", &OP_IS_UUID),
+    ("is_vec", &OP_IS_VEC),
+    ("length", &OP_LENGTH),
+    ("sorted", &OP_SORTED),
+    ("reverse", &OP_REVERSE),
+    ("append", &OP_APPEND),
+    ("prepend", &OP_PREPEND),
+    ("unicode_normalize", &OP_UNICODE_NORMALIZE),
+    ("clean_text", &OP_CLEAN_TEXT),
+    ("haversine", &OP_HAVERSINE),
+    ("haversine_deg_input", &OP_HAVERSINE_DEG_INPUT),
+    ("deg_to_rad", &OP_DEG_TO_RAD),
+    ("rad_to_deg", &OP_RAD_TO_DEG),
+    ("get", &OP_GET),
+    ("maybe_get", &OP_MAYBE_GET),
+    ("has_key", &OP_HAS_KEY),
+    ("value_in", &OP_VALUE_IN),
+    ("project", &OP_PROJECT),
+    ("omit", &OP_OMIT),
+    ("get_path", &OP_GET_PATH),
+    ("chars", &OP_CHARS),
+    ("from_substrings", &OP_FROM_SUBSTRINGS),
+    ("slice", &OP_SLICE),
+    ("reduce", &OP_REDUCE),
+    ("regex_matches", &OP_REGEX_MATCHES),
+    ("regex_replace", &OP_REGEX_REPLACE),
+    ("regex_replace_all", &OP_REGEX_REPLACE_ALL),
+    ("regex_extract", &OP_REGEX_EXTRACT),
+    ("regex_extract_first", &OP_REGEX_EXTRACT_FIRST),
+    ("encode_base64", &OP_ENCODE_BASE64),
+    ("decode_base64", &OP_DECODE_BASE64),
+    ("first", &OP_FIRST),
+    ("last", &OP_LAST),
+    ("chunks", &OP_CHUNKS),
+    ("chunks_exact", &OP_CHUNKS_EXACT),
+    ("windows", &OP_WINDOWS),
+    ("zip", &OP_ZIP),
+    ("to_int", &OP_TO_INT),
+    ("to_float", &OP_TO_FLOAT),
+    ("parse_int", &OP_PARSE_INT),
+    ("parse_float", &OP_PARSE_FLOAT),
+    ("to_string", &OP_TO_STRING),
+    ("to_json", &OP_TO_JSON),
+    ("from_json", &OP_FROM_JSON),
+    ("format", &OP_FORMAT),
+    ("hash", &OP_HASH),
+    ("l2_dist", &OP_L2_DIST),
+    ("l2_normalize", &OP_L2_NORMALIZE),
+    ("ip_dist", &OP_IP_DIST),
+    ("cos_dist", &OP_COS_DIST),
+    ("int_range", &OP_INT_RANGE),
+    ("rand_float", &OP_RAND_FLOAT),
+    ("random", &OP_RAND_FLOAT),
+    ("rand_bernoulli", &OP_RAND_BERNOULLI),
+    ("rand_int", &OP_RAND_INT),
+    ("random_int", &OP_RAND_INT),
+    ("rand_choose", &OP_RAND_CHOOSE),
+    ("assert", &OP_ASSERT),
+    ("union", &OP_UNION),
+    ("intersection", &OP_INTERSECTION),
+    ("difference", &OP_DIFFERENCE),
+    ("to_uuid", &OP_TO_UUID),
+    ("to_bool", &OP_TO_BOOL),
+    ("to_unity", &OP_TO_UNITY),
+    ("rand_uuid_v1", &OP_RAND_UUID_V1),
+    ("rand_uuid_v4", &OP_RAND_UUID_V4),
+    ("uuid_timestamp", &OP_UUID_TIMESTAMP),
+    ("now", &OP_NOW),
+    ("format_timestamp", &OP_FORMAT_TIMESTAMP),
+    ("parse_timestamp", &OP_PARSE_TIMESTAMP),
+    ("vec", &OP_VEC),
+    ("rand_vec", &OP_RAND_VEC),
+];
+
 impl Op {
     pub(crate) fn post_process_args(&self, args: &mut [Expr]) {
         if self.name.starts_with("OP_REGEX_") {
@@ -0,0 +1,137 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::{bail, ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::expr::{get_op, Expr, HigherOrderOp, Lambda};
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::parse::expr::lower_coalesce;
+use crate::parse::SourceSpan;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Named function '{0}' not found")]
+#[diagnostic(code(build::func_not_found))]
+struct FuncNotFoundError(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Wrong number of arguments for function '{0}'")]
+#[diagnostic(code(build::func_wrong_num_args))]
+struct WrongNumArgsError(String, #[help] String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'{0}' requires at least one clause")]
+#[diagnostic(code(build::no_clauses))]
+struct NoClausesError(&'static str);
+
+/// Builds expressions with the same checks the parser performs, so that the resulting
+/// trees can always be evaluated. Expressions are built without source spans.
+pub struct ExprBuilder;
+
+impl ExprBuilder {
+    /// A constant value
+    pub fn constant(val: impl Into<DataValue>) -> Expr {
+        Expr::Const {
+            val: val.into(),
+            span: SourceSpan(0, 0),
+        }
+    }
+    /// A reference to a variable
+    pub fn var(name: &str) -> Expr {
+        Expr::Binding {
+            var: Symbol::new(name, SourceSpan(0, 0)),
+            tuple_pos: None,
+        }
+    }
+    /// Applies the built-in or custom function `name`, checking the number of arguments
+    pub fn apply(name: &str, mut args: Vec<Expr>) -> Result<Expr> {
+        let op = get_op(name).ok_or_else(|| FuncNotFoundError(name.to_string()))?;
+        let arity = op.arity();
+        ensure!(
+            arity.accepts(args.len()),
+            WrongNumArgsError(
+                name.to_string(),
+                format!("Need {arity} argument(s), got {}", args.len())
+            )
+        );
+        op.post_process_args(&mut args);
+        Ok(Expr::Apply {
+            op,
+            args: args.into(),
+            span: SourceSpan(0, 0),
+        })
+    }
+    /// The value of the first clause whose condition is true, or null if there is none
+    pub fn cond(mut clauses: Vec<(Expr, Expr)>) -> Result<Expr> {
+        ensure!(!clauses.is_empty(), NoClausesError("cond"));
+        if !matches!(
+            clauses.last(),
+            Some((
+                Expr::Const {
+                    val: DataValue::Bool(true),
+                    ..
+                },
+                _
+            ))
+        ) {
+            clauses.push((Self::constant(true), Self::constant(DataValue::Null)));
+        }
+        Ok(Expr::Cond {
+            clauses,
+            span: SourceSpan(0, 0),
+        })
+    }
+    /// The value of the first clause that evaluates without error
+    pub fn try_clauses(clauses: Vec<Expr>) -> Result<Expr> {
+        ensure!(!clauses.is_empty(), NoClausesError("try"));
+        Ok(Expr::Try {
+            clauses,
+            span: SourceSpan(0, 0),
+        })
+    }
+    /// Short-circuiting conjunction
+    pub fn and(args: Vec<Expr>) -> Expr {
+        Expr::build_and(args, SourceSpan(0, 0))
+    }
+    /// Short-circuiting disjunction
+    pub fn or(args: Vec<Expr>) -> Expr {
+        Expr::build_or(args, SourceSpan(0, 0))
+    }
+    /// The value of the first argument that is not null
+    pub fn coalesce(args: Vec<Expr>) -> Expr {
+        lower_coalesce(args, SourceSpan(0, 0))
+    }
+    /// Binds `var` to the value in `body`
+    pub fn let_in(var: &str, value: Expr, body: Expr) -> Expr {
+        Expr::Let {
+            var: Symbol::new(var, SourceSpan(0, 0)),
+            value: Box::new(value),
+            body: Box::new(body),
+            slot: None,
+            span: SourceSpan(0, 0),
+        }
+    }
+    /// Applies the lambda `var -> body` to each element of `list`, for the higher-order
+    /// function `name`, e.g. `map` or `filter`
+    pub fn higher_order(name: &str, list: Expr, var: &str, body: Expr) -> Result<Expr> {
+        let Some(op) = HigherOrderOp::from_name(name) else {
+            bail!(FuncNotFoundError(name.to_string()))
+        };
+        Ok(Expr::HigherOrder {
+            op,
+            list: Box::new(list),
+            lambda: Lambda {
+                var: Symbol::new(var, SourceSpan(0, 0)),
+                body: Box::new(body),
+                slot: None,
+            },
+            span: SourceSpan(0, 0),
+        })
+    }
+}
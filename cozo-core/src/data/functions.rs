@@ -6,14 +6,18 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cell::Cell;
 use std::cmp::Reverse;
 use std::collections::BTreeSet;
+use std::iter;
+use std::mem;
 use std::ops::{Div, Rem};
 use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use byteorder::{BigEndian, ByteOrder};
 use chrono::{DateTime, TimeZone, Utc};
 use itertools::Itertools;
 #[cfg(target_arch = "wasm32")]
@@ -21,14 +25,19 @@ use js_sys::Date;
 use miette::{bail, ensure, miette, Result};
 use num_traits::FloatConst;
 use rand::prelude::*;
+use sha2::{Digest, Sha256};
 use smartstring::SmartString;
 use unicode_normalization::UnicodeNormalization;
 use uuid::v1::Timestamp;
 
-use crate::data::expr::Op;
+use crate::data::eval::ModeGuard;
+use crate::data::expr::{get_op, Op, OpImpl};
 use crate::data::json::JsonValue;
+use crate::data::memcmp::MemCmpEncoder;
 use crate::data::relation::VecElementType;
-use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector};
+use crate::data::value::{
+    DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
+};
 
 macro_rules! define_op {
     ($name:ident, $min_arity:expr, $vararg:expr) => {
@@ -36,7 +45,17 @@ macro_rules! define_op {
             name: stringify!($name),
             min_arity: $min_arity,
             vararg: $vararg,
-            inner: ::casey::lower!($name),
+            side_effect: false,
+            inner: OpImpl::Builtin(::casey::lower!($name)),
+        };
+    };
+    ($name:ident, $min_arity:expr, $vararg:expr, side_effect) => {
+        pub(crate) const $name: Op = Op {
+            name: stringify!($name),
+            min_arity: $min_arity,
+            vararg: $vararg,
+            side_effect: true,
+            inner: OpImpl::Builtin(::casey::lower!($name)),
         };
     };
 }
@@ -50,9 +69,13 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
             | (Num(_), Num(_))
             | (Str(_), Str(_))
             | (Bytes(_), Bytes(_))
+            | (Uuid(_), Uuid(_))
             | (Regex(_), Regex(_))
             | (List(_), List(_))
             | (Set(_), Set(_))
+            | (Vec(_), Vec(_))
+            | (Json(_), Json(_))
+            | (Validity(_), Validity(_))
             | (Bot, Bot)
     ) {
         bail!(
@@ -79,6 +102,36 @@ pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Null)
 }
 
+define_op!(OP_COALESCE_EMPTY, 0, true);
+pub(crate) fn op_coalesce_empty(args: &[DataValue]) -> Result<DataValue> {
+    for val in args {
+        match val {
+            DataValue::Null => {}
+            DataValue::Str(s) if s.is_empty() => {}
+            v => return Ok(v.clone()),
+        }
+    }
+    Ok(DataValue::Null)
+}
+
+define_op!(OP_NULL_IF_EMPTY, 1, false);
+/// Null for an empty string, list, set or JSON container, otherwise the value itself
+pub(crate) fn op_null_if_empty(args: &[DataValue]) -> Result<DataValue> {
+    let empty = match &args[0] {
+        DataValue::Str(s) => s.is_empty(),
+        DataValue::List(l) => l.is_empty(),
+        DataValue::Set(s) => s.is_empty(),
+        DataValue::Json(JsonData(JsonValue::Object(o))) => o.is_empty(),
+        DataValue::Json(JsonData(JsonValue::Array(a))) => a.is_empty(),
+        _ => false,
+    };
+    Ok(if empty {
+        DataValue::Null
+    } else {
+        args[0].clone()
+    })
+}
+
 define_op!(OP_EQ, 2, false);
 pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
@@ -88,6 +141,47 @@ pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
+define_op!(OP_DEEP_EQ, 2, false);
+/// Structural equality: an int and a float are equal if they have the same numeric value, also
+/// inside lists and JSON, and JSON objects are equal if they have equal values for the same keys.
+/// Null is equal to null, and values of different types are otherwise never equal.
+pub(crate) fn op_deep_eq(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(deep_eq(&args[0], &args[1])))
+}
+
+fn deep_eq(a: &DataValue, b: &DataValue) -> bool {
+    match (a, b) {
+        (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
+        | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 == *f,
+        (DataValue::List(a), DataValue::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| deep_eq(x, y))
+        }
+        (DataValue::Set(a), DataValue::Set(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| deep_eq(x, y))
+        }
+        (DataValue::Json(a), DataValue::Json(b)) => json_deep_eq(&a.0, &b.0),
+        (a, b) => a == b,
+    }
+}
+
+fn json_deep_eq(a: &JsonValue, b: &JsonValue) -> bool {
+    match (a, b) {
+        (JsonValue::Number(x), JsonValue::Number(y)) => match (x.as_i64(), y.as_i64()) {
+            (Some(x), Some(y)) => x == y,
+            _ => x.as_f64() == y.as_f64(),
+        },
+        (JsonValue::Array(a), JsonValue::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| json_deep_eq(x, y))
+        }
+        (JsonValue::Object(a), JsonValue::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, x)| b.get(k).is_some_and(|y| json_deep_eq(x, y)))
+        }
+        (a, b) => a == b,
+    }
+}
+
 define_op!(OP_IS_UUID, 1, false);
 pub(crate) fn op_is_uuid(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(matches!(args[0], DataValue::Uuid(_))))
@@ -95,11 +189,18 @@ pub(crate) fn op_is_uuid(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_IS_IN, 2, false);
 pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
-    let left = &args[0];
     let right = args[1]
-        .get_slice()
-        .ok_or_else(|| miette!("right hand side of 'is_in' must be a list"))?;
-    Ok(DataValue::from(right.contains(left)))
+        .stream()
+        .ok_or_else(|| miette!("right hand side of 'is_in' must be a list or a set"))?;
+    Ok(DataValue::from(stream_contains(right, &args[0])))
+}
+
+/// Whether `needle` is among the values of `stream`, stopping at the first match
+pub(crate) fn stream_contains<'a>(
+    mut stream: impl Iterator<Item = &'a DataValue>,
+    needle: &DataValue,
+) -> bool {
+    stream.any(|v| v == needle)
 }
 
 define_op!(OP_NEQ, 2, false);
@@ -111,13 +212,96 @@ pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
     }))
 }
 
+const DEFAULT_APPROX_EQ_EPSILON: f64 = 1e-9;
+
+define_op!(OP_APPROX_EQ, 2, true);
+pub(crate) fn op_approx_eq(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(
+        args.len() <= 3,
+        "'approx_eq' requires 2 or 3 arguments, got {}",
+        args.len()
+    );
+    if args.contains(&DataValue::Null) {
+        return Ok(DataValue::Null);
+    }
+    let a = args[0]
+        .get_float()
+        .ok_or_else(|| miette!("'approx_eq' requires numbers"))?;
+    let b = args[1]
+        .get_float()
+        .ok_or_else(|| miette!("'approx_eq' requires numbers"))?;
+    let eps = match args.get(2) {
+        None => DEFAULT_APPROX_EQ_EPSILON,
+        Some(v) => v
+            .get_float()
+            .ok_or_else(|| miette!("'approx_eq' requires a number as epsilon"))?,
+    };
+    ensure!(eps >= 0., "'approx_eq' requires a non-negative epsilon");
+    Ok(DataValue::from((a - b).abs() <= eps))
+}
+
+/// How strings are ordered by the comparison operators `gt`, `ge`, `lt` and `le`.
+/// Equality is not affected: `eq` always compares strings exactly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Collation {
+    /// By unicode code point, the ordering strings have when stored
+    #[default]
+    Codepoint,
+    /// By code point after lowercasing
+    CaseInsensitive,
+    /// By code point after NFKC normalization and lowercasing, so that e.g. full-width
+    /// and ligature forms sort with their plain counterparts
+    Normalized,
+}
+
+impl Collation {
+    /// Compares two strings under this collation
+    pub fn compare(self, a: &str, b: &str) -> std::cmp::Ordering {
+        match self {
+            Collation::Codepoint => a.cmp(b),
+            Collation::CaseInsensitive => a
+                .chars()
+                .flat_map(char::to_lowercase)
+                .cmp(b.chars().flat_map(char::to_lowercase)),
+            Collation::Normalized => a
+                .nfkc()
+                .flat_map(char::to_lowercase)
+                .cmp(b.nfkc().flat_map(char::to_lowercase)),
+        }
+        .then_with(|| a.cmp(b))
+    }
+}
+
+thread_local! {
+    pub(crate) static COLLATION: Cell<Collation> = const { Cell::new(Collation::Codepoint) };
+}
+
+/// Runs `f` with strings compared under `collation`, including in the queries run in it.
+/// Comparisons between constants are folded when expressions are compiled, so compile them
+/// inside `f` as well.
+pub fn with_collation<R>(collation: Collation, f: impl FnOnce() -> R) -> R {
+    let _collation = ModeGuard::set(&COLLATION, collation);
+    f()
+}
+
+pub(crate) fn current_collation() -> Collation {
+    COLLATION.with(Cell::get)
+}
+
+fn collated_cmp(a: &DataValue, b: &DataValue) -> std::cmp::Ordering {
+    match (a, b) {
+        (DataValue::Str(a), DataValue::Str(b)) => COLLATION.with(Cell::get).compare(a, b),
+        (a, b) => a.cmp(b),
+    }
+}
+
 define_op!(OP_GT, 2, false);
 pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l > *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 > *r,
-        (a, b) => a > b,
+        (a, b) => collated_cmp(a, b).is_gt(),
     }))
 }
 
@@ -127,7 +311,7 @@ pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l >= *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 >= *r,
-        (a, b) => a >= b,
+        (a, b) => collated_cmp(a, b).is_ge(),
     }))
 }
 
@@ -137,7 +321,7 @@ pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l < (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) < *r,
-        (a, b) => a < b,
+        (a, b) => collated_cmp(a, b).is_lt(),
     }))
 }
 
@@ -147,12 +331,92 @@ pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l <= (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) <= *r,
-        (a, b) => a <= b,
+        (a, b) => collated_cmp(a, b).is_le(),
     }))
 }
 
+define_op!(OP_BETWEEN, 3, true);
+/// `between(x, lo, hi, bounds)` where `bounds` is one of `'[]'` (the default), `'()'`, `'[)'`
+/// or `'(]'`, selecting which ends of the range are inclusive
+pub(crate) fn op_between(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(
+        args.len() <= 4,
+        "'between' requires 3 or 4 arguments, got {}",
+        args.len()
+    );
+    if args[..3].contains(&DataValue::Null) {
+        return Ok(DataValue::Null);
+    }
+    let (lo_inclusive, hi_inclusive) = match args.get(3) {
+        None => (true, true),
+        Some(DataValue::Str(s)) => match s as &str {
+            "[]" => (true, true),
+            "()" => (false, false),
+            "[)" => (true, false),
+            "(]" => (false, true),
+            _ => bail!("'between' does not recognize bounds {}", s),
+        },
+        Some(v) => bail!("'between' requires a string as bounds, got {:?}", v),
+    };
+    let lower = [args[1].clone(), args[0].clone()];
+    let above_lo = if lo_inclusive {
+        op_le(&lower)?
+    } else {
+        op_lt(&lower)?
+    };
+    let upper = [args[0].clone(), args[2].clone()];
+    let below_hi = if hi_inclusive {
+        op_le(&upper)?
+    } else {
+        op_lt(&upper)?
+    };
+    Ok(DataValue::from(
+        above_lo == DataValue::from(true) && below_hi == DataValue::from(true),
+    ))
+}
+
+/// How arithmetic treats operands mixing integers and floats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumericPromotion {
+    /// Integers are converted to floats
+    #[default]
+    Float,
+    /// Mixing is an error, e.g. to keep floats out of integer columns
+    Error,
+}
+
+thread_local! {
+    pub(crate) static NUMERIC_PROMOTION: Cell<NumericPromotion> = const { Cell::new(NumericPromotion::Float) };
+}
+
+/// Runs `f` with arithmetic on mixed integers and floats following `promotion`, including in
+/// the queries run in it. Constant arithmetic is folded when expressions are compiled, so
+/// compile them inside `f` as well.
+pub fn with_numeric_promotion<R>(promotion: NumericPromotion, f: impl FnOnce() -> R) -> R {
+    let _promotion = ModeGuard::set(&NUMERIC_PROMOTION, promotion);
+    f()
+}
+
+fn check_promotion(args: &[DataValue], name: &str) -> Result<()> {
+    if NUMERIC_PROMOTION.with(Cell::get) == NumericPromotion::Error {
+        let has_int = args
+            .iter()
+            .any(|a| matches!(a, DataValue::Num(Num::Int(_))));
+        let has_float = args
+            .iter()
+            .any(|a| matches!(a, DataValue::Num(Num::Float(_))));
+        ensure!(
+            !(has_int && has_float),
+            "'{}' cannot mix integers and floats, convert them explicitly first",
+            name
+        );
+    }
+    Ok(())
+}
+
 define_op!(OP_ADD, 0, true);
 pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
+    check_promotion(args, "add")?;
     let mut i_accum = 0i64;
     let mut f_accum = 0.0f64;
     for arg in args {
@@ -254,6 +518,7 @@ pub(crate) fn op_min(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_SUB, 2, false);
 pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
+    check_promotion(args, "sub")?;
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Int(*a - *b))
@@ -315,6 +580,7 @@ pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MUL, 0, true);
 pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
+    check_promotion(args, "mul")?;
     let mut i_accum = 1i64;
     let mut f_accum = 1.0f64;
     for arg in args {
@@ -386,6 +652,7 @@ fn mul_vecs(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_DIV, 2, false);
 pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
+    check_promotion(args, "div")?;
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float((*a as f64) / (*b as f64)))
@@ -439,10 +706,31 @@ pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_INT_DIV, 2, false);
+/// Truncating integer division, rounding towards zero.
+pub(crate) fn op_int_div(args: &[DataValue]) -> Result<DataValue> {
+    match (&args[0], &args[1]) {
+        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
+            ensure!(*b != 0, "'int_div' requires a non-zero divisor");
+            Ok(DataValue::from(a.checked_div(*b).ok_or_else(|| {
+                miette!("'int_div' overflowed for {} divided by {}", a, b)
+            })?))
+        }
+        _ => bail!("'int_div' requires integers"),
+    }
+}
+
 define_op!(OP_MINUS, 1, false);
 pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
-        DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(-(*i))),
+        DataValue::Num(Num::Int(i)) => {
+            DataValue::Num(Num::Int(i.checked_neg().ok_or_else(|| {
+                miette!(
+                    "minus overflowed for integer {}, convert it to a float first",
+                    i
+                )
+            })?))
+        }
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(-(*f))),
         DataValue::Vec(Vector::F64(v)) => DataValue::Vec(Vector::F64(0. - v)),
         DataValue::Vec(Vector::F32(v)) => DataValue::Vec(Vector::F32(0. - v)),
@@ -812,6 +1100,8 @@ pub(crate) fn op_sqrt(args: &[DataValue]) -> Result<DataValue> {
 }
 
 define_op!(OP_POW, 2, false);
+/// Integers raised to non-negative integer powers give exact integers, erroring on overflow,
+/// all other combinations give floats
 pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
     let a = match &args[0] {
         DataValue::Num(Num::Int(i)) => *i as f64,
@@ -835,52 +1125,275 @@ pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Num(Num::Float(f)) => *f,
         _ => bail!("'pow' requires numbers"),
     };
+    ensure!(
+        a != 0. || b >= 0.,
+        "'pow' cannot raise zero to a negative power"
+    );
+    if let (DataValue::Num(Num::Int(base)), DataValue::Num(Num::Int(exp))) = (&args[0], &args[1]) {
+        if *exp >= 0 {
+            let exact = match u32::try_from(*exp) {
+                Ok(exp) => base.checked_pow(exp),
+                // only these bases do not overflow for huge exponents
+                Err(_) => match base {
+                    0 | 1 => Some(*base),
+                    -1 => Some(if exp % 2 == 0 { 1 } else { -1 }),
+                    _ => None,
+                },
+            };
+            let exact = exact.ok_or_else(|| {
+                miette!(
+                    "'pow' overflowed for integers {} and {}, convert them to floats first",
+                    base,
+                    exp
+                )
+            })?;
+            return Ok(DataValue::from(exact));
+        }
+    }
     Ok(DataValue::Num(Num::Float(a.powf(b))))
 }
 
+define_op!(OP_ROUND_SIG, 2, false);
+/// Rounds to `sig_figs` significant figures, as opposed to `round`, which rounds to
+/// an integer
+pub(crate) fn op_round_sig(args: &[DataValue]) -> Result<DataValue> {
+    let x = match &args[0] {
+        DataValue::Null => return Ok(DataValue::Null),
+        DataValue::Num(Num::Int(i)) => *i as f64,
+        DataValue::Num(Num::Float(f)) => *f,
+        _ => bail!("'round_sig' requires a number as first argument"),
+    };
+    let sig_figs = match &args[1] {
+        DataValue::Null => return Ok(DataValue::Null),
+        DataValue::Num(Num::Int(n)) if *n > 0 => *n,
+        v => bail!(
+            "'round_sig' requires a positive integer of significant figures, got {:?}",
+            v
+        ),
+    };
+    // an f64 holds at most 17 significant decimal digits
+    if x == 0. || !x.is_finite() || sig_figs >= 17 {
+        return Ok(DataValue::from(x));
+    }
+    let exp = sig_figs as i32 - 1 - x.abs().log10().floor() as i32;
+    // scale by a power of ten that is exactly representable where possible
+    let res = if exp >= 0 {
+        let scale = 10f64.powi(exp);
+        (x * scale).round() / scale
+    } else {
+        let scale = 10f64.powi(-exp);
+        (x / scale).round() * scale
+    };
+    Ok(DataValue::from(res))
+}
+
+define_op!(OP_NTH_ROOT, 2, false);
+/// The real `n`-th root, which for odd `n` is also defined for negative values
+pub(crate) fn op_nth_root(args: &[DataValue]) -> Result<DataValue> {
+    let a = match &args[0] {
+        DataValue::Num(Num::Int(i)) => *i as f64,
+        DataValue::Num(Num::Float(f)) => *f,
+        _ => bail!("'nth_root' requires a number as first argument"),
+    };
+    let n = match &args[1] {
+        DataValue::Num(Num::Int(n)) if *n > 0 => *n,
+        v => bail!(
+            "'nth_root' requires a positive integer as second argument, got {:?}",
+            v
+        ),
+    };
+    let root = |x: f64| match n {
+        2 => x.sqrt(),
+        3 => x.cbrt(),
+        n => x.powf(1. / n as f64),
+    };
+    let res = if a >= 0. {
+        root(a)
+    } else if n % 2 == 1 {
+        -root(-a)
+    } else {
+        bail!(
+            "'nth_root' cannot take the even root {} of negative {}",
+            n,
+            a
+        )
+    };
+    Ok(DataValue::Num(Num::Float(res)))
+}
+
 define_op!(OP_MOD, 2, false);
+/// Truncated modulo: the sign of the result follows the dividend.
 pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
-    Ok(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Int(a.rem(b)))
+    check_promotion(args, "mod")?;
+    let (a, b) = mod_operands(args, "mod")?;
+    Ok(match (a, b) {
+        (Num::Int(a), Num::Int(b)) => DataValue::from(a.wrapping_rem(b)),
+        (a, b) => DataValue::from(a.get_float().rem(b.get_float())),
+    })
+}
+
+define_op!(OP_MOD_FLOOR, 2, false);
+/// Floored modulo: the sign of the result follows the divisor.
+pub(crate) fn op_mod_floor(args: &[DataValue]) -> Result<DataValue> {
+    check_promotion(args, "mod_floor")?;
+    let (a, b) = mod_operands(args, "mod_floor")?;
+    Ok(match (a, b) {
+        (Num::Int(a), Num::Int(b)) => {
+            let r = a.wrapping_rem(b);
+            DataValue::from(if r != 0 && (r < 0) != (b < 0) {
+                r + b
+            } else {
+                r
+            })
         }
-        (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Float(b))) => {
-            DataValue::Num(Num::Float(a.rem(*b)))
+        (a, b) => {
+            let (a, b) = (a.get_float(), b.get_float());
+            let r = a.rem(b);
+            DataValue::from(if r != 0. && (r < 0.) != (b < 0.) {
+                r + b
+            } else {
+                r
+            })
         }
-        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Float(b))) => {
-            DataValue::Num(Num::Float((*a as f64).rem(b)))
+    })
+}
+
+fn mod_operands(args: &[DataValue], name: &str) -> Result<(Num, Num)> {
+    match (&args[0], &args[1]) {
+        (DataValue::Num(a), DataValue::Num(b)) => {
+            ensure!(
+                b.get_float() != 0.,
+                "'{}' requires a non-zero divisor",
+                name
+            );
+            Ok((*a, *b))
         }
-        (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Float(a.rem(*b as f64)))
+        _ => bail!("'{}' requires numbers", name),
+    }
+}
+
+define_op!(OP_BUCKET, 2, false);
+/// The index of the half-open bin `[bounds[i - 1], bounds[i])` containing the value, given
+/// sorted bounds: 0 below the first bound and the number of bounds from the last one on
+pub(crate) fn op_bucket(args: &[DataValue]) -> Result<DataValue> {
+    let x = match &args[0] {
+        DataValue::Null => return Ok(DataValue::Null),
+        DataValue::Num(n) if !n.get_float().is_nan() => n.get_float(),
+        v => bail!("'bucket' requires a number as first argument, got {:?}", v),
+    };
+    let bounds = args[1]
+        .get_slice()
+        .ok_or_else(|| miette!("'bucket' requires a list of bounds"))?
+        .iter()
+        .map(|b| match b {
+            DataValue::Num(n) => Ok(n.get_float()),
+            v => bail!("'bucket' requires numbers as bounds, got {:?}", v),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(
+        bounds.windows(2).all(|w| w[0] <= w[1]),
+        "'bucket' requires sorted bounds"
+    );
+    Ok(DataValue::from(bounds.partition_point(|b| *b <= x) as i64))
+}
+
+define_op!(OP_GCD, 2, false);
+/// The greatest common divisor of the absolute values, with `gcd(0, 0) == 0`
+pub(crate) fn op_gcd(args: &[DataValue]) -> Result<DataValue> {
+    let Some((a, b)) = gcd_operands(args, "gcd")? else {
+        return Ok(DataValue::Null);
+    };
+    let ret = gcd(a, b);
+    Ok(DataValue::from(i64::try_from(ret).map_err(|_| {
+        miette!("'gcd' overflowed, the result {} is too large", ret)
+    })?))
+}
+
+define_op!(OP_LCM, 2, false);
+/// The least common multiple of the absolute values, zero if either is zero
+pub(crate) fn op_lcm(args: &[DataValue]) -> Result<DataValue> {
+    let Some((a, b)) = gcd_operands(args, "lcm")? else {
+        return Ok(DataValue::Null);
+    };
+    if a == 0 || b == 0 {
+        return Ok(DataValue::from(0));
+    }
+    let ret = (a / gcd(a, b))
+        .checked_mul(b)
+        .and_then(|l| i64::try_from(l).ok())
+        .ok_or_else(|| miette!("'lcm' overflowed for {} and {}", args[0], args[1]))?;
+    Ok(DataValue::from(ret))
+}
+
+fn gcd_operands(args: &[DataValue], name: &str) -> Result<Option<(u64, u64)>> {
+    match (&args[0], &args[1]) {
+        (DataValue::Null, _) | (_, DataValue::Null) => Ok(None),
+        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
+            Ok(Some((a.unsigned_abs(), b.unsigned_abs())))
         }
-        _ => bail!("'mod' requires numbers"),
-    })
+        _ => bail!("'{}' requires integers", name),
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
 }
 
 define_op!(OP_AND, 0, true);
+/// Three-valued: false if any argument is false, otherwise null if any argument is null
 pub(crate) fn op_and(args: &[DataValue]) -> Result<DataValue> {
+    let mut ret = DataValue::from(true);
     for arg in args {
-        if !arg
-            .get_bool()
-            .ok_or_else(|| miette!("'and' requires booleans"))?
-        {
-            return Ok(DataValue::from(false));
+        match arg {
+            DataValue::Bool(false) => return Ok(DataValue::from(false)),
+            DataValue::Bool(true) => {}
+            DataValue::Null => ret = DataValue::Null,
+            _ => bail!("'and' requires booleans"),
         }
     }
-    Ok(DataValue::from(true))
+    Ok(ret)
 }
 
 define_op!(OP_OR, 0, true);
+/// Three-valued: true if any argument is true, otherwise null if any argument is null
 pub(crate) fn op_or(args: &[DataValue]) -> Result<DataValue> {
+    let mut ret = DataValue::from(false);
     for arg in args {
-        if arg
-            .get_bool()
-            .ok_or_else(|| miette!("'or' requires booleans"))?
-        {
-            return Ok(DataValue::from(true));
+        match arg {
+            DataValue::Bool(true) => return Ok(DataValue::from(true)),
+            DataValue::Bool(false) => {}
+            DataValue::Null => ret = DataValue::Null,
+            _ => bail!("'or' requires booleans"),
         }
     }
-    Ok(DataValue::from(false))
+    Ok(ret)
+}
+
+define_op!(OP_XOR, 2, false);
+/// Null if either operand is null
+pub(crate) fn op_xor(args: &[DataValue]) -> Result<DataValue> {
+    match (&args[0], &args[1]) {
+        (DataValue::Bool(a), DataValue::Bool(b)) => Ok(DataValue::from(a != b)),
+        (DataValue::Null | DataValue::Bool(_), DataValue::Null | DataValue::Bool(_)) => {
+            Ok(DataValue::Null)
+        }
+        _ => bail!("'xor' requires booleans"),
+    }
+}
+
+define_op!(OP_IMPLIES, 2, false);
+/// `!a || b` in three-valued logic, the consequent is not inspected if the antecedent is false
+pub(crate) fn op_implies(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match (&args[0], &args[1]) {
+        (DataValue::Bool(false), _) => DataValue::from(true),
+        (DataValue::Bool(true), b @ (DataValue::Null | DataValue::Bool(_))) => b.clone(),
+        (DataValue::Null, DataValue::Bool(true)) => DataValue::from(true),
+        (DataValue::Null, DataValue::Null | DataValue::Bool(false)) => DataValue::Null,
+        _ => bail!("'implies' requires booleans"),
+    })
 }
 
 define_op!(OP_NEGATE, 1, false);
@@ -960,6 +1473,61 @@ pub(crate) fn op_bit_xor(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_POPCOUNT, 1, false);
+/// The number of set bits of an integer in two's complement, or of bytes
+pub(crate) fn op_popcount(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Null => Ok(DataValue::Null),
+        DataValue::Num(Num::Int(i)) => Ok(DataValue::from(i.count_ones() as i64)),
+        DataValue::Bytes(bs) => Ok(DataValue::from(
+            bs.iter().map(|b| b.count_ones() as i64).sum::<i64>(),
+        )),
+        _ => bail!("'popcount' requires an integer or bytes"),
+    }
+}
+
+define_op!(OP_LEADING_ZEROS, 1, false);
+/// The number of zero bits above the most significant set bit. For bytes, the first bit
+/// given by `unpack_bits` is the most significant.
+pub(crate) fn op_leading_zeros(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Null => Ok(DataValue::Null),
+        DataValue::Num(Num::Int(i)) => Ok(DataValue::from(i.leading_zeros() as i64)),
+        DataValue::Bytes(bs) => Ok(DataValue::from(count_zero_bits(
+            bs.iter().copied(),
+            u8::leading_zeros,
+        ))),
+        _ => bail!("'leading_zeros' requires an integer or bytes"),
+    }
+}
+
+define_op!(OP_TRAILING_ZEROS, 1, false);
+/// The number of zero bits below the least significant set bit. For bytes, the last bit
+/// given by `unpack_bits` is the least significant.
+pub(crate) fn op_trailing_zeros(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Null => Ok(DataValue::Null),
+        DataValue::Num(Num::Int(i)) => Ok(DataValue::from(i.trailing_zeros() as i64)),
+        DataValue::Bytes(bs) => Ok(DataValue::from(count_zero_bits(
+            bs.iter().rev().copied(),
+            u8::trailing_zeros,
+        ))),
+        _ => bail!("'trailing_zeros' requires an integer or bytes"),
+    }
+}
+
+/// Counts zero bits across `bytes` with `zeros` until a byte has a set bit
+fn count_zero_bits(bytes: impl Iterator<Item = u8>, zeros: fn(u8) -> u32) -> i64 {
+    let mut count = 0;
+    for byte in bytes {
+        count += zeros(byte) as i64;
+        if byte != 0 {
+            break;
+        }
+    }
+    count
+}
+
 define_op!(OP_UNPACK_BITS, 1, false);
 pub(crate) fn op_unpack_bits(args: &[DataValue]) -> Result<DataValue> {
     if let DataValue::Bytes(bs) = &args[0] {
@@ -1058,6 +1626,51 @@ pub(crate) fn op_str_includes(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_INDEX_OF, 2, false);
+/// The character offset of the first occurrence of `needle` in `haystack`, -1 if there is none
+pub(crate) fn op_index_of(args: &[DataValue]) -> Result<DataValue> {
+    match (&args[0], &args[1]) {
+        (DataValue::Null, _) | (_, DataValue::Null) => Ok(DataValue::Null),
+        (DataValue::Str(haystack), DataValue::Str(needle)) => {
+            Ok(DataValue::from(match haystack.find(needle as &str) {
+                Some(byte_idx) => haystack[..byte_idx].chars().count() as i64,
+                None => -1,
+            }))
+        }
+        _ => bail!("'index_of' requires strings"),
+    }
+}
+
+define_op!(OP_BYTE_SLICE, 3, false);
+/// The part of a string between two byte offsets. Offsets are clamped to the string and
+/// moved inward to the nearest character boundaries, so characters are never split.
+pub(crate) fn op_byte_slice(args: &[DataValue]) -> Result<DataValue> {
+    if args.contains(&DataValue::Null) {
+        return Ok(DataValue::Null);
+    }
+    let s = match &args[0] {
+        DataValue::Str(s) => s,
+        _ => bail!("'byte_slice' requires a string as first argument"),
+    };
+    let clamp = |v: &DataValue| match v {
+        DataValue::Num(Num::Int(i)) => Ok((*i).clamp(0, s.len() as i64) as usize),
+        v => bail!("'byte_slice' requires integers as offsets, got {:?}", v),
+    };
+    let mut start = clamp(&args[1])?;
+    let mut end = clamp(&args[2])?;
+    while !s.is_char_boundary(start) {
+        start += 1;
+    }
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    Ok(DataValue::from(if start < end {
+        &s[start..end]
+    } else {
+        ""
+    }))
+}
+
 define_op!(OP_LOWERCASE, 1, false);
 pub(crate) fn op_lowercase(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
@@ -1124,6 +1737,57 @@ pub(crate) fn op_ends_with(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(a.ends_with(b as &str)))
 }
 
+define_op!(OP_EDIT_DISTANCE, 2, true);
+/// The Levenshtein distance between two strings, counted in characters. With a third
+/// argument, distances above it are not computed and the cap is returned instead.
+pub(crate) fn op_edit_distance(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(
+        args.len() <= 3,
+        "'edit_distance' requires 2 or 3 arguments, got {}",
+        args.len()
+    );
+    if args.contains(&DataValue::Null) {
+        return Ok(DataValue::Null);
+    }
+    let (a, b) = match (&args[0], &args[1]) {
+        (DataValue::Str(a), DataValue::Str(b)) => (a, b),
+        _ => bail!("'edit_distance' requires strings"),
+    };
+    let cap = match args.get(2) {
+        None => None,
+        Some(DataValue::Num(Num::Int(n))) if *n >= 0 => Some(*n as usize),
+        Some(v) => bail!(
+            "'edit_distance' requires a non-negative integer as cap, got {:?}",
+            v
+        ),
+    };
+    Ok(DataValue::from(edit_distance(a, b, cap) as i64))
+}
+
+fn edit_distance(a: &str, b: &str, cap: Option<usize>) -> usize {
+    let a = a.chars().collect_vec();
+    let b = b.chars().collect_vec();
+    let cap = cap.unwrap_or(usize::MAX);
+    if a.len().abs_diff(b.len()) > cap {
+        return cap;
+    }
+    let mut prev = (0..=b.len()).collect_vec();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        // the smallest distance in a row never decreases in later rows
+        if cur.iter().all(|d| *d > cap) {
+            return cap;
+        }
+        mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()].min(cap)
+}
+
 define_op!(OP_REGEX, 1, false);
 pub(crate) fn op_regex(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
@@ -1302,14 +1966,22 @@ pub(crate) fn op_is_bytes(args: &[DataValue]) -> Result<DataValue> {
 }
 
 define_op!(OP_LENGTH, 1, false);
+/// The number of elements of lists and JSON arrays, of entries of JSON objects,
+/// and of characters of strings
 pub(crate) fn op_length(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
+        DataValue::Null => return Ok(DataValue::Null),
         DataValue::Set(s) => s.len() as i64,
         DataValue::List(l) => l.len() as i64,
         DataValue::Str(s) => s.chars().count() as i64,
         DataValue::Bytes(b) => b.len() as i64,
         DataValue::Vec(v) => v.len() as i64,
-        _ => bail!("'length' requires lists"),
+        DataValue::Json(JsonData(JsonValue::Array(a))) => a.len() as i64,
+        DataValue::Json(JsonData(JsonValue::Object(o))) => o.len() as i64,
+        v => bail!(
+            "'length' requires a list, a string, bytes, a vector or a JSON array or object, got {:?}",
+            v
+        ),
     }))
 }
 
@@ -1327,6 +1999,43 @@ pub(crate) fn op_unicode_normalize(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_CLEAN_TEXT, 1, true);
+/// Removes control characters, or with mode `'space'` replaces each with a space. The modes
+/// `'strip_nfc'` and `'space_nfc'` additionally normalize the result to NFC.
+pub(crate) fn op_clean_text(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(
+        args.len() <= 2,
+        "'clean_text' requires 1 or 2 arguments, got {}",
+        args.len()
+    );
+    let s = match &args[0] {
+        DataValue::Null => return Ok(DataValue::Null),
+        DataValue::Str(s) => s,
+        _ => bail!("'clean_text' requires strings"),
+    };
+    let (replace, normalize) = match args.get(1) {
+        None => (false, false),
+        Some(DataValue::Str(mode)) => match mode as &str {
+            "strip" => (false, false),
+            "space" => (true, false),
+            "strip_nfc" => (false, true),
+            "space_nfc" => (true, true),
+            m => bail!("unknown mode {} for 'clean_text'", m),
+        },
+        Some(v) => bail!("'clean_text' requires a string as mode, got {:?}", v),
+    };
+    let cleaned = s.chars().filter_map(|c| match c {
+        c if !c.is_control() => Some(c),
+        _ if replace => Some(' '),
+        _ => None,
+    });
+    Ok(DataValue::Str(if normalize {
+        cleaned.nfc().collect()
+    } else {
+        cleaned.collect()
+    }))
+}
+
 define_op!(OP_SORTED, 1, false);
 pub(crate) fn op_sorted(args: &[DataValue]) -> Result<DataValue> {
     let mut arg = args[0]
@@ -1461,7 +2170,27 @@ pub(crate) fn op_windows(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(res))
 }
 
-fn get_index(mut i: i64, total: usize) -> Result<usize> {
+define_op!(OP_ZIP, 2, false);
+/// Pairs up the elements of two lists, stopping at the end of the shorter one
+pub(crate) fn op_zip(args: &[DataValue]) -> Result<DataValue> {
+    if args.contains(&DataValue::Null) {
+        return Ok(DataValue::Null);
+    }
+    let a = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("first argument of 'zip' must be a list"))?;
+    let b = args[1]
+        .get_slice()
+        .ok_or_else(|| miette!("second argument of 'zip' must be a list"))?;
+    let res = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| DataValue::List(vec![x.clone(), y.clone()]))
+        .collect_vec();
+    Ok(DataValue::List(res))
+}
+
+pub(crate) fn get_index(mut i: i64, total: usize) -> Result<usize> {
     if i < 0 {
         i += total as i64;
     }
@@ -1490,20 +2219,241 @@ pub(crate) fn op_get(args: &[DataValue]) -> Result<DataValue> {
 }
 
 define_op!(OP_MAYBE_GET, 2, false);
+/// The safe counterpart of `get`: also looks up keys in JSON objects and indices in JSON arrays,
+/// and gives null instead of an error when the value cannot be indexed that way
 pub(crate) fn op_maybe_get(args: &[DataValue]) -> Result<DataValue> {
-    let l = args[0]
-        .get_slice()
-        .ok_or_else(|| miette!("first argument to 'maybe_get' mut be a list"))?;
-    let n = args[1]
-        .get_int()
-        .ok_or_else(|| miette!("second argument to 'maybe_get' mut be an integer"))?;
-    if let Ok(idx) = get_index(n, l.len()) {
-        Ok(l[idx].clone())
+    ensure!(
+        matches!(args[1], DataValue::Num(Num::Int(_)) | DataValue::Str(_)),
+        "second argument to 'maybe_get' must be an integer or a string"
+    );
+    Ok(match (&args[0], &args[1]) {
+        (DataValue::List(l), DataValue::Num(Num::Int(n))) => match get_index(*n, l.len()) {
+            Ok(idx) => l[idx].clone(),
+            Err(_) => DataValue::Null,
+        },
+        (DataValue::Json(JsonData(JsonValue::Array(a))), DataValue::Num(Num::Int(n))) => {
+            match get_index(*n, a.len()) {
+                Ok(idx) => json_to_value(&a[idx]),
+                Err(_) => DataValue::Null,
+            }
+        }
+        (DataValue::Json(JsonData(JsonValue::Object(o))), DataValue::Str(k)) => o
+            .get(k as &str)
+            .map(json_to_value)
+            .unwrap_or(DataValue::Null),
+        _ => DataValue::Null,
+    })
+}
+
+define_op!(OP_HAS_KEY, 2, false);
+/// Whether the JSON object has the key, even if the key maps to null
+pub(crate) fn op_has_key(args: &[DataValue]) -> Result<DataValue> {
+    let key = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("second argument to 'has_key' must be a string"))?;
+    match &args[0] {
+        DataValue::Null => Ok(DataValue::Null),
+        DataValue::Json(JsonData(JsonValue::Object(o))) => Ok(DataValue::from(o.contains_key(key))),
+        v => bail!(
+            "first argument to 'has_key' must be a JSON object, got {:?}",
+            v
+        ),
+    }
+}
+
+define_op!(OP_VALUE_IN, 2, false);
+/// Whether any value of the JSON object equals the needle. As with SQL's `IN`, the result is
+/// null if the needle is null, or if there is no match but some value is null.
+pub(crate) fn op_value_in(args: &[DataValue]) -> Result<DataValue> {
+    let o = match &args[1] {
+        DataValue::Null => return Ok(DataValue::Null),
+        DataValue::Json(JsonData(JsonValue::Object(o))) => o,
+        v => bail!(
+            "second argument to 'value_in' must be a JSON object, got {:?}",
+            v
+        ),
+    };
+    if args[0] == DataValue::Null {
+        return Ok(DataValue::Null);
+    }
+    let mut seen_null = false;
+    for v in o.values() {
+        if v.is_null() {
+            seen_null = true;
+        } else if json_to_value(v) == args[0] {
+            return Ok(DataValue::from(true));
+        }
+    }
+    Ok(if seen_null {
+        DataValue::Null
     } else {
-        Ok(DataValue::Null)
+        DataValue::from(false)
+    })
+}
+
+define_op!(OP_PROJECT, 2, false);
+/// Keeps only the entries of the JSON object whose keys are listed
+pub(crate) fn op_project(args: &[DataValue]) -> Result<DataValue> {
+    select_keys(args, "project", true)
+}
+
+define_op!(OP_OMIT, 2, false);
+/// Drops the entries of the JSON object whose keys are listed
+pub(crate) fn op_omit(args: &[DataValue]) -> Result<DataValue> {
+    select_keys(args, "omit", false)
+}
+
+fn select_keys(args: &[DataValue], name: &str, keep: bool) -> Result<DataValue> {
+    let keys = args[1]
+        .stream()
+        .ok_or_else(|| miette!("second argument to '{}' must be a list of strings", name))?
+        .map(|k| {
+            k.get_str().ok_or_else(|| {
+                miette!(
+                    "second argument to '{}' must be a list of strings, got key {:?}",
+                    name,
+                    k
+                )
+            })
+        })
+        .collect::<Result<BTreeSet<_>>>()?;
+    match &args[0] {
+        DataValue::Null => Ok(DataValue::Null),
+        DataValue::Json(JsonData(JsonValue::Object(o))) => {
+            let selected = o
+                .iter()
+                .filter(|(k, _)| keys.contains(k.as_str()) == keep)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            Ok(DataValue::Json(JsonData(JsonValue::Object(selected))))
+        }
+        v => bail!(
+            "first argument to '{}' must be a JSON object, got {:?}",
+            name,
+            v
+        ),
+    }
+}
+
+/// JSON arrays and objects stay JSON, scalars become native values
+pub(crate) fn json_to_value(j: &JsonValue) -> DataValue {
+    match j {
+        JsonValue::Array(_) | JsonValue::Object(_) => DataValue::Json(JsonData(j.clone())),
+        j => DataValue::from(j),
     }
 }
 
+enum PathSegment<'a> {
+    Key(&'a str),
+    Index(i64),
+}
+
+/// Parses paths like `a.b[0].c`, the empty path refers to the value itself
+fn parse_path(path: &str) -> Result<Vec<PathSegment<'_>>> {
+    let malformed = || miette!("malformed path {:?} given to 'get_path'", path);
+    let mut segments = vec![];
+    let mut rest = path;
+    let mut key_expected = false;
+    while !rest.is_empty() || key_expected {
+        match rest.strip_prefix('[') {
+            Some(r) if !key_expected => {
+                let end = r.find(']').ok_or_else(malformed)?;
+                let idx = r[..end].trim().parse::<i64>().map_err(|_| malformed())?;
+                segments.push(PathSegment::Index(idx));
+                rest = &r[end + 1..];
+            }
+            _ => {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(malformed());
+                }
+                segments.push(PathSegment::Key(&rest[..end]));
+                rest = &rest[end..];
+            }
+        }
+        key_expected = match rest.strip_prefix('.') {
+            Some(r) => {
+                rest = r;
+                true
+            }
+            None => false,
+        };
+        if !key_expected && !rest.is_empty() && !rest.starts_with('[') {
+            return Err(malformed());
+        }
+    }
+    Ok(segments)
+}
+
+define_op!(OP_GET_PATH, 2, false);
+/// Missing keys, out of bound indices and indexing into anything other than
+/// lists all give null
+pub(crate) fn op_get_path(args: &[DataValue]) -> Result<DataValue> {
+    let path = args[1]
+        .get_str()
+        .ok_or_else(|| miette!("second argument to 'get_path' must be a string"))?;
+    let mut segments = parse_path(path)?.into_iter();
+    let mut val = &args[0];
+    while let Some(segment) = segments.next() {
+        match (val, segment) {
+            (DataValue::List(l), PathSegment::Index(i)) => match get_index(i, l.len()) {
+                Ok(i) => val = &l[i],
+                Err(_) => return Ok(DataValue::Null),
+            },
+            (DataValue::Json(j), segment) => {
+                let mut j = &j.0;
+                for segment in iter::once(segment).chain(segments.by_ref()) {
+                    j = match (j, segment) {
+                        (JsonValue::Array(a), PathSegment::Index(i)) => {
+                            match get_index(i, a.len()) {
+                                Ok(i) => &a[i],
+                                Err(_) => return Ok(DataValue::Null),
+                            }
+                        }
+                        (JsonValue::Object(o), PathSegment::Key(k)) => match o.get(k) {
+                            Some(v) => v,
+                            None => return Ok(DataValue::Null),
+                        },
+                        _ => return Ok(DataValue::Null),
+                    }
+                }
+                return Ok(json_to_value(j));
+            }
+            _ => return Ok(DataValue::Null),
+        }
+    }
+    Ok(val.clone())
+}
+
+define_op!(OP_REDUCE, 3, false);
+/// `reduce(list, initial, name)` folds the list from the left with the named binary function
+/// In strict float mode, invalid floats are reported as produced by `reduce` itself.
+pub(crate) fn op_reduce(args: &[DataValue]) -> Result<DataValue> {
+    let l = args[0]
+        .stream()
+        .ok_or_else(|| miette!("first argument to 'reduce' must be a list or a set"))?;
+    let name = args[2]
+        .get_str()
+        .ok_or_else(|| miette!("third argument to 'reduce' must be a function name"))?;
+    let op = get_op(name).ok_or_else(|| miette!("'reduce' got unknown function '{}'", name))?;
+    ensure!(
+        op.arity().accepts(2),
+        "'reduce' requires a binary function, but '{}' takes {} argument(s)",
+        name,
+        op.arity()
+    );
+    ensure!(
+        !op.has_side_effect(),
+        "'reduce' cannot be used with '{}', which has side effects",
+        name
+    );
+    let mut acc = args[1].clone();
+    for el in l {
+        acc = op.apply(&[acc, el.clone()])?;
+    }
+    Ok(acc)
+}
+
 define_op!(OP_SLICE, 3, false);
 pub(crate) fn op_slice(args: &[DataValue]) -> Result<DataValue> {
     let l = args[0]
@@ -1600,11 +2550,23 @@ pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => !l.is_empty(),
         DataValue::Set(s) => !s.is_empty(),
         DataValue::Vec(_) => true,
+        DataValue::Json(j) => json_to_bool(&j.0),
         DataValue::Validity(vld) => vld.is_assert.0,
         DataValue::Bot => false,
     }))
 }
 
+fn json_to_bool(j: &JsonValue) -> bool {
+    match j {
+        JsonValue::Null => false,
+        JsonValue::Bool(b) => *b,
+        JsonValue::Number(n) => n.as_f64() != Some(0.),
+        JsonValue::String(s) => !s.is_empty(),
+        JsonValue::Array(a) => !a.is_empty(),
+        JsonValue::Object(o) => !o.is_empty(),
+    }
+}
+
 define_op!(OP_TO_UNITY, 1, false);
 pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
@@ -1618,6 +2580,7 @@ pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => i64::from(!l.is_empty()),
         DataValue::Set(s) => i64::from(!s.is_empty()),
         DataValue::Vec(_) => 1,
+        DataValue::Json(j) => i64::from(json_to_bool(&j.0)),
         DataValue::Validity(vld) => i64::from(vld.is_assert.0),
         DataValue::Bot => 0,
     }))
@@ -1666,6 +2629,30 @@ pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_PARSE_INT, 1, false);
+/// Unlike `to_int`, only strings are accepted, surrounding whitespace is ignored
+pub(crate) fn op_parse_int(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        DataValue::Null => DataValue::Null,
+        DataValue::Str(s) => i64::from_str(s.trim())
+            .map_err(|_| miette!("'parse_int' cannot interpret {:?} as int", s))?
+            .into(),
+        _ => bail!("'parse_int' requires strings"),
+    })
+}
+
+define_op!(OP_PARSE_FLOAT, 1, false);
+/// Unlike `to_float`, only strings are accepted, surrounding whitespace is ignored
+pub(crate) fn op_parse_float(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        DataValue::Null => DataValue::Null,
+        DataValue::Str(s) => f64::from_str(s.trim())
+            .map_err(|_| miette!("'parse_float' cannot interpret {:?} as float", s))?
+            .into(),
+        _ => bail!("'parse_float' requires strings"),
+    })
+}
+
 define_op!(OP_TO_STRING, 1, false);
 pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
@@ -1678,6 +2665,91 @@ pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_TO_JSON, 1, false);
+/// Unlike `to_string`, strings are quoted, so that the result can always be read back by `from_json`
+pub(crate) fn op_to_json(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(
+        JsonValue::from(args[0].clone()).to_string(),
+    ))
+}
+
+define_op!(OP_FROM_JSON, 1, false);
+/// JSON arrays become lists and JSON objects are kept as JSON values
+pub(crate) fn op_from_json(args: &[DataValue]) -> Result<DataValue> {
+    fn convert(j: JsonValue) -> DataValue {
+        match j {
+            JsonValue::Array(a) => DataValue::List(a.into_iter().map(convert).collect()),
+            JsonValue::Object(_) => DataValue::Json(JsonData(j)),
+            j => DataValue::from(j),
+        }
+    }
+
+    Ok(match &args[0] {
+        DataValue::Null => DataValue::Null,
+        DataValue::Str(s) => convert(
+            serde_json::from_str(s)
+                .map_err(|e| miette!("'from_json' cannot interpret {:?} as JSON: {}", s, e))?,
+        ),
+        _ => bail!("'from_json' requires strings"),
+    })
+}
+
+define_op!(OP_FORMAT, 1, true);
+/// Each `{}` in the template is replaced by the next argument converted as by `to_string`,
+/// so that null becomes `null`. `{{` and `}}` stand for literal braces.
+pub(crate) fn op_format(args: &[DataValue]) -> Result<DataValue> {
+    let template = args[0]
+        .get_str()
+        .ok_or_else(|| miette!("first argument to 'format' must be a string"))?;
+    let mut values = args[1..].iter();
+    let mut n_placeholders = 0;
+    let mut ret = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                ret.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                n_placeholders += 1;
+                if let Some(val) = values.next() {
+                    match op_to_string(std::slice::from_ref(val))? {
+                        DataValue::Str(s) => ret += &s,
+                        _ => unreachable!(),
+                    }
+                }
+            }
+            ('{' | '}', _) => bail!("unmatched brace in template given to 'format'"),
+            (c, _) => ret.push(c),
+        }
+    }
+    ensure!(
+        n_placeholders == args.len() - 1,
+        "'format' requires as many arguments as placeholders: {} placeholders, {} arguments",
+        n_placeholders,
+        args.len() - 1
+    );
+    Ok(DataValue::from(ret))
+}
+
+define_op!(OP_HASH, 1, false);
+/// Stable across runs and platforms: the leading bytes of the SHA-256 digest of
+/// the memcmp encoding of the value, which is also used for keys in storage.
+/// Keys of JSON objects are always sorted, so insertion order does not matter.
+pub(crate) fn op_hash(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(stable_hash(&args[0]) as i64))
+}
+
+/// The hash computed by `hash`, as unsigned
+pub(crate) fn stable_hash(val: &DataValue) -> u64 {
+    let mut encoded = vec![];
+    encoded.encode_datavalue(val);
+    let digest = Sha256::digest(&encoded);
+    BigEndian::read_u64(&digest[..8])
+}
+
 define_op!(OP_VEC, 1, true);
 pub(crate) fn op_vec(args: &[DataValue]) -> Result<DataValue> {
     let t = match args.get(1) {
@@ -1727,7 +2799,7 @@ pub(crate) fn op_vec(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_RAND_VEC, 1, true);
+define_op!(OP_RAND_VEC, 1, true, side_effect);
 pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
     let len = args[0]
         .get_int()
@@ -1742,8 +2814,7 @@ pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
         _ => bail!("'vec' requires a string as second argument"),
     };
 
-    let mut rng = thread_rng();
-    match t {
+    with_rng(|rng| match t {
         VecElementType::F32 => {
             let mut res_arr = ndarray::Array1::zeros(len);
             for mut row in res_arr.axis_iter_mut(ndarray::Axis(0)) {
@@ -1758,7 +2829,7 @@ pub(crate) fn op_rand_vec(args: &[DataValue]) -> Result<DataValue> {
             }
             Ok(DataValue::Vec(Vector::F64(res_arr)))
         }
-    }
+    })
 }
 
 define_op!(OP_L2_NORMALIZE, 1, false);
@@ -1850,60 +2921,92 @@ pub(crate) fn op_cos_dist(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+/// The default for [with_max_int_range_len]
+pub(crate) const DEFAULT_MAX_INT_RANGE_LEN: usize = 10_000_000;
+
+thread_local! {
+    pub(crate) static MAX_INT_RANGE_LEN: Cell<usize> = const { Cell::new(DEFAULT_MAX_INT_RANGE_LEN) };
+}
+
+/// Runs `f` with `int_range` limited to producing `len` elements, including in the queries
+/// run in it. Larger ranges are errors.
+pub fn with_max_int_range_len<R>(len: usize, f: impl FnOnce() -> R) -> R {
+    let _max_len = ModeGuard::set(&MAX_INT_RANGE_LEN, len);
+    f()
+}
+
 define_op!(OP_INT_RANGE, 1, true);
 pub(crate) fn op_int_range(args: &[DataValue]) -> Result<DataValue> {
-    let [start, end] = match args.len() {
-        1 => {
-            let end = args[0]
-                .get_int()
-                .ok_or_else(|| miette!("'int_range' requires integer argument for end"))?;
-            [0, end]
-        }
-        2 => {
-            let start = args[0]
-                .get_int()
-                .ok_or_else(|| miette!("'int_range' requires integer argument for start"))?;
-            let end = args[1]
-                .get_int()
-                .ok_or_else(|| miette!("'int_range' requires integer argument for end"))?;
-            [start, end]
-        }
-        3 => {
-            let start = args[0]
-                .get_int()
-                .ok_or_else(|| miette!("'int_range' requires integer argument for start"))?;
-            let end = args[1]
-                .get_int()
-                .ok_or_else(|| miette!("'int_range' requires integer argument for end"))?;
-            let step = args[2]
-                .get_int()
-                .ok_or_else(|| miette!("'int_range' requires integer argument for step"))?;
-            let mut current = start;
-            let mut result = vec![];
-            if step > 0 {
-                while current < end {
-                    result.push(DataValue::from(current));
-                    current += step;
-                }
-            } else {
-                while current > end {
-                    result.push(DataValue::from(current));
-                    current += step;
-                }
-            }
-            return Ok(DataValue::List(result));
-        }
+    let get_int = |i: usize, what: &str| {
+        args[i]
+            .get_int()
+            .ok_or_else(|| miette!("'int_range' requires integer argument for {}", what))
+    };
+    let (start, end, step) = match args.len() {
+        1 => (0, get_int(0, "end")?, 1),
+        2 => (get_int(0, "start")?, get_int(1, "end")?, 1),
+        3 => (
+            get_int(0, "start")?,
+            get_int(1, "end")?,
+            get_int(2, "step")?,
+        ),
         _ => bail!("'int_range' requires 1 to 3 argument"),
     };
-    Ok(DataValue::List((start..end).map(DataValue::from).collect()))
+    ensure!(step != 0, "'int_range' requires a non-zero step");
+    // computed in i128 so that ranges near the limits of i64 cannot overflow
+    let (start, end, step) = (start as i128, end as i128, step as i128);
+    let len = if step > 0 && start < end {
+        (end - start + step - 1) / step
+    } else if step < 0 && start > end {
+        (start - end - step - 1) / -step
+    } else {
+        0
+    };
+    let max_len = MAX_INT_RANGE_LEN.with(Cell::get);
+    ensure!(
+        len <= max_len as i128,
+        "'int_range' would produce {} elements, more than the limit of {}",
+        len,
+        max_len
+    );
+    Ok(DataValue::List(
+        (0..len)
+            .map(|i| DataValue::from((start + i * step) as i64))
+            .collect(),
+    ))
 }
 
-define_op!(OP_RAND_FLOAT, 0, false);
+thread_local! {
+    pub(crate) static RNG_SEED: Cell<Option<u64>> = const { Cell::new(None) };
+    pub(crate) static SEEDED_RNG: Cell<Option<StdRng>> = const { Cell::new(None) };
+}
+
+/// Runs `f` with the random functions and aggregations drawing from an RNG seeded with `seed`,
+/// so that their results are reproducible, including in the queries run in it. Each rule of
+/// a query draws from its own RNG seeded with `seed`, as rules may be evaluated in parallel.
+pub fn with_seeded_rng<T>(seed: u64, f: impl FnOnce() -> T) -> T {
+    let _seed = ModeGuard::set(&RNG_SEED, Some(seed));
+    let _rng = ModeGuard::set(&SEEDED_RNG, Some(StdRng::seed_from_u64(seed)));
+    f()
+}
+
+pub(crate) fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    // taken out of the cell for the duration of `f`
+    let mut seeded = SEEDED_RNG.with(Cell::take);
+    let ret = match &mut seeded {
+        Some(rng) => f(rng),
+        None => f(&mut thread_rng()),
+    };
+    SEEDED_RNG.with(|r| r.set(seeded));
+    ret
+}
+
+define_op!(OP_RAND_FLOAT, 0, false, side_effect);
 pub(crate) fn op_rand_float(_args: &[DataValue]) -> Result<DataValue> {
-    Ok(thread_rng().gen::<f64>().into())
+    Ok(with_rng(|rng| rng.gen::<f64>()).into())
 }
 
-define_op!(OP_RAND_BERNOULLI, 1, false);
+define_op!(OP_RAND_BERNOULLI, 1, false, side_effect);
 pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
     let prob = match &args[0] {
         DataValue::Num(n) => {
@@ -1916,10 +3019,10 @@ pub(crate) fn op_rand_bernoulli(args: &[DataValue]) -> Result<DataValue> {
         }
         _ => bail!("'rand_bernoulli' requires number between 0. and 1."),
     };
-    Ok(DataValue::from(thread_rng().gen_bool(prob)))
+    Ok(DataValue::from(with_rng(|rng| rng.gen_bool(prob))))
 }
 
-define_op!(OP_RAND_INT, 2, false);
+define_op!(OP_RAND_INT, 2, false, side_effect);
 pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let lower = &args[0]
         .get_int()
@@ -1927,23 +3030,18 @@ pub(crate) fn op_rand_int(args: &[DataValue]) -> Result<DataValue> {
     let upper = &args[1]
         .get_int()
         .ok_or_else(|| miette!("'rand_int' requires integers"))?;
-    Ok(thread_rng().gen_range(*lower..=*upper).into())
+    ensure!(lower <= upper, "'rand_int' requires a non-empty range");
+    Ok(with_rng(|rng| rng.gen_range(*lower..=*upper)).into())
 }
 
-define_op!(OP_RAND_CHOOSE, 1, false);
+define_op!(OP_RAND_CHOOSE, 1, false, side_effect);
 pub(crate) fn op_rand_choose(args: &[DataValue]) -> Result<DataValue> {
     match &args[0] {
-        DataValue::List(l) => Ok(l
-            .choose(&mut thread_rng())
-            .cloned()
-            .unwrap_or(DataValue::Null)),
-        DataValue::Set(l) => Ok(l
-            .iter()
-            .collect_vec()
-            .choose(&mut thread_rng())
-            .cloned()
-            .cloned()
-            .unwrap_or(DataValue::Null)),
+        DataValue::List(l) => Ok(with_rng(|rng| l.choose(rng).cloned()).unwrap_or(DataValue::Null)),
+        DataValue::Set(l) => {
+            let items = l.iter().collect_vec();
+            Ok(with_rng(|rng| items.choose(rng).cloned().cloned()).unwrap_or(DataValue::Null))
+        }
         _ => bail!("'rand_choice' requires lists"),
     }
 }
@@ -2034,7 +3132,7 @@ pub(crate) fn op_to_uuid(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_NOW, 0, false);
+define_op!(OP_NOW, 0, false, side_effect);
 #[cfg(target_arch = "wasm32")]
 pub(crate) fn op_now(_args: &[DataValue]) -> Result<DataValue> {
     let d: f64 = Date::now() / 1000.;
@@ -2120,10 +3218,9 @@ pub(crate) fn str2vld(s: &str) -> Result<ValidityTs> {
     Ok(ValidityTs(Reverse(microseconds as i64)))
 }
 
-define_op!(OP_RAND_UUID_V1, 0, false);
+define_op!(OP_RAND_UUID_V1, 0, false, side_effect);
 pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
-    let mut rng = rand::thread_rng();
-    let uuid_ctx = uuid::v1::Context::new(rng.gen());
+    let uuid_ctx = uuid::v1::Context::new(with_rng(|rng| rng.gen()));
     #[cfg(target_arch = "wasm32")]
     let ts = {
         let since_epoch: f64 = Date::now();
@@ -2138,12 +3235,12 @@ pub(crate) fn op_rand_uuid_v1(_args: &[DataValue]) -> Result<DataValue> {
         Timestamp::from_unix(uuid_ctx, since_epoch.as_secs(), since_epoch.subsec_nanos())
     };
     let mut rand_vals = [0u8; 6];
-    rng.fill(&mut rand_vals);
+    with_rng(|rng| rng.fill(&mut rand_vals));
     let id = uuid::Uuid::new_v1(ts, &rand_vals);
     Ok(DataValue::uuid(id))
 }
 
-define_op!(OP_RAND_UUID_V4, 0, false);
+define_op!(OP_RAND_UUID_V4, 0, false, side_effect);
 pub(crate) fn op_rand_uuid_v4(_args: &[DataValue]) -> Result<DataValue> {
     let id = uuid::Uuid::new_v4();
     Ok(DataValue::uuid(id))
@@ -9,7 +9,7 @@
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use serde_json::json;
-pub(crate) use serde_json::Value as JsonValue;
+pub use serde_json::Value as JsonValue;
 
 use crate::data::value::{Vector, DataValue, Num};
 
@@ -106,6 +106,7 @@ impl From<DataValue> for JsonValue {
             DataValue::Validity(v) => {
                 json!([v.timestamp.0, v.is_assert])
             }
+            DataValue::Json(j) => j.0,
         }
     }
 }
@@ -14,7 +14,10 @@ use std::str::FromStr;
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use regex::Regex;
 
-use crate::data::value::{Vector, DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+use crate::data::json::JsonValue;
+use crate::data::value::{
+    json_num, DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
+};
 
 const INIT_TAG: u8 = 0x00;
 const NULL_TAG: u8 = 0x01;
@@ -29,11 +32,27 @@ const REGEX_TAG: u8 = 0x09;
 const LIST_TAG: u8 = 0x0A;
 const SET_TAG: u8 = 0x0B;
 const VLD_TAG: u8 = 0x0C;
+// added after the storage format was first released: keys containing JSON values
+// cannot be decoded by versions of the database without JSON values
+const JSON_TAG: u8 = 0x0D;
 const BOT_TAG: u8 = 0xFF;
 
 const VEC_F32: u8 = 0x01;
 const VEC_F64: u8 = 0x02;
 
+// JSON values are encoded structurally so that their encodings sort in the order of the values
+const JSON_NULL: u8 = 0x01;
+const JSON_FALSE: u8 = 0x02;
+const JSON_TRUE: u8 = 0x03;
+const JSON_NUM: u8 = 0x04;
+const JSON_STR: u8 = 0x05;
+const JSON_ARR: u8 = 0x06;
+const JSON_OBJ: u8 = 0x07;
+const JSON_ENTRY: u8 = 0x01;
+// after the encoding of a JSON number decoding to a float
+const JSON_BIG_UINT: u8 = 0x00;
+const JSON_FLOAT: u8 = 0x01;
+
 const IS_FLOAT: u8 = 0b00010000;
 const IS_APPROX_INT: u8 = 0b00000100;
 const IS_EXACT_INT: u8 = 0b00000000;
@@ -113,6 +132,10 @@ pub(crate) trait MemCmpEncoder: Write {
                 self.write_u64::<BigEndian>(ts_flipped).unwrap();
                 self.write_u8(!vld.is_assert.0 as u8).unwrap();
             }
+            DataValue::Json(j) => {
+                self.write_u8(JSON_TAG).unwrap();
+                self.encode_json(&j.0)
+            }
             DataValue::Bot => self.write_u8(BOT_TAG).unwrap(),
         }
     }
@@ -136,6 +159,48 @@ pub(crate) trait MemCmpEncoder: Write {
         }
     }
 
+    fn encode_json(&mut self, j: &JsonValue) {
+        match j {
+            JsonValue::Null => self.write_u8(JSON_NULL).unwrap(),
+            JsonValue::Bool(false) => self.write_u8(JSON_FALSE).unwrap(),
+            JsonValue::Bool(true) => self.write_u8(JSON_TRUE).unwrap(),
+            JsonValue::Number(n) => {
+                self.write_u8(JSON_NUM).unwrap();
+                let num = json_num(n);
+                self.encode_num(num);
+                if let Num::Float(_) = num {
+                    match n.as_u64() {
+                        Some(u) => {
+                            self.write_u8(JSON_BIG_UINT).unwrap();
+                            self.write_u64::<BigEndian>(u).unwrap();
+                        }
+                        None => self.write_u8(JSON_FLOAT).unwrap(),
+                    }
+                }
+            }
+            JsonValue::String(s) => {
+                self.write_u8(JSON_STR).unwrap();
+                self.encode_bytes(s.as_bytes());
+            }
+            JsonValue::Array(a) => {
+                self.write_u8(JSON_ARR).unwrap();
+                for el in a {
+                    self.encode_json(el);
+                }
+                self.write_u8(INIT_TAG).unwrap()
+            }
+            JsonValue::Object(o) => {
+                self.write_u8(JSON_OBJ).unwrap();
+                for (k, v) in o {
+                    self.write_u8(JSON_ENTRY).unwrap();
+                    self.encode_bytes(k.as_bytes());
+                    self.encode_json(v);
+                }
+                self.write_u8(INIT_TAG).unwrap()
+            }
+        }
+    }
+
     fn encode_bytes(&mut self, key: &[u8]) {
         let len = key.len();
         let mut index = 0;
@@ -185,6 +250,55 @@ pub fn decode_bytes(data: &[u8]) -> (Vec<u8>, &[u8]) {
 
 const SIGN_MARK: u64 = 0x8000000000000000;
 
+fn decode_json(bs: &[u8]) -> (JsonValue, &[u8]) {
+    let (tag, remaining) = bs.split_first().unwrap();
+    match *tag {
+        JSON_NULL => (JsonValue::Null, remaining),
+        JSON_FALSE => (JsonValue::Bool(false), remaining),
+        JSON_TRUE => (JsonValue::Bool(true), remaining),
+        JSON_NUM => match Num::decode_from_key(remaining) {
+            (Num::Int(i), remaining) => (JsonValue::from(i), remaining),
+            (Num::Float(f), remaining) => {
+                let (kind, remaining) = remaining.split_first().unwrap();
+                if *kind == JSON_BIG_UINT {
+                    let (u_bytes, remaining) = remaining.split_at(8);
+                    (JsonValue::from(BigEndian::read_u64(u_bytes)), remaining)
+                } else {
+                    (JsonValue::from(f), remaining)
+                }
+            }
+        },
+        JSON_STR => {
+            let (bytes, remaining) = decode_bytes(remaining);
+            let s = unsafe { String::from_utf8_unchecked(bytes) };
+            (JsonValue::String(s), remaining)
+        }
+        JSON_ARR => {
+            let mut collected = vec![];
+            let mut remaining = remaining;
+            while remaining[0] != INIT_TAG {
+                let (val, next_chunk) = decode_json(remaining);
+                remaining = next_chunk;
+                collected.push(val);
+            }
+            (JsonValue::Array(collected), &remaining[1..])
+        }
+        JSON_OBJ => {
+            let mut collected = serde_json::Map::new();
+            let mut remaining = remaining;
+            while remaining[0] != INIT_TAG {
+                let (key, next_chunk) = decode_bytes(&remaining[1..]);
+                let key = unsafe { String::from_utf8_unchecked(key) };
+                let (val, next_chunk) = decode_json(next_chunk);
+                remaining = next_chunk;
+                collected.insert(key, val);
+            }
+            (JsonValue::Object(collected), &remaining[1..])
+        }
+        _ => unreachable!("unexpected JSON tag {}", tag),
+    }
+}
+
 fn order_encode_i64(v: i64) -> u64 {
     v as u64 ^ SIGN_MARK
 }
@@ -319,6 +433,10 @@ impl DataValue {
                     rest,
                 )
             }
+            JSON_TAG => {
+                let (j, remaining) = decode_json(remaining);
+                (DataValue::Json(JsonData(j)), remaining)
+            }
             BOT_TAG => (DataValue::Bot, remaining),
             VEC_TAG => {
                 let (t_tag, remaining) = remaining.split_first().unwrap();
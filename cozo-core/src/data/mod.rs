@@ -7,7 +7,9 @@
  */
 
 pub(crate) mod aggr;
+pub(crate) mod eval;
 pub(crate) mod expr;
+pub(crate) mod expr_build;
 pub(crate) mod functions;
 pub(crate) mod json;
 pub(crate) mod memcmp;
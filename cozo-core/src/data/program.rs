@@ -1009,7 +1009,8 @@ impl HnswSearchInput {
         {
             if let Some(arg) = self.bindings.remove(&col.name) {
                 match arg {
-                    Expr::Binding { var, .. } => {
+                    Expr::Binding { ref var, .. } => {
+                        let var = var.clone();
                         if var.is_ignored_symbol() {
                             bindings.push(gen.next_ignored(var.span));
                         } else if seen_variables.insert(var.clone()) {
@@ -1057,7 +1058,7 @@ impl HnswSearchInput {
         }
 
         let query = match self.query {
-            Expr::Binding { var, .. } => var,
+            Expr::Binding { ref var, .. } => var.clone(),
             expr => {
                 let span = expr.span();
                 let kw = gen.next(span);
@@ -1074,7 +1075,7 @@ impl HnswSearchInput {
 
         let bind_field = match self.bind_field {
             None => None,
-            Some(Expr::Binding { var, .. }) => Some(var),
+            Some(Expr::Binding { ref var, .. }) => Some(var.clone()),
             Some(expr) => {
                 let span = expr.span();
                 let kw = gen.next(span);
@@ -1091,7 +1092,7 @@ impl HnswSearchInput {
 
         let bind_field_idx = match self.bind_field_idx {
             None => None,
-            Some(Expr::Binding { var, .. }) => Some(var),
+            Some(Expr::Binding { ref var, .. }) => Some(var.clone()),
             Some(expr) => {
                 let span = expr.span();
                 let kw = gen.next(span);
@@ -1108,7 +1109,7 @@ impl HnswSearchInput {
 
         let bind_distance = match self.bind_distance {
             None => None,
-            Some(Expr::Binding { var, .. }) => Some(var),
+            Some(Expr::Binding { ref var, .. }) => Some(var.clone()),
             Some(expr) => {
                 let span = expr.span();
                 let kw = gen.next(span);
@@ -1125,7 +1126,7 @@ impl HnswSearchInput {
 
         let bind_vector = match self.bind_vector {
             None => None,
-            Some(Expr::Binding { var, .. }) => Some(var),
+            Some(Expr::Binding { ref var, .. }) => Some(var.clone()),
             Some(expr) => {
                 let span = expr.span();
                 let kw = gen.next(span);
@@ -1393,7 +1394,7 @@ pub(crate) struct Unification {
 
 impl Unification {
     pub(crate) fn is_const(&self) -> bool {
-        matches!(self.expr, Expr::Const { .. })
+        self.expr.is_const()
     }
     pub(crate) fn bindings_in_expr(&self) -> BTreeSet<Symbol> {
         self.expr.bindings()
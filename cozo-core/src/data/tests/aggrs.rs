@@ -10,8 +10,20 @@ use approx::AbsDiffEq;
 use itertools::Itertools;
 
 use crate::data::aggr::parse_aggr;
+use crate::data::functions::with_seeded_rng;
 use crate::data::value::DataValue;
 
+/// The result of the normal aggregation `name` initialized with `args` over `vals`
+fn run_aggr(name: &str, args: &[DataValue], vals: &[DataValue]) -> DataValue {
+    let mut aggr = parse_aggr(name).unwrap().clone();
+    aggr.normal_init(args).unwrap();
+    let mut op = aggr.normal_op.unwrap();
+    for v in vals {
+        op.set(v).unwrap();
+    }
+    op.get().unwrap()
+}
+
 #[test]
 fn test_and() {
     let mut aggr = parse_aggr("and").unwrap().clone();
@@ -71,6 +83,43 @@ fn test_or() {
     assert_eq!(v, DataValue::from(true));
 }
 
+#[test]
+fn test_wants_more() {
+    // feeds values the way the query driver does, counting how many are taken
+    let feed = |name: &str, values: &[DataValue]| {
+        let mut aggr = parse_aggr(name).unwrap().clone();
+        aggr.normal_init(&[]).unwrap();
+        let mut op = aggr.normal_op.unwrap();
+        let mut fed = 0;
+        for v in values {
+            if !op.wants_more() {
+                break;
+            }
+            op.set(v).unwrap();
+            fed += 1;
+        }
+        (op.get().unwrap(), fed)
+    };
+    let bools = |bs: &[bool]| bs.iter().map(|b| DataValue::from(*b)).collect_vec();
+    assert_eq!(
+        feed("or", &bools(&[false, true, false, false])),
+        (DataValue::from(true), 2)
+    );
+    assert_eq!(
+        feed("or", &bools(&[false, false])),
+        (DataValue::from(false), 2)
+    );
+    assert_eq!(
+        feed("and", &bools(&[true, false, true])),
+        (DataValue::from(false), 2)
+    );
+    let vals = [DataValue::Null, DataValue::from(1), DataValue::from(2)];
+    assert_eq!(feed("first", &vals), (DataValue::from(1), 2));
+    assert_eq!(feed("choice", &vals), (DataValue::from(1), 2));
+    assert_eq!(feed("last", &vals), (DataValue::from(2), 3));
+    assert_eq!(feed("count", &vals), (DataValue::from(3), 3));
+}
+
 #[test]
 fn test_unique() {
     let mut aggr = parse_aggr("unique").unwrap().clone();
@@ -210,6 +259,39 @@ fn test_count_unique() {
     assert_eq!(count_unique_aggr.get().unwrap(), DataValue::from(3));
 }
 
+#[test]
+fn test_approx_count_unique() {
+    let run = |args: &[DataValue], vals: &mut dyn Iterator<Item = DataValue>| {
+        run_aggr("approx_count_unique", args, &vals.collect_vec())
+            .get_int()
+            .unwrap()
+    };
+    // small counts are close to exact, and repeated values are not counted again
+    let small = run(&[], &mut (0..10).chain(0..10).map(DataValue::from));
+    assert!((9..=11).contains(&small), "{small}");
+    assert_eq!(run(&[], &mut [DataValue::Null].into_iter()), 0);
+
+    // within three standard errors
+    let estimate = run(&[], &mut (0..5000).map(DataValue::from)) as f64;
+    assert!(
+        (estimate - 5000.).abs() < 5000. * 3. * 1.04 / 64.,
+        "{estimate}"
+    );
+    let estimate = run(
+        &[DataValue::from(8)],
+        &mut (0..5000).map(|i| DataValue::from(format!("item {i}"))),
+    ) as f64;
+    assert!(
+        (estimate - 5000.).abs() < 5000. * 3. * 1.04 / 16.,
+        "{estimate}"
+    );
+
+    let mut aggr = parse_aggr("approx_count_unique").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(3)]).is_err());
+    assert!(aggr.normal_init(&[DataValue::from(17)]).is_err());
+    assert!(aggr.normal_init(&[DataValue::from("12")]).is_err());
+}
+
 #[test]
 fn test_collect() {
     let mut aggr = parse_aggr("collect").unwrap().clone();
@@ -233,6 +315,97 @@ fn test_collect() {
     );
 }
 
+#[test]
+fn test_array_agg() {
+    let ints = |v: &[i64]| DataValue::List(v.iter().map(|i| DataValue::from(*i)).collect());
+    let column = [3, 1, 2, 3]
+        .into_iter()
+        .map(DataValue::from)
+        .chain([DataValue::Null, DataValue::from(1)])
+        .collect_vec();
+
+    assert_eq!(run_aggr("array_agg", &[], &column), ints(&[3, 1, 2, 3, 1]));
+    assert_eq!(
+        run_aggr("array_agg_distinct", &[], &column),
+        ints(&[3, 1, 2])
+    );
+    assert_eq!(
+        run_aggr("array_agg", &[DataValue::from("asc")], &column),
+        ints(&[1, 1, 2, 3, 3])
+    );
+    assert_eq!(
+        run_aggr("array_agg_distinct", &[DataValue::from("desc")], &column),
+        ints(&[3, 2, 1])
+    );
+    assert_eq!(
+        run_aggr(
+            "array_agg",
+            &[DataValue::Null, DataValue::from(true)],
+            &column
+        ),
+        DataValue::List(column.clone())
+    );
+    assert_eq!(run_aggr("array_agg", &[], &[]), DataValue::List(vec![]));
+    assert_eq!(
+        run_aggr("array_agg_distinct", &[], &[]),
+        DataValue::List(vec![])
+    );
+
+    let mut aggr = parse_aggr("array_agg").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from("sideways")]).is_err());
+    assert!(aggr
+        .normal_init(&[DataValue::Null, DataValue::from(1)])
+        .is_err());
+}
+
+#[test]
+fn test_group_concat() {
+    let strs = |v: &[&str]| v.iter().map(|s| DataValue::from(*s)).collect_vec();
+    let comma = [DataValue::from(",")];
+
+    assert_eq!(
+        run_aggr("group_concat", &comma, &strs(&["a", "b", "c"])),
+        DataValue::from("a,b,c")
+    );
+    assert_eq!(
+        run_aggr(
+            "group_concat",
+            &[DataValue::from(" | ")],
+            &[
+                DataValue::Null,
+                DataValue::from("a"),
+                DataValue::Null,
+                DataValue::from("b")
+            ]
+        ),
+        DataValue::from("a | b")
+    );
+    assert_eq!(
+        run_aggr(
+            "group_concat",
+            &[],
+            &[DataValue::from(1), DataValue::from("x")]
+        ),
+        DataValue::from("1,x")
+    );
+    assert_eq!(
+        run_aggr(
+            "group_concat_distinct",
+            &comma,
+            &strs(&["a", "b", "a", "c", "b"])
+        ),
+        DataValue::from("a,b,c")
+    );
+    assert_eq!(run_aggr("group_concat", &comma, &[]), DataValue::from(""));
+    assert_eq!(
+        run_aggr("group_concat", &comma, &[DataValue::Null]),
+        DataValue::from("")
+    );
+
+    let mut aggr = parse_aggr("group_concat").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(1)]).is_err());
+}
+
 #[test]
 fn test_count() {
     let mut aggr = parse_aggr("count").unwrap().clone();
@@ -271,6 +444,40 @@ fn test_std_dev() {
     assert!(v.abs_diff_eq(&(0.5_f64).sqrt(), 1e-10));
 }
 
+#[test]
+fn test_variance_variants() {
+    let run = |name: &str, vals: &[DataValue]| run_aggr(name, &[], vals);
+    // mean 5, squared deviations sum to 32
+    let vals = [2, 4, 4, 4, 5, 5, 7, 9]
+        .into_iter()
+        .map(DataValue::from)
+        .chain([DataValue::Null])
+        .collect_vec();
+    let approx = |v: DataValue, expected: f64| v.get_float().unwrap().abs_diff_eq(&expected, 1e-10);
+    assert!(approx(run("variance", &vals), 32. / 7.));
+    assert!(approx(run("variance_pop", &vals), 4.));
+    assert!(approx(run("std_dev", &vals), (32_f64 / 7.).sqrt()));
+    assert!(approx(run("std_dev_pop", &vals), 2.));
+
+    // naive sums of squares lose all precision here
+    let shifted = vals
+        .iter()
+        .map(|v| match v {
+            DataValue::Num(n) => DataValue::from(n.get_float() + 1e9),
+            v => v.clone(),
+        })
+        .collect_vec();
+    let v = run("variance_pop", &shifted).get_float().unwrap();
+    assert!(v.abs_diff_eq(&4., 1e-6));
+
+    let one = [DataValue::from(1.5), DataValue::Null];
+    assert_eq!(run("variance", &one), DataValue::Null);
+    assert_eq!(run("std_dev", &one), DataValue::Null);
+    assert_eq!(run("variance_pop", &one), DataValue::from(0.));
+    assert_eq!(run("variance_pop", &[]), DataValue::Null);
+    assert_eq!(run("std_dev_pop", &[DataValue::Null]), DataValue::Null);
+}
+
 #[test]
 fn test_mean() {
     let mut aggr = parse_aggr("mean").unwrap().clone();
@@ -285,6 +492,41 @@ fn test_mean() {
     assert_eq!(mean_aggr.get().unwrap(), DataValue::from(3.));
 }
 
+#[test]
+fn test_percentile() {
+    let run = |name: &str, args: &[DataValue], vals: &[i64]| {
+        let vals = [DataValue::Null]
+            .into_iter()
+            .chain(vals.iter().map(|v| DataValue::from(*v)))
+            .collect_vec();
+        run_aggr(name, args, &vals)
+    };
+    assert_eq!(run("median", &[], &[5, 1, 3]), DataValue::from(3.));
+    assert_eq!(run("median", &[], &[4, 1, 3, 2]), DataValue::from(2.5));
+    let p90 = [DataValue::from(0.9)];
+    // the fractional index is 0.9 * 10 = 9
+    assert_eq!(
+        run("percentile", &p90, &[10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]),
+        DataValue::from(9.)
+    );
+    let v = run("percentile", &p90, &[1, 2, 3, 4]).get_float().unwrap();
+    assert!(v.abs_diff_eq(&3.7, 1e-10));
+    assert_eq!(
+        run("percentile", &[DataValue::from(0)], &[3, 2, 8]),
+        DataValue::from(2.)
+    );
+    assert_eq!(
+        run("percentile", &[DataValue::from(1)], &[3, 2, 8]),
+        DataValue::from(8.)
+    );
+    assert_eq!(run("median", &[], &[]), DataValue::Null);
+
+    let mut aggr = parse_aggr("percentile").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(1.5)]).is_err());
+    assert!(aggr.normal_init(&[DataValue::from(-0.1)]).is_err());
+    assert!(aggr.normal_init(&[]).is_err());
+}
+
 #[test]
 fn test_sum() {
     let mut aggr = parse_aggr("sum").unwrap().clone();
@@ -445,6 +687,103 @@ fn test_latest_by() {
     assert_eq!(latest_by_aggr.get().unwrap(), DataValue::Null);
 }
 
+#[test]
+fn test_sample() {
+    let run = |n: i64, vals: &[DataValue]| run_aggr("sample", &[DataValue::from(n)], vals);
+    let vals = (0..1000).map(DataValue::from).collect_vec();
+    let sample = with_seeded_rng(7, || run(5, &vals));
+    assert_eq!(sample, with_seeded_rng(7, || run(5, &vals)));
+    assert_ne!(sample, with_seeded_rng(8, || run(5, &vals)));
+    let sample = sample.get_slice().unwrap().to_vec();
+    assert_eq!(sample.len(), 5);
+    assert_eq!(sample.iter().unique().count(), 5);
+    assert!(sample.iter().all(|v| vals.contains(v)));
+    // later values are sampled too, not just the first ones
+    assert_ne!(sample, vals[..5]);
+
+    // with fewer than n values all of them are returned
+    assert_eq!(run(5, &vals[..3]), DataValue::List(vals[..3].to_vec()));
+    assert_eq!(run(5, &[]), DataValue::List(vec![]));
+
+    let mut aggr = parse_aggr("sample").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(0)]).is_err());
+    assert!(aggr.normal_init(&[DataValue::from(1.5)]).is_err());
+    assert!(aggr.normal_init(&[]).is_err());
+}
+
+#[test]
+fn test_arg_max_min() {
+    let run = |name: &str, vals: &[(DataValue, DataValue)]| {
+        let vals = vals
+            .iter()
+            .map(|(v, k)| DataValue::List(vec![v.clone(), k.clone()]))
+            .collect_vec();
+        run_aggr(name, &[], &vals)
+    };
+    let pairs = |ps: &[(&str, i64)]| {
+        ps.iter()
+            .map(|(v, k)| (DataValue::from(*v), DataValue::from(*k)))
+            .collect_vec()
+    };
+    let vals = pairs(&[("a", 2), ("b", 5), ("c", 1), ("d", 3)]);
+    assert_eq!(run("arg_max", &vals), DataValue::from("b"));
+    assert_eq!(run("arg_min", &vals), DataValue::from("c"));
+
+    // ties go to the first value seen
+    let ties = pairs(&[("a", 1), ("b", 5), ("c", 5), ("d", 1)]);
+    assert_eq!(run("arg_max", &ties), DataValue::from("b"));
+    assert_eq!(run("arg_min", &ties), DataValue::from("a"));
+
+    // null keys are ignored
+    let mut with_nulls = vec![(DataValue::from("x"), DataValue::Null)];
+    with_nulls.extend(pairs(&[("a", 2), ("b", 1)]));
+    assert_eq!(run("arg_min", &with_nulls), DataValue::from("b"));
+    let all_null = [
+        (DataValue::from("x"), DataValue::Null),
+        (DataValue::from("y"), DataValue::Null),
+    ];
+    assert_eq!(run("arg_max", &all_null), DataValue::Null);
+    assert_eq!(run("arg_min", &[]), DataValue::Null);
+
+    let mut aggr = parse_aggr("arg_max").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    assert!(aggr.normal_op.unwrap().set(&DataValue::from(1)).is_err());
+}
+
+#[test]
+fn test_first_last() {
+    let vals = [
+        DataValue::Null,
+        DataValue::from("a"),
+        DataValue::from("b"),
+        DataValue::from("c"),
+        DataValue::Null,
+    ];
+    assert_eq!(run_aggr("first", &[], &vals), DataValue::from("a"));
+    assert_eq!(run_aggr("last", &[], &vals), DataValue::from("c"));
+
+    let keyed = [("x", 3), ("y", 1), ("z", 2)]
+        .into_iter()
+        .map(|(v, k)| DataValue::List(vec![DataValue::from(v), DataValue::from(k)]))
+        .chain([DataValue::List(vec![DataValue::Null, DataValue::from(0)])])
+        .collect_vec();
+    let by_key = [DataValue::from(true)];
+    assert_eq!(run_aggr("first", &by_key, &keyed), DataValue::from("y"));
+    assert_eq!(run_aggr("last", &by_key, &keyed), DataValue::from("x"));
+    let mut reversed = keyed.clone();
+    reversed.reverse();
+    assert_eq!(run_aggr("first", &by_key, &reversed), DataValue::from("y"));
+    assert_eq!(run_aggr("last", &by_key, &reversed), DataValue::from("x"));
+
+    assert_eq!(run_aggr("first", &[], &[DataValue::Null]), DataValue::Null);
+    assert_eq!(run_aggr("last", &[], &[DataValue::Null]), DataValue::Null);
+    assert_eq!(run_aggr("last", &by_key, &[]), DataValue::Null);
+
+    let mut aggr = parse_aggr("first").unwrap().clone();
+    aggr.normal_init(&by_key).unwrap();
+    assert!(aggr.normal_op.unwrap().set(&DataValue::from(1)).is_err());
+}
+
 #[test]
 fn test_shortest() {
     let mut aggr = parse_aggr("shortest").unwrap().clone();
@@ -572,3 +911,33 @@ fn test_bit_xor() {
     bit_xor_aggr.set(&DataValue::Bytes(vec![0b01011])).unwrap();
     assert_eq!(bit_xor_aggr.get().unwrap(), DataValue::Bytes(vec![0b10111]));
 }
+
+#[test]
+fn test_bit_int() {
+    let run = |name: &str, vals: &[DataValue]| run_aggr(name, &[], vals);
+    let flags = [
+        DataValue::from(0b0110),
+        DataValue::Null,
+        DataValue::from(0b1100),
+        DataValue::from(0b0101),
+    ];
+    assert_eq!(run("bit_or_int", &flags), DataValue::from(0b1111));
+    assert_eq!(run("bit_and_int", &flags), DataValue::from(0b0100));
+    assert_eq!(
+        run(
+            "bit_and_int",
+            &[DataValue::from(0b0110), DataValue::from(0b1100)]
+        ),
+        DataValue::from(0b0100)
+    );
+    // the identities
+    assert_eq!(run("bit_or_int", &[]), DataValue::from(0));
+    assert_eq!(run("bit_and_int", &[]), DataValue::from(-1));
+    assert_eq!(run("bit_and_int", &[DataValue::Null]), DataValue::from(-1));
+
+    let mut aggr = parse_aggr("bit_or_int").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut op = aggr.normal_op.unwrap();
+    assert!(op.set(&DataValue::from(1.0)).is_err());
+    assert!(op.set(&DataValue::Bytes(vec![1])).is_err());
+}
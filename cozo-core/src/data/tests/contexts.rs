@@ -0,0 +1,301 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+use crate::data::eval::{ChainedContext, MapRowContext, SortOrder, WithDefaults};
+use crate::data::functions::{Collation, NumericPromotion};
+use crate::data::symb::Symbol;
+use crate::data::value::JsonData;
+use crate::parse::{parse_expressions, SourceSpan};
+use crate::DataValue;
+
+fn scope(vars: &[(&str, DataValue)]) -> BTreeMap<String, DataValue> {
+    vars.iter()
+        .map(|(name, val)| (name.to_string(), val.clone()))
+        .collect()
+}
+
+#[test]
+fn chained_context() {
+    let outer = scope(&[("x", DataValue::from(1)), ("y", DataValue::from(10))]);
+    let inner = scope(&[("x", DataValue::from(100))]);
+    let ctx = ChainedContext::new(&inner, &outer);
+
+    // inner scope shadows outer
+    let expr = parse_expressions("x", &Default::default()).unwrap();
+    assert_eq!(expr.eval_in_context(&ctx).unwrap(), DataValue::from(100));
+    assert_eq!(expr.eval_in_context(&outer).unwrap(), DataValue::from(1));
+
+    // fallback to outer when inner misses
+    let expr = parse_expressions("x + y", &Default::default()).unwrap();
+    assert_eq!(expr.eval_in_context(&ctx).unwrap(), DataValue::from(110));
+
+    let empty = scope(&[]);
+    let ctx = ChainedContext::new(&empty, &outer);
+    assert_eq!(expr.eval_in_context(&ctx).unwrap(), DataValue::from(11));
+
+    // locals of the expression are not looked up
+    let expr = parse_expressions("let(x, 2, x * y)", &Default::default()).unwrap();
+    assert_eq!(expr.eval_in_context(&ctx).unwrap(), DataValue::from(20));
+
+    let expr = parse_expressions("x + z", &Default::default()).unwrap();
+    assert!(expr.eval_in_context(&ctx).is_err());
+}
+
+#[test]
+fn map_row_context() {
+    let mut expr = parse_expressions("[x + y, let(z, x * 2, z + y)]", &Default::default()).unwrap();
+    let binding_map = BTreeMap::from([
+        (Symbol::new("x", SourceSpan(0, 0)), 0),
+        (Symbol::new("y", SourceSpan(0, 0)), 5),
+    ]);
+    expr.fill_binding_indices(&binding_map).unwrap();
+
+    let row: MapRowContext = [(0, DataValue::from(1)), (5, DataValue::from(2))]
+        .into_iter()
+        .collect();
+    assert_eq!(
+        expr.eval_in_row(&row).unwrap(),
+        DataValue::List(vec![DataValue::from(3), DataValue::from(4)])
+    );
+
+    let row: MapRowContext = [(0, DataValue::from(1)), (4, DataValue::from(2))]
+        .into_iter()
+        .collect();
+    assert!(expr.eval_in_row(&row).is_err());
+
+    // missing positions as null, e.g. for rows predating a column
+    let mut expr = parse_expressions("[x, y ~ 'missing']", &Default::default()).unwrap();
+    expr.fill_binding_indices(&binding_map).unwrap();
+    assert!(expr.eval_in_row(&row).is_err());
+    let row = row.with_missing_as_null();
+    assert_eq!(
+        expr.eval_in_row(&row).unwrap(),
+        DataValue::List(vec![DataValue::from(1), DataValue::from("missing")])
+    );
+}
+
+#[test]
+fn row_collation() {
+    let mut expr = parse_expressions("a < b", &Default::default()).unwrap();
+    let binding_map = BTreeMap::from([
+        (Symbol::new("a", SourceSpan(0, 0)), 0),
+        (Symbol::new("b", SourceSpan(0, 0)), 1),
+    ]);
+    expr.fill_binding_indices(&binding_map).unwrap();
+
+    let row: MapRowContext = [
+        (0, DataValue::from("apple")),
+        (1, DataValue::from("Banana")),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(expr.eval_in_row(&row).unwrap(), DataValue::from(false));
+    let row = row.collated(Collation::CaseInsensitive);
+    assert_eq!(expr.eval_in_row(&row).unwrap(), DataValue::from(true));
+}
+
+#[test]
+fn row_numeric_promotion() {
+    let mut expr = parse_expressions("a + 2.0", &Default::default()).unwrap();
+    let binding_map = BTreeMap::from([(Symbol::new("a", SourceSpan(0, 0)), 0)]);
+    expr.fill_binding_indices(&binding_map).unwrap();
+
+    let row: MapRowContext = [(0, DataValue::from(1))].into_iter().collect();
+    assert_eq!(expr.eval_in_row(&row).unwrap(), DataValue::from(3.0));
+    let row = row.with_promotion(NumericPromotion::Error);
+    assert!(expr.eval_in_row(&row).is_err());
+}
+
+#[test]
+fn sort_keys() {
+    let mut expr = parse_expressions("try(a + b, null)", &Default::default()).unwrap();
+    let binding_map = BTreeMap::from([
+        (Symbol::new("a", SourceSpan(0, 0)), 0),
+        (Symbol::new("b", SourceSpan(0, 0)), 1),
+    ]);
+    expr.fill_binding_indices(&binding_map).unwrap();
+    let rows = [
+        (DataValue::from(1), DataValue::from(2)),
+        (DataValue::Null, DataValue::from(1)),
+        (DataValue::from(0), DataValue::from(-5)),
+        (DataValue::from(2.5), DataValue::from(0)),
+    ];
+    let sorted = |order: SortOrder| {
+        let mut keyed = rows
+            .iter()
+            .map(|(a, b)| {
+                let row: MapRowContext = [(0, a.clone()), (1, b.clone())].into_iter().collect();
+                expr.sort_key(&row, order).unwrap()
+            })
+            .collect::<Vec<_>>();
+        keyed.sort();
+        keyed.into_iter().map(|key| key.val).collect::<Vec<_>>()
+    };
+    let (null, three, neg_five, two_half) = (
+        DataValue::Null,
+        DataValue::from(3),
+        DataValue::from(-5),
+        DataValue::from(2.5),
+    );
+    let order = |descending, nulls_last| SortOrder {
+        descending,
+        nulls_last,
+    };
+    assert_eq!(
+        sorted(order(false, false)),
+        [
+            null.clone(),
+            neg_five.clone(),
+            two_half.clone(),
+            three.clone()
+        ]
+    );
+    assert_eq!(
+        sorted(order(false, true)),
+        [
+            neg_five.clone(),
+            two_half.clone(),
+            three.clone(),
+            null.clone()
+        ]
+    );
+    assert_eq!(
+        sorted(order(true, false)),
+        [
+            null.clone(),
+            three.clone(),
+            two_half.clone(),
+            neg_five.clone()
+        ]
+    );
+    assert_eq!(sorted(order(true, true)), [three, two_half, neg_five, null]);
+}
+
+#[test]
+fn context_with_defaults() {
+    let params = scope(&[("limit", DataValue::from(5))]);
+    let ctx = WithDefaults::new(&params).with_default("offset", DataValue::from(0));
+
+    let mut expr = parse_expressions("offset + limit * 2", &Default::default()).unwrap();
+    assert_eq!(expr.eval_in_context(&ctx).unwrap(), DataValue::from(10));
+    expr.partial_eval_in_context(&ctx, &mut Default::default())
+        .unwrap();
+    assert_eq!(expr.get_const(), Some(&DataValue::from(10)));
+
+    // defaults only apply to misses
+    let params = scope(&[
+        ("offset", DataValue::from(3)),
+        ("limit", DataValue::from(5)),
+    ]);
+    let ctx = WithDefaults::new(&params).with_default("offset", DataValue::from(0));
+    let expr = parse_expressions("offset + limit * 2", &Default::default()).unwrap();
+    assert_eq!(expr.eval_in_context(&ctx).unwrap(), DataValue::from(13));
+
+    // a miss without default still errors, and is kept when folding
+    let mut expr = parse_expressions("offset + limit * page", &Default::default()).unwrap();
+    assert!(expr.eval_in_context(&ctx).is_err());
+    expr.partial_eval_in_context(&ctx, &mut Default::default())
+        .unwrap();
+    assert_eq!(expr.to_string(), "add(3, mul(5, page))");
+
+    let ctx = WithDefaults::new(&params).with_fallback(DataValue::from(1));
+    assert_eq!(expr.eval_in_context(&ctx).unwrap(), DataValue::from(8));
+}
+
+#[test]
+fn prepare_in_context() {
+    let ctx = scope(&[("x", DataValue::from(2)), ("y", DataValue::from(3))]);
+    let prepare = |src: &str| {
+        let expr = parse_expressions(src, &Default::default()).unwrap();
+        expr.prepare(&ctx, &mut Default::default()).unwrap()
+    };
+
+    let prepared = prepare("1 + x * y");
+    assert_eq!(prepared.get_const(), Some(&DataValue::from(7)));
+    // the outcome does not depend on which passes have already been run
+    for src in ["1 + x * y < z", "z > 1 + x * y"] {
+        let expected = prepare(src);
+        assert_eq!(expected.to_string(), "gt(z, 7)");
+        let mut folded = parse_expressions(src, &Default::default()).unwrap();
+        folded.partial_eval().unwrap();
+        assert!(folded
+            .prepare(&ctx, &mut Default::default())
+            .unwrap()
+            .is_equivalent(&expected));
+        let mut normalized = parse_expressions(src, &Default::default()).unwrap();
+        normalized.normalize_comparisons();
+        assert!(normalized
+            .prepare(&ctx, &mut Default::default())
+            .unwrap()
+            .is_equivalent(&expected));
+        let mut substituted = parse_expressions(src, &Default::default()).unwrap();
+        substituted
+            .partial_eval_in_context(&ctx, &mut Default::default())
+            .unwrap();
+        assert!(substituted
+            .prepare(&ctx, &mut Default::default())
+            .unwrap()
+            .is_equivalent(&expected));
+        let again = expected
+            .clone()
+            .prepare(&ctx, &mut Default::default())
+            .unwrap();
+        assert!(again.is_equivalent(&expected));
+    }
+    assert_eq!(
+        prepare("let(z, x - 1, y * z) ~ 0").get_const(),
+        Some(&DataValue::from(3))
+    );
+    assert!(parse_expressions("assert(x > y)", &Default::default())
+        .unwrap()
+        .prepare(&ctx, &mut Default::default())
+        .is_err());
+}
+
+#[test]
+fn json_context() {
+    let ctx = json!({
+        "s": "abc",
+        "i": 2,
+        "f": 2.5,
+        "b": true,
+        "n": null,
+        "l": [1, "x"],
+        "o": {"k": 1}
+    });
+    let eval = |src: &str| {
+        parse_expressions(src, &Default::default())
+            .unwrap()
+            .eval_in_context(&ctx)
+    };
+    assert_eq!(eval("s").unwrap(), DataValue::from("abc"));
+    assert_eq!(eval("i").unwrap(), DataValue::from(2));
+    assert_eq!(eval("f").unwrap(), DataValue::from(2.5));
+    assert_eq!(eval("i * 2").unwrap(), DataValue::from(4));
+    assert_eq!(eval("b").unwrap(), DataValue::from(true));
+    assert_eq!(eval("n").unwrap(), DataValue::Null);
+    assert_eq!(
+        eval("l").unwrap(),
+        DataValue::Json(JsonData(json!([1, "x"])))
+    );
+    assert_eq!(
+        eval("o").unwrap(),
+        DataValue::Json(JsonData(json!({"k": 1})))
+    );
+    assert_eq!(eval("get_path(o, 'k')").unwrap(), DataValue::from(1));
+    assert!(eval("missing").is_err());
+    // only the fields of objects are resolved
+    assert!(parse_expressions("x", &Default::default())
+        .unwrap()
+        .eval_in_context(&json!([1]))
+        .is_err());
+}
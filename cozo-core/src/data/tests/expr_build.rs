@@ -0,0 +1,63 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use crate::data::expr_build::ExprBuilder as B;
+use crate::data::symb::Symbol;
+use crate::parse::SourceSpan;
+use crate::DataValue;
+
+#[test]
+fn builder_checks_arity() {
+    assert!(B::apply("sub", vec![B::constant(1)]).is_err());
+    assert!(B::apply("sub", vec![B::constant(1), B::constant(2), B::constant(3)]).is_err());
+    assert!(B::apply("no_such_function", vec![]).is_err());
+
+    let mut expr = B::apply("add", vec![B::var("x"), B::constant(2)]).unwrap();
+    assert_eq!(expr.to_string(), "add(x, 2)");
+    let bindings = BTreeMap::from([(Symbol::new("x", SourceSpan(0, 0)), 0)]);
+    expr.fill_binding_indices(&bindings).unwrap();
+    assert_eq!(
+        expr.eval(&[DataValue::from(1)]).unwrap(),
+        DataValue::from(3)
+    );
+    expr.partial_eval().unwrap();
+}
+
+#[test]
+fn builder_special_forms() {
+    assert!(B::cond(vec![]).is_err());
+    assert!(B::try_clauses(vec![]).is_err());
+    assert!(B::higher_order("no_such_function", B::constant(1), "x", B::var("x")).is_err());
+
+    let cond = B::cond(vec![(B::var("c"), B::constant(1))]).unwrap();
+    assert_eq!(cond.to_string(), "cond(c, 1, true, null)");
+
+    let mut expr = B::let_in(
+        "y",
+        B::apply("mul", vec![B::constant(3), B::constant(4)]).unwrap(),
+        B::higher_order(
+            "map",
+            B::apply("list", vec![B::constant(1), B::constant(2)]).unwrap(),
+            "x",
+            B::apply("add", vec![B::var("x"), B::var("y")]).unwrap(),
+        )
+        .unwrap(),
+    );
+    expr.fill_binding_indices(&Default::default()).unwrap();
+    assert_eq!(
+        expr.eval(&[]).unwrap(),
+        DataValue::List(vec![DataValue::from(13), DataValue::from(14)])
+    );
+
+    // regex arguments are compiled, as in parsed expressions
+    let mut expr = B::apply("regex_matches", vec![B::constant("abc"), B::constant("b")]).unwrap();
+    expr.partial_eval().unwrap();
+    assert_eq!(expr.get_const(), Some(&DataValue::from(true)));
+}
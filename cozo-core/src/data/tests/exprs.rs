@@ -6,8 +6,58 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::expr::{
+    all_builtin_ops, eval_bytecode, get_op, register_custom_op, with_strict_floats, CustomOp, Expr,
+    ExprInterner, PartialEvalMemo, MAX_EXPR_DEPTH,
+};
+use crate::data::expr_build::ExprBuilder as B;
+use crate::data::functions::{
+    op_and, op_or, with_numeric_promotion, NumericPromotion, OP_ADD, OP_GT, OP_NEGATE, OP_SQRT,
+    OP_SUB,
+};
+use crate::data::relation::{ColType, NullableColType};
+use crate::data::symb::Symbol;
+use crate::data::value::Num;
+use crate::parse::{parse_expressions, SourceSpan};
 use crate::{new_cozo_mem, DataValue};
 
+/// Evaluates `src` with the variables `vars` bound to the provided values,
+/// checking that the interpreter and the bytecode give the same result.
+fn eval_with(src: &str, vars: &[(&str, DataValue)]) -> Result<DataValue> {
+    let mut expr = parse_expressions(src, &Default::default())?;
+    let binding_map: BTreeMap<_, _> = vars
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (Symbol::new(*name, SourceSpan(0, 0)), i))
+        .collect();
+    let values = vars.iter().map(|(_, v)| v.clone()).collect::<Vec<_>>();
+    expr.fill_binding_indices(&binding_map)?;
+    let interpreted = expr.eval(&values);
+    let mut stack = vec![];
+    let compiled = eval_bytecode(&expr.compile(), &values, &mut stack);
+    match (&interpreted, &compiled) {
+        (Ok(a), Ok(b)) => assert_eq!(a, b),
+        (Err(_), Err(_)) => {}
+        _ => panic!("evaluation mismatch: {interpreted:?} vs {compiled:?}"),
+    }
+    interpreted
+}
+
+/// `src` after partial evaluation, as text
+fn fold(src: &str) -> String {
+    let mut expr = parse_expressions(src, &Default::default()).unwrap();
+    expr.partial_eval().unwrap();
+    expr.to_string()
+}
+
 #[test]
 fn expression_eval() {
     let db = new_cozo_mem().unwrap();
@@ -32,3 +82,1480 @@ fn expression_eval() {
         .unwrap();
     assert_eq!(res.rows[0][0].get_bool().unwrap(), true);
 }
+
+fn error_span(err: &miette::Report) -> (usize, usize) {
+    let label = err.labels().unwrap().next().unwrap();
+    (label.offset(), label.len())
+}
+
+#[test]
+fn eval_error_spans() {
+    let src = "1 + (2 * 'x')";
+    let expr = parse_expressions(src, &Default::default()).unwrap();
+    let err = expr.eval(&[]).unwrap_err();
+    assert_eq!(error_span(&err), (5, 7));
+    let mut stack = vec![];
+    let err = eval_bytecode(&expr.compile(), [], &mut stack).unwrap_err();
+    assert_eq!(error_span(&err), (5, 7));
+    let err = expr.eval_to_const().unwrap_err();
+    assert_eq!(error_span(&err), (5, 7));
+
+    let src = "if(1 + 2, 3, 4)";
+    let expr = parse_expressions(src, &Default::default()).unwrap();
+    let err = expr.eval(&[]).unwrap_err();
+    assert_eq!(error_span(&err), (3, 5));
+    let err = eval_bytecode(&expr.compile(), [], &mut stack).unwrap_err();
+    assert_eq!(error_span(&err), (3, 5));
+}
+
+#[test]
+fn try_expr() {
+    let x = |v: DataValue| [("x", v)];
+    assert_eq!(
+        eval_with("try(to_int(x), assert(false))", &x(DataValue::from("42"))).unwrap(),
+        DataValue::from(42)
+    );
+    assert_eq!(
+        eval_with("try(to_int(x), -1)", &x(DataValue::from("abc"))).unwrap(),
+        DataValue::from(-1)
+    );
+    assert_eq!(
+        eval_with("try(x + 1, x ++ 'y', 0)", &x(DataValue::from("abc"))).unwrap(),
+        DataValue::from("abcy")
+    );
+    assert!(eval_with("try(to_int(x), assert(false))", &x(DataValue::from("abc"))).is_err());
+    assert_eq!(
+        eval_with("1 + try(try(x + 1, assert(false)), 2)", &x(DataValue::Null)).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        eval_with("[1, try(cond(x, 2), 3)]", &x(DataValue::Null)).unwrap(),
+        DataValue::List(vec![DataValue::from(1), DataValue::from(3)])
+    );
+
+    let mut folded = parse_expressions("try(1 + 'a', 2 * 3)", &Default::default()).unwrap();
+    folded.partial_eval().unwrap();
+    assert_eq!(folded.get_const(), Some(&DataValue::from(6)));
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[x, y] := x in ['1', 'a', '3'], y = try(to_int(x), -1)",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![DataValue::from("1"), DataValue::from(1)],
+            vec![DataValue::from("3"), DataValue::from(3)],
+            vec![DataValue::from("a"), DataValue::from(-1)],
+        ]
+    );
+}
+
+#[test]
+fn let_expr() {
+    let x = |v: DataValue| [("x", v)];
+    assert_eq!(
+        eval_with("let(y, x * 2, [y, y + 1, y * y])", &x(DataValue::from(3))).unwrap(),
+        DataValue::List(vec![
+            DataValue::from(6),
+            DataValue::from(7),
+            DataValue::from(36)
+        ])
+    );
+    // the bound value is evaluated only once
+    assert_eq!(
+        eval_with("let(r, rand_float(), r - r)", &[]).unwrap(),
+        DataValue::from(0.)
+    );
+    assert_eq!(
+        eval_with(
+            "let(r, rand_int(0, x), r == r)",
+            &x(DataValue::from(1000000))
+        )
+        .unwrap(),
+        DataValue::from(true)
+    );
+    // shadowing
+    assert_eq!(
+        eval_with(
+            "let(x, x + 1, let(x, x * 10, x) + x)",
+            &x(DataValue::from(1))
+        )
+        .unwrap(),
+        DataValue::from(22)
+    );
+    // the value of a binding is in the enclosing scope, so bindings cannot form cycles:
+    // the first `b` below is not the inner one, and is unbound without an outer `b`
+    assert_eq!(
+        eval_with("let(a, b, let(b, a, a + b))", &[("b", DataValue::from(1))]).unwrap(),
+        DataValue::from(2)
+    );
+    assert!(eval_with("let(a, b, let(b, a, a + b))", &[]).is_err());
+    assert_eq!(
+        eval_with("let(a, 1, let(b, a + 1, let(c, b + 1, c)))", &[]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        eval_with("[let(y, 1, let(z, 2, y + z)), x]", &x(DataValue::from(0))).unwrap(),
+        DataValue::List(vec![DataValue::from(3), DataValue::from(0)])
+    );
+    assert_eq!(
+        eval_with(
+            "try(let(y, x + 1, y), let(y, 1, y))",
+            &x(DataValue::from("a"))
+        )
+        .unwrap(),
+        DataValue::from(1)
+    );
+
+    let expr = parse_expressions("let(y, x + 1, y * z)", &Default::default()).unwrap();
+    assert_eq!(
+        expr.bindings()
+            .into_iter()
+            .map(|s| s.name)
+            .collect::<Vec<_>>(),
+        vec!["x", "z"]
+    );
+    let mut folded =
+        parse_expressions("let(y, 1 + 2, let(z, y, y * z))", &Default::default()).unwrap();
+    folded.partial_eval().unwrap();
+    assert_eq!(folded.get_const(), Some(&DataValue::from(9)));
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[x, y] := x in [1, 2], y = let(z, x * 2, z + z)",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![DataValue::from(1), DataValue::from(4)],
+            vec![DataValue::from(2), DataValue::from(8)],
+        ]
+    );
+}
+
+#[test]
+fn map_expr() {
+    let ints = |v: &[i64]| DataValue::List(v.iter().map(|i| DataValue::from(*i)).collect());
+    let xs = |v: DataValue| [("xs", v)];
+    assert_eq!(
+        eval_with("map(xs, x -> x * 2)", &xs(ints(&[1, 2, 3]))).unwrap(),
+        ints(&[2, 4, 6])
+    );
+    assert_eq!(
+        eval_with("map(xs, x -> x * 2)", &xs(ints(&[]))).unwrap(),
+        ints(&[])
+    );
+    assert_eq!(
+        eval_with("map(xs, x -> x * 2)", &xs(DataValue::Null)).unwrap(),
+        DataValue::Null
+    );
+    assert!(eval_with("map(xs, x -> x * 2)", &xs(DataValue::from(1))).is_err());
+    // the innermost parameter is used, outer ones stay visible
+    assert_eq!(
+        eval_with(
+            "map(xs, x -> map([x, x + 1], x -> x * 10))",
+            &xs(ints(&[1, 2]))
+        )
+        .unwrap(),
+        DataValue::List(vec![ints(&[10, 20]), ints(&[20, 30])])
+    );
+    assert_eq!(
+        eval_with("map(xs, x -> map([1, 2], y -> x * y))", &xs(ints(&[1, 2]))).unwrap(),
+        DataValue::List(vec![ints(&[1, 2]), ints(&[2, 4])])
+    );
+    assert_eq!(
+        eval_with(
+            "try(map(xs, x -> x + 1), map(xs, x -> x))",
+            &xs(DataValue::List(vec![
+                DataValue::from(1),
+                DataValue::from("a")
+            ]))
+        )
+        .unwrap(),
+        DataValue::List(vec![DataValue::from(1), DataValue::from("a")])
+    );
+
+    let expr = parse_expressions("map(xs, x -> x + y)", &Default::default()).unwrap();
+    assert_eq!(expr.to_string(), "map(xs, x -> add(x, y))");
+    assert_eq!(
+        expr.bindings()
+            .into_iter()
+            .map(|s| s.name)
+            .collect::<Vec<_>>(),
+        vec!["xs", "y"]
+    );
+    assert!(parse_expressions("map(xs)", &Default::default()).is_err());
+    assert!(parse_expressions("map(xs, x -> x, 1)", &Default::default()).is_err());
+    assert!(parse_expressions("length(x -> x)", &Default::default()).is_err());
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[x, y] := x in [1, 2], y = map([x, 10], z -> z + x)",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![DataValue::from(1), ints(&[2, 11])],
+            vec![DataValue::from(2), ints(&[4, 12])],
+        ]
+    );
+}
+
+#[test]
+fn filter_expr() {
+    let ints = |v: &[i64]| DataValue::List(v.iter().map(|i| DataValue::from(*i)).collect());
+    let xs = |v: DataValue| [("xs", v)];
+    assert_eq!(
+        eval_with("filter(xs, x -> x % 2 == 0)", &xs(ints(&[1, 2, 3, 4]))).unwrap(),
+        ints(&[2, 4])
+    );
+    assert_eq!(
+        eval_with("filter(xs, x -> false)", &xs(ints(&[1, 2]))).unwrap(),
+        ints(&[])
+    );
+    // elements for which the predicate is null are excluded
+    assert_eq!(
+        eval_with("filter(xs, x -> x > 1 && null)", &xs(ints(&[1, 2, 3]))).unwrap(),
+        ints(&[])
+    );
+    assert_eq!(
+        eval_with("filter(xs, x -> if(x > 1, true))", &xs(ints(&[1, 2, 3]))).unwrap(),
+        ints(&[2, 3])
+    );
+    assert!(eval_with("filter(xs, x -> x)", &xs(ints(&[1]))).is_err());
+    assert_eq!(
+        eval_with("filter(xs, x -> x)", &xs(DataValue::Null)).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        eval_with(
+            "map(filter(xs, x -> x > 1), x -> x * 10)",
+            &xs(ints(&[1, 2, 3]))
+        )
+        .unwrap(),
+        ints(&[20, 30])
+    );
+}
+
+#[test]
+fn any_all_expr() {
+    let list = |v: &[DataValue]| [("xs", DataValue::List(v.to_vec()))];
+    let (t, f, n) = (
+        DataValue::from(true),
+        DataValue::from(false),
+        DataValue::Null,
+    );
+    let cases = [
+        (vec![t.clone(), t.clone()], t.clone(), t.clone()),
+        (vec![t.clone(), f.clone()], t.clone(), f.clone()),
+        (vec![f.clone(), f.clone()], f.clone(), f.clone()),
+        (vec![], f.clone(), t.clone()),
+        (vec![n.clone(), t.clone()], t.clone(), n.clone()),
+        (vec![n.clone(), f.clone()], n.clone(), f.clone()),
+        (vec![n.clone()], n.clone(), n.clone()),
+    ];
+    for (items, any, all) in cases {
+        assert_eq!(
+            eval_with("any(xs, x -> x)", &list(&items)).unwrap(),
+            any,
+            "any({items:?})"
+        );
+        assert_eq!(
+            eval_with("all(xs, x -> x)", &list(&items)).unwrap(),
+            all,
+            "all({items:?})"
+        );
+    }
+    let ints = [DataValue::from(1), DataValue::from(2), DataValue::from(3)];
+    assert_eq!(eval_with("any(xs, x -> x > 2)", &list(&ints)).unwrap(), t);
+    assert_eq!(eval_with("all(xs, x -> x > 2)", &list(&ints)).unwrap(), f);
+    // the remaining elements are skipped once the result is decided
+    assert_eq!(
+        eval_with("any(xs, x -> x == 1 || assert(false))", &list(&ints)).unwrap(),
+        t
+    );
+    assert_eq!(
+        eval_with("all(xs, x -> x != 1 && assert(false))", &list(&ints)).unwrap(),
+        f
+    );
+    assert!(eval_with("all(xs, x -> x)", &list(&ints)).is_err());
+}
+
+#[test]
+fn chained_comparison() {
+    let x = |v: DataValue| [("x", v)];
+    assert_eq!(
+        eval_with("1 < x <= 10", &x(DataValue::from(5))).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        eval_with("1 <= x + 1 < 10", &x(DataValue::from(5))).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        eval_with("0 < x < 3 <= 10", &x(DataValue::from(5))).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        eval_with("10 > x * 2 >= 0", &x(DataValue::from(5))).unwrap(),
+        DataValue::from(false)
+    );
+    // same as the conjunction of the comparisons
+    assert!(eval_with("1 < x < 10", &x(DataValue::Null)).is_err());
+    assert!(eval_with("1 < x && x < 10", &x(DataValue::Null)).is_err());
+    // which stops at the first false comparison
+    assert_eq!(
+        eval_with("10 < 1 < x", &x(DataValue::Null)).unwrap(),
+        DataValue::from(false)
+    );
+    // parentheses keep the comparisons apart
+    assert!(eval_with("(1 < x) < 10", &x(DataValue::from(5))).is_err());
+
+    // the middle term is computed only once
+    let expr = parse_expressions("1 < x + 1 < 10", &Default::default()).unwrap();
+    match &expr {
+        Expr::Let { value, .. } => assert_eq!(value.to_string(), "add(x, 1)"),
+        e => panic!("unexpected {e}"),
+    }
+
+    // fully constant chains fold to a single constant
+    for (src, expected) in [
+        ("1 < 2 < 3", true),
+        ("1 < 2 + 1 <= 3", true),
+        ("3 > 2 > 2", false),
+    ] {
+        let mut expr = parse_expressions(src, &Default::default()).unwrap();
+        expr.partial_eval().unwrap();
+        match expr {
+            Expr::Const {
+                val: DataValue::Bool(b),
+                ..
+            } => assert_eq!(b, expected, "{src}"),
+            e => panic!("{src} folded to {e}"),
+        }
+    }
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[x] := x in [1, 2, 3, 4, 5], 1 < x <= 3",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(2)], vec![DataValue::from(3)]]
+    );
+}
+
+#[test]
+fn const_extraction() {
+    let expr = parse_expressions("(1 + 2) * 3 - to_int('4')", &Default::default()).unwrap();
+    assert!(!expr.is_const());
+    assert_eq!(
+        expr.partial_eval_to_value().unwrap(),
+        Some(DataValue::from(5))
+    );
+
+    let mut expr = parse_expressions("(1 + 2) * x", &Default::default()).unwrap();
+    assert_eq!(expr.clone().partial_eval_to_value().unwrap(), None);
+    expr.partial_eval().unwrap();
+    assert!(!expr.is_const());
+    assert_eq!(expr.get_const(), None);
+    assert_eq!(expr.to_string(), "mul(3, x)");
+}
+
+#[test]
+fn implies_expr() {
+    let x = |v: DataValue| [("x", v)];
+    assert_eq!(
+        eval_with("implies(x > 1, x > 0)", &x(DataValue::from(2))).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        eval_with("implies(x > 1, x > 5)", &x(DataValue::from(2))).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        eval_with("implies(x, null)", &x(DataValue::Null)).unwrap(),
+        DataValue::Null
+    );
+    // the consequent is skipped when the antecedent is false
+    assert_eq!(
+        eval_with("implies(x > 1, assert(false))", &x(DataValue::from(0))).unwrap(),
+        DataValue::from(true)
+    );
+    assert!(eval_with("implies(x > 1, assert(false))", &x(DataValue::from(2))).is_err());
+    assert!(eval_with("implies(x, true)", &x(DataValue::from(1))).is_err());
+
+    let mut folded =
+        parse_expressions("implies(1 > 2, assert(false))", &Default::default()).unwrap();
+    folded.partial_eval().unwrap();
+    assert_eq!(folded.get_const(), Some(&DataValue::from(true)));
+    let mut folded = parse_expressions("implies(1 < 2, y)", &Default::default()).unwrap();
+    folded.partial_eval().unwrap();
+    assert_eq!(folded.to_string(), "implies(true, y)");
+}
+
+#[test]
+fn coalesce_short_circuit() {
+    let x = |v: DataValue| [("x", v)];
+    // the right operand is skipped when the left is not null
+    assert_eq!(
+        eval_with("x ~ assert(false)", &x(DataValue::from(1))).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        eval_with("(x + 1) ~ assert(false)", &x(DataValue::from(1))).unwrap(),
+        DataValue::from(2)
+    );
+    assert_eq!(
+        eval_with("coalesce(x, assert(false), 3)", &x(DataValue::from(1))).unwrap(),
+        DataValue::from(1)
+    );
+    assert!(eval_with("x ~ assert(false)", &x(DataValue::Null)).is_err());
+    assert_eq!(
+        eval_with("coalesce(x, null, x ~ 2)", &x(DataValue::Null)).unwrap(),
+        DataValue::from(2)
+    );
+    assert_eq!(eval_with("coalesce()", &[]).unwrap(), DataValue::Null);
+
+    let mut folded = parse_expressions("1 ~ assert(false)", &Default::default()).unwrap();
+    folded.partial_eval().unwrap();
+    assert_eq!(folded.get_const(), Some(&DataValue::from(1)));
+}
+
+#[test]
+fn strict_floats() {
+    let nan = eval_with("0.0 / 0.0", &[]).unwrap();
+    assert!(nan.get_float().unwrap().is_nan());
+    assert_eq!(
+        eval_with("exp(1000)", &[]).unwrap(),
+        DataValue::from(f64::INFINITY)
+    );
+
+    with_strict_floats(|| {
+        for src in ["0.0 / 0.0", "exp(1000)", "1 / 0"] {
+            let err = eval_with(src, &[]).unwrap_err();
+            assert!(format!("{err:?}").contains("invalid float"), "{src}");
+            let mut expr = parse_expressions(src, &Default::default()).unwrap();
+            assert!(expr.partial_eval().is_err());
+        }
+        // the error points at the expression producing the float
+        let expr = parse_expressions("1 + exp(1000)", &Default::default()).unwrap();
+        let err = expr.eval(&[]).unwrap_err();
+        assert_eq!(error_span(&err), (4, 9));
+        assert_eq!(err.code().unwrap().to_string(), "eval::invalid_float");
+        let err = eval_bytecode(&expr.compile(), [], &mut vec![]).unwrap_err();
+        assert_eq!(error_span(&err), (4, 9));
+        // a value that is already invalid propagates
+        let x = [("x", DataValue::from(f64::NAN))];
+        assert!(eval_with("x + 1", &x)
+            .unwrap()
+            .get_float()
+            .unwrap()
+            .is_nan());
+        assert_eq!(
+            eval_with("exp(10)", &[]).unwrap(),
+            DataValue::from(10f64.exp())
+        );
+    });
+
+    // the mode is restored afterwards
+    assert!(eval_with("exp(1000)", &[]).is_ok());
+}
+
+#[test]
+fn self_comparison_folding() {
+    // constant operands are folded like any constant expression
+    assert_eq!(fold("1.5 == 1.5"), "true");
+    assert_eq!(fold("'a' != 'a'"), "false");
+    assert_eq!(fold("[1, 2] == [1, 2]"), "true");
+    assert_eq!(fold("3 - 3"), "0");
+    assert_eq!(fold("(1 + 2) - (2 + 1)"), "0");
+    // a variable may be null or unbound, and its error must not disappear
+    assert_eq!(fold("x == x"), "eq(x, x)");
+    assert_eq!(fold("x != x"), "neq(x, x)");
+    assert_eq!(fold("[x, 1] == [x, 1]"), "eq(list(x, 1), list(x, 1))");
+    assert!(eval_with("x == x", &[]).is_err());
+    assert_eq!(
+        eval_with("x == x", &[("x", DataValue::Null)]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(fold("x == y"), "eq(x, y)");
+    // the operands might fail or differ between evaluations
+    assert_eq!(fold("(x + 1) == (x + 1)"), "eq(add(x, 1), add(x, 1))");
+    assert_eq!(
+        fold("rand_int(1, 2) == rand_int(1, 2)"),
+        "eq(rand_int(1, 2), rand_int(1, 2))"
+    );
+    assert_eq!(fold("random() == random()"), "eq(rand_float, rand_float)");
+    // the difference is 0, 0.0, NaN or an error depending on the value
+    assert_eq!(fold("x - x"), "sub(x, x)");
+    assert_eq!(fold("random() - random()"), "sub(rand_float, rand_float)");
+}
+
+#[test]
+fn list_access_folding() {
+    assert_eq!(fold("get([1, x, 3], 1)"), "x");
+    assert_eq!(fold("get([1, x, 3], -1)"), "3");
+    // a list of lists, with the inner access folded first
+    assert_eq!(fold("get(get([[1, x], [y]], 0), 1)"), "x");
+    assert_eq!(fold("maybe_get(get([[1, x], [y + 1]], 1), 0)"), "add(y, 1)");
+    // a missing index
+    assert_eq!(fold("maybe_get([x, y], 2)"), "null");
+    assert_eq!(fold("maybe_get(get([[x], [y]], 1), 5)"), "null");
+    assert_eq!(fold("get([x, y], 2)"), "get(list(x, y), 2)");
+    // negative indices resolve from the end, and may still be out of range
+    assert_eq!(fold("maybe_get([x, y, 3], -3)"), "x");
+    assert_eq!(fold("maybe_get([x, y], -3)"), "null");
+    assert_eq!(fold("get([x, y], -3)"), "get(list(x, y), -3)");
+    // the same for constant lists
+    assert_eq!(fold("get([1, 2, 3], -1)"), "3");
+    assert_eq!(fold("maybe_get([1, 2, 3], -3)"), "1");
+    assert_eq!(fold("maybe_get([1, 2, 3], -4)"), "null");
+    // skipping an element that might fail would hide its error
+    assert_eq!(fold("get([assert(x), y], 1)"), "get(list(assert(x), y), 1)");
+    assert_eq!(fold("get([x, y], z)"), "get(list(x, y), z)");
+
+    let vars = [("x", DataValue::from(7)), ("y", DataValue::from(8))];
+    assert_eq!(
+        eval_with("get(get([[1, x], [y]], 0), 1)", &vars).unwrap(),
+        DataValue::from(7)
+    );
+}
+
+#[test]
+fn flat_coalesce() {
+    let vals = |a: DataValue, b: DataValue, c: DataValue| [("a", a), ("b", b), ("c", c)];
+    let cases = [DataValue::Null, DataValue::from(1), DataValue::from("x")];
+    for a in &cases {
+        for b in &cases {
+            for c in &cases {
+                let vars = vals(a.clone(), b.clone(), c.clone());
+                let flat = eval_with("coalesce(a, b, c)", &vars).unwrap();
+                assert_eq!(flat, eval_with("a ~ (b ~ c)", &vars).unwrap());
+                assert_eq!(flat, eval_with("(a ~ b) ~ c", &vars).unwrap());
+                assert_eq!(
+                    flat,
+                    eval_with("coalesce(a, coalesce(b), c)", &vars).unwrap()
+                );
+            }
+        }
+    }
+
+    // chains are flattened instead of nested
+    let expr = parse_expressions("a ~ b ~ (c + 1) ~ 4", &Default::default()).unwrap();
+    assert_eq!(expr.to_string(), "coalesce(a, b, add(c, 1), 4)");
+    assert_eq!(
+        expr.explain(),
+        "Coalesce\n  Binding a (unresolved)\n  Binding b (unresolved)\n  Apply add\n    \
+         Binding c (unresolved)\n    Const 1\n  Const 4\n"
+    );
+
+    // later arguments are not evaluated
+    let one = DataValue::from(1);
+    assert_eq!(
+        eval_with(
+            "coalesce(a, b, assert(false), c)",
+            &vals(DataValue::Null, one.clone(), DataValue::Null)
+        )
+        .unwrap(),
+        one
+    );
+    assert!(eval_with(
+        "coalesce(a, b, assert(false), c)",
+        &vals(DataValue::Null, DataValue::Null, one.clone())
+    )
+    .is_err());
+
+    assert_eq!(fold("coalesce(null, a, null, 1 + 1, b)"), "coalesce(a, 2)");
+    assert_eq!(fold("coalesce(null, 1 + 1, a)"), "2");
+    assert_eq!(fold("coalesce(null, a)"), "a");
+    assert_eq!(fold("coalesce(null, null)"), "null");
+    assert_eq!(
+        fold("coalesce(a, assert(false), b)"),
+        "coalesce(a, assert(false), b)"
+    );
+}
+
+#[test]
+fn flat_and_or() {
+    let t = DataValue::from(true);
+    let f = DataValue::from(false);
+    let n = DataValue::Null;
+    let cases = [t.clone(), f.clone(), n.clone()];
+    for a in &cases {
+        for b in &cases {
+            let vars = [("a", a.clone()), ("b", b.clone())];
+            let and = eval_with("a && b", &vars).unwrap();
+            let expected = if *a == f || *b == f {
+                f.clone()
+            } else if *a == n || *b == n {
+                n.clone()
+            } else {
+                t.clone()
+            };
+            assert_eq!(and, expected);
+            assert_eq!(and, op_and(&[a.clone(), b.clone()]).unwrap());
+            let or = eval_with("a || b", &vars).unwrap();
+            let expected = if *a == t || *b == t {
+                t.clone()
+            } else if *a == n || *b == n {
+                n.clone()
+            } else {
+                f.clone()
+            };
+            assert_eq!(or, expected);
+            assert_eq!(or, op_or(&[a.clone(), b.clone()]).unwrap());
+            for c in &cases {
+                let vars = [("a", a.clone()), ("b", b.clone()), ("c", c.clone())];
+                let flat = eval_with("and(a, b, c)", &vars).unwrap();
+                assert_eq!(flat, eval_with("a && (b && c)", &vars).unwrap());
+                assert_eq!(flat, eval_with("(a && b) && c", &vars).unwrap());
+                let flat = eval_with("or(a, b, c)", &vars).unwrap();
+                assert_eq!(flat, eval_with("a || (b || c)", &vars).unwrap());
+                assert_eq!(flat, eval_with("(a || b) || c", &vars).unwrap());
+            }
+        }
+    }
+    assert_eq!(eval_with("and()", &[]).unwrap(), t);
+    assert_eq!(eval_with("or()", &[]).unwrap(), f);
+
+    // chains are flattened instead of nested
+    let expr = parse_expressions("a && b && (c || d || e)", &Default::default()).unwrap();
+    assert_eq!(expr.to_string(), "and(a, b, or(c, d, e))");
+
+    // later arguments are skipped once the result is decided, even after a null
+    let vars = |a: DataValue| [("a", a), ("b", DataValue::Null)];
+    assert_eq!(
+        eval_with("b && a && assert(false)", &vars(f.clone())).unwrap(),
+        f
+    );
+    assert_eq!(
+        eval_with("b || a || assert(false)", &vars(t.clone())).unwrap(),
+        t
+    );
+    assert!(eval_with("b && a && assert(false)", &vars(t.clone())).is_err());
+    assert!(eval_with("a && 1", &vars(t.clone())).is_err());
+    assert_eq!(eval_with("a && 1", &vars(f.clone())).unwrap(), f);
+
+    assert_eq!(fold("true && a && 1 > 0"), "and(a)");
+    assert_eq!(fold("a && 1 > 2 && b"), "and(a, false)");
+    assert_eq!(fold("1 > 2 && assert(false)"), "false");
+    assert_eq!(fold("a || 1 > 0 || b"), "or(a, true)");
+    assert_eq!(fold("false || 1 > 2"), "false");
+    assert_eq!(fold("null && true"), "null");
+    assert_eq!(fold("null || a"), "or(null, a)");
+}
+
+#[test]
+fn cond_folding() {
+    assert_eq!(fold("if(1 > 2, x, 3)"), "3");
+    assert_eq!(
+        fold("cond(x, 1 + 1, 1 < 2, 3, y, 4)"),
+        "cond(x, 2, true, 3)"
+    );
+    assert_eq!(fold("cond(1 > 2, 1)"), "null");
+    // unreachable clauses are not evaluated
+    assert_eq!(
+        fold("if(x, 1, assert(false))"),
+        "cond(x, 1, true, assert(false))"
+    );
+}
+
+#[test]
+fn folding_inside_unlowered_ops() {
+    assert_eq!(fold("is_in(x, [1 + 1, 2 * 3])"), "is_in(x, [2, 6])");
+    assert_eq!(fold("is_in(x + (1 + 1), y)"), "is_in(add(x, 2), y)");
+    assert_eq!(
+        fold("regex_matches(x, concat('a', 'b'))"),
+        "regex_matches(x, regex(\"ab\"))"
+    );
+    assert_eq!(
+        fold("implies(x, 1 + 1 == 2)"),
+        "let(~implies, x, cond(eq(~implies, false), true, true, implies(~implies, true)))"
+    );
+    assert_eq!(fold("0 < 1 + 1 < x"), "and(lt(2, x))");
+    assert_eq!(fold("x ~ (1 + 1)"), "coalesce(x, 2)");
+    assert_eq!(fold("format('{}', 1 + 1, x)"), "format(\"{}\", 2, x)");
+}
+
+#[test]
+fn constant_folding_is_complete() {
+    // every kind of expression built from constants alone folds to the value it evaluates to
+    let cases = [
+        "1 + 2 * 3",
+        "-(4 - 5) ^ 2 / 2",
+        "[1, 2 + 1, [3 * 3]]",
+        "concat('a', 'b') ++ 'c'",
+        "if(1 > 2, 'a', 'b')",
+        "cond(1 > 2, 1, 2 > 1, 2)",
+        "cond(false, 1)",
+        "try(assert(false), 1 + 1)",
+        "try(1 / 0, 0)",
+        "let(x, 1 + 1, x * x)",
+        "let(x, 2, let(y, x + 1, x * y))",
+        "map([1, 2], x -> x * 2)",
+        "map([], x -> assert(false))",
+        "map([1, 2], x -> map([3], y -> x * y))",
+        "filter([1, 2, 3], x -> x != 2)",
+        "any([1, 2], x -> x > 1)",
+        "all([1, null], x -> x > 1)",
+        "null ~ null ~ (1 + 1)",
+        "coalesce(null, null)",
+        "1 < 2 <= 2 < 3",
+        "3 < 2 < 1",
+        "true && 1 < 2",
+        "null && false",
+        "null && true",
+        "false || null",
+        "null || true",
+        "implies(1 > 2, assert(false))",
+        "implies(true, null)",
+        "is_null(1 + 1)",
+        "!(!(1 > 2))",
+        "format('{}-{}', 1 + 1, null ~ 3)",
+    ];
+    for src in cases {
+        let mut expr = parse_expressions(src, &Default::default()).unwrap();
+        expr.fill_binding_indices(&Default::default()).unwrap();
+        let expected = expr.eval([]).unwrap();
+        let mut folded = expr.clone();
+        folded.partial_eval().unwrap();
+        assert_eq!(
+            folded.get_const(),
+            Some(&expected),
+            "{src} folded to {folded}"
+        );
+        let mut memoized = expr;
+        memoized
+            .partial_eval_memoized(&mut Default::default())
+            .unwrap();
+        assert_eq!(
+            memoized.get_const(),
+            Some(&expected),
+            "{src} folded to {memoized}"
+        );
+    }
+}
+
+#[test]
+fn side_effects_not_folded() {
+    for src in [
+        "random()",
+        "random_int(1, 10)",
+        "rand_uuid_v4()",
+        "now() + 1",
+    ] {
+        let mut expr = parse_expressions(src, &Default::default()).unwrap();
+        expr.partial_eval().unwrap();
+        assert!(matches!(expr, Expr::Apply { .. }), "{src} was folded");
+    }
+    let mut expr = parse_expressions("random_int(1 + 1, 2 * 5)", &Default::default()).unwrap();
+    expr.partial_eval().unwrap();
+    assert_eq!(expr.to_string(), "rand_int(2, 10)");
+    // but closed expressions still evaluate as constants
+    let n = parse_expressions("random_int(5, 5)", &Default::default())
+        .unwrap()
+        .eval_to_const()
+        .unwrap();
+    assert_eq!(n, DataValue::from(5));
+}
+
+#[test]
+fn is_null_folding() {
+    assert_eq!(fold("is_null(1)"), "false");
+    assert_eq!(fold("is_null(null)"), "true");
+    assert_eq!(fold("!is_null(1)"), "true");
+    assert_eq!(fold("!is_null(null)"), "false");
+    assert_eq!(fold("is_null(1 + 2 * 3)"), "false");
+    // not constant, but known to be a number
+    assert_eq!(fold("is_null(random() * 10 - 1)"), "false");
+    assert_eq!(fold("!is_null(-now())"), "true");
+    // may be null, or may raise an error
+    assert_eq!(fold("is_null(x)"), "is_null(x)");
+    assert_eq!(fold("is_null(x + 1)"), "is_null(add(x, 1))");
+    assert_eq!(
+        fold("is_null(random() + 'a')"),
+        "is_null(add(rand_float, \"a\"))"
+    );
+    // arithmetic that fails in the strict modes is left for evaluation to raise the error
+    assert_eq!(fold("is_null(random() / 0.0)"), "false");
+    with_strict_floats(|| {
+        assert_eq!(
+            fold("is_null(random() / 0.0)"),
+            "is_null(div(rand_float, 0))"
+        );
+        assert!(eval_with("is_null(random() / 0.0)", &[]).is_err());
+        assert_eq!(fold("is_null(random())"), "false");
+    });
+    assert_eq!(fold("is_null(now() + 1)"), "false");
+    with_numeric_promotion(NumericPromotion::Error, || {
+        assert_eq!(fold("is_null(now() + 1)"), "is_null(add(now, 1))");
+        assert!(eval_with("is_null(now() + 1)", &[]).is_err());
+    });
+}
+
+#[test]
+fn comparison_normalization() {
+    let normalize = |src: &str| {
+        let mut expr = parse_expressions(src, &Default::default()).unwrap();
+        expr.partial_eval().unwrap();
+        expr.normalize_comparisons();
+        expr.to_string()
+    };
+    assert_eq!(normalize("x > 1"), "gt(x, 1)");
+    assert_eq!(normalize("1 > x"), "lt(x, 1)");
+    assert_eq!(normalize("x >= 1"), "ge(x, 1)");
+    assert_eq!(normalize("1 >= x"), "le(x, 1)");
+    assert_eq!(normalize("x < 1"), "lt(x, 1)");
+    assert_eq!(normalize("1 < x"), "gt(x, 1)");
+    assert_eq!(normalize("x <= 1"), "le(x, 1)");
+    assert_eq!(normalize("1 <= x"), "ge(x, 1)");
+    // the constant side may need folding first
+    assert_eq!(normalize("1 + 1 < x"), "gt(x, 2)");
+    // nested comparisons are normalized as well
+    assert_eq!(
+        normalize("1 < x && 'a' >= y"),
+        "and(gt(x, 1), le(y, \"a\"))"
+    );
+    // only a lone variable opposite a constant is rewritten
+    assert_eq!(normalize("y < x"), "lt(y, x)");
+    assert_eq!(normalize("1 < x + 1"), "lt(1, add(x, 1))");
+}
+
+#[test]
+fn conjunct_splitting() {
+    let split = |expr: &Expr| {
+        expr.split_conjuncts()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+    };
+    let expr = parse_expressions("a && b > 1 && c", &Default::default()).unwrap();
+    assert_eq!(split(&expr), ["a", "gt(b, 1)", "c"]);
+    let expr = parse_expressions("a > 1", &Default::default()).unwrap();
+    assert_eq!(split(&expr), ["gt(a, 1)"]);
+    // disjunctions and negations are single conjuncts
+    let expr = parse_expressions("(a || b) && !(c && d)", &Default::default()).unwrap();
+    assert_eq!(split(&expr), ["or(a, b)", "negate(and(c, d))"]);
+    // nested conjunctions are flattened even if constructed directly
+    let nested = Expr::And {
+        args: vec![
+            expr,
+            Expr::And {
+                args: vec![parse_expressions("e", &Default::default()).unwrap()],
+                span: SourceSpan(0, 0),
+            },
+        ],
+        span: SourceSpan(0, 0),
+    };
+    assert_eq!(split(&nested), ["or(a, b)", "negate(and(c, d))", "e"]);
+}
+
+#[test]
+fn disjunctive_normal_form() {
+    let dnf = |src: &str| {
+        parse_expressions(src, &Default::default())
+            .unwrap()
+            .into_dnf()
+            .to_string()
+    };
+    assert_eq!(dnf("a && (b || c)"), "or(and(a, b), and(a, c))");
+    assert_eq!(
+        dnf("(a || b) && (c || d)"),
+        "or(and(a, c), and(a, d), and(b, c), and(b, d))"
+    );
+    assert_eq!(
+        dnf("!(a > 1 || b > 1)"),
+        "and(negate(gt(a, 1)), negate(gt(b, 1)))"
+    );
+    assert_eq!(
+        dnf("!(a > 1 && (b > 1 || !(c > 1)))"),
+        "or(negate(gt(a, 1)), and(negate(gt(b, 1)), gt(c, 1)))"
+    );
+    assert_eq!(dnf("!!(a > 1)"), "gt(a, 1)");
+    // negating null is an error, so operands that might be null are not negated
+    assert_eq!(dnf("!(a || b > 1)"), "negate(or(a, gt(b, 1)))");
+    assert_eq!(dnf("!!a"), "negate(negate(a))");
+    assert_eq!(dnf("a"), "a");
+
+    // nulls propagate as in the original expression
+    let cases = [
+        DataValue::Null,
+        DataValue::from(true),
+        DataValue::from(false),
+    ];
+    for src in [
+        "a && (b || c)",
+        "!(a && (b || !c))",
+        "(a || b) && !(c && a)",
+    ] {
+        let mut orig = parse_expressions(src, &Default::default()).unwrap();
+        let mut rewritten = orig.clone().into_dnf();
+        let binding_map = ["a", "b", "c"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (Symbol::new(name, SourceSpan(0, 0)), i))
+            .collect();
+        orig.fill_binding_indices(&binding_map).unwrap();
+        rewritten.fill_binding_indices(&binding_map).unwrap();
+        for a in &cases {
+            for b in &cases {
+                for c in &cases {
+                    let vals = [a.clone(), b.clone(), c.clone()];
+                    match (orig.eval(&vals), rewritten.eval(&vals)) {
+                        (Ok(x), Ok(y)) => assert_eq!(x, y, "{src} with {vals:?}"),
+                        // negating null is an error
+                        (Err(_), Err(_)) => {}
+                        (x, y) => panic!("{src} with {vals:?}: {x:?} vs {y:?}"),
+                    }
+                }
+            }
+        }
+    }
+
+    // too many conjunctions
+    let src = "(a || b) && (c || d) && (e || f) && (g || h) && (i || j) && (k || l) && (m || n)";
+    let expr = parse_expressions(src, &Default::default()).unwrap();
+    assert_eq!(expr.clone().into_dnf().to_string(), expr.to_string());
+    // this would evaluate the random number twice
+    assert_eq!(
+        dnf("a && (rand_float() > 0.5 || c)"),
+        "and(a, or(gt(rand_float, 0.5), c))"
+    );
+}
+
+#[test]
+fn expr_equivalence() {
+    let equiv = |a: &str, b: &str| {
+        let a = parse_expressions(a, &Default::default()).unwrap();
+        let b = parse_expressions(b, &Default::default()).unwrap();
+        assert_eq!(a.is_equivalent(&b), b.is_equivalent(&a));
+        a.is_equivalent(&b)
+    };
+    assert!(equiv("a + b", "a + b"));
+    assert!(equiv("a + b", "b + a"));
+    assert!(!equiv("a - b", "b - a"));
+    assert!(!equiv("a / b", "b / a"));
+    assert!(!equiv("a < b", "b < a"));
+    assert!(equiv("(a + b) * c", "c * (b + a)"));
+    assert!(equiv("a == 1 && b != 'x'", "'x' != b && 1 == a"));
+    assert!(equiv("a || b || c", "c || (b || a)"));
+    assert!(!equiv("(a - b) * c", "c * (b - a)"));
+    assert!(!equiv("a + a", "a + b"));
+    assert!(!equiv("a + b", "a * b"));
+    assert!(!equiv("a + b", "a + b + c"));
+    assert!(equiv("if(a + b > 0, a, b)", "if(b + a > 0, a, b)"));
+    assert!(!equiv("if(a, 1, 2)", "if(a, 2, 1)"));
+}
+
+#[test]
+fn custom_ops() {
+    struct Double;
+    impl CustomOp for Double {
+        fn name(&self) -> &'static str {
+            "double"
+        }
+        fn min_arity(&self) -> usize {
+            1
+        }
+        fn vararg(&self) -> bool {
+            false
+        }
+        fn return_type(&self) -> NullableColType {
+            NullableColType {
+                coltype: ColType::Int,
+                nullable: false,
+            }
+        }
+        fn call(&self, args: &[DataValue]) -> Result<DataValue> {
+            match args[0].get_int() {
+                Some(i) => Ok(DataValue::from(i * 2)),
+                None => miette::bail!("'double' requires an integer"),
+            }
+        }
+    }
+    struct Counter;
+    impl CustomOp for Counter {
+        fn name(&self) -> &'static str {
+            "test_counter"
+        }
+        fn min_arity(&self) -> usize {
+            0
+        }
+        fn vararg(&self) -> bool {
+            false
+        }
+        fn return_type(&self) -> NullableColType {
+            NullableColType {
+                coltype: ColType::Int,
+                nullable: false,
+            }
+        }
+        fn has_side_effect(&self) -> bool {
+            true
+        }
+        fn call(&self, _args: &[DataValue]) -> Result<DataValue> {
+            Ok(DataValue::from(1))
+        }
+    }
+
+    assert!(parse_expressions("double(21)", &Default::default()).is_err());
+    register_custom_op(Double).unwrap();
+    register_custom_op(Counter).unwrap();
+    assert!(register_custom_op(Double).is_err());
+
+    assert_eq!(eval_with("double(21)", &[]).unwrap(), DataValue::from(42));
+    assert_eq!(
+        eval_with("double(x) + 1", &[("x", DataValue::from(4))]).unwrap(),
+        DataValue::from(9)
+    );
+    assert!(eval_with("double('a')", &[]).is_err());
+    assert!(parse_expressions("double(1, 2)", &Default::default()).is_err());
+
+    let mut expr = parse_expressions("double(1 + 1)", &Default::default()).unwrap();
+    expr.partial_eval().unwrap();
+    assert_eq!(expr.get_const(), Some(&DataValue::from(4)));
+    let mut expr = parse_expressions("test_counter()", &Default::default()).unwrap();
+    expr.partial_eval().unwrap();
+    assert_eq!(expr.to_string(), "test_counter");
+
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script("?[a] := a = double(21)", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(42));
+}
+
+#[test]
+fn builtin_ops_listing() {
+    let ops = all_builtin_ops();
+    assert!(ops.len() > 100);
+    let mut names = BTreeMap::new();
+    for info in &ops {
+        assert!(!info.name.is_empty());
+        assert!(
+            names.insert(info.name, info).is_none(),
+            "{} listed twice",
+            info.name
+        );
+        let op = get_op(info.name).unwrap();
+        assert!(op.arity().accepts(info.min_arity));
+        assert_eq!(op.arity().accepts(info.min_arity + 1), info.vararg);
+        assert_eq!(op.has_side_effect(), info.side_effect);
+    }
+    assert_eq!(names["add"].min_arity, 0);
+    assert!(names["add"].vararg);
+    assert_eq!(names["nth_root"].min_arity, 2);
+    assert!(!names["nth_root"].vararg);
+    assert!(names["rand_float"].side_effect);
+    assert!(names["random"].side_effect);
+}
+
+#[test]
+fn custom_ops_cannot_shadow_builtins() {
+    struct Bad(&'static str);
+    impl CustomOp for Bad {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn min_arity(&self) -> usize {
+            0
+        }
+        fn vararg(&self) -> bool {
+            true
+        }
+        fn return_type(&self) -> NullableColType {
+            NullableColType {
+                coltype: ColType::Any,
+                nullable: true,
+            }
+        }
+        fn call(&self, _args: &[DataValue]) -> Result<DataValue> {
+            Ok(DataValue::Null)
+        }
+    }
+    assert!(register_custom_op(Bad("add")).is_err());
+    for name in [
+        "map", "filter", "any", "all", "coalesce", "and", "or", "if", "cond",
+    ] {
+        assert!(register_custom_op(Bad(name)).is_err(), "{name} registered");
+    }
+    assert!(register_custom_op(Bad("Upper")).is_err());
+    assert!(register_custom_op(Bad("")).is_err());
+}
+
+#[test]
+fn arity_checked_in_partial_eval() {
+    let apply = |op, n: i64| Expr::Apply {
+        op,
+        args: (0..n)
+            .map(|i| Expr::Const {
+                val: DataValue::from(i),
+                span: SourceSpan(0, 0),
+            })
+            .collect(),
+        span: SourceSpan(0, 0),
+    };
+    for (op, good) in [(&OP_SUB, 2), (&OP_SQRT, 1)] {
+        assert!(apply(op, good).partial_eval().is_ok());
+        assert!(apply(op, good - 1).partial_eval().is_err());
+        assert!(apply(op, good + 1).partial_eval().is_err());
+    }
+    for n in 0..3 {
+        assert!(apply(&OP_ADD, n).partial_eval().is_ok());
+    }
+
+    assert!(parse_expressions("sub(1)", &Default::default()).is_err());
+    assert!(parse_expressions("sqrt(1, 2)", &Default::default()).is_err());
+}
+
+#[test]
+fn wrong_arity_does_not_panic() {
+    let sub = |n: i64| Expr::Apply {
+        op: &OP_SUB,
+        args: (0..n)
+            .map(|i| Expr::Const {
+                val: DataValue::from(i),
+                span: SourceSpan(0, 0),
+            })
+            .collect(),
+        span: SourceSpan(0, 0),
+    };
+    let x = Symbol::new("x", SourceSpan(0, 0));
+    for n in [1, 3] {
+        let expr = sub(n);
+        assert!(expr.eval([]).is_err());
+        assert!(eval_bytecode(&expr.compile(), [], &mut vec![]).is_err());
+        assert!(expr.extract_bound(&x).is_err());
+    }
+    let bound = Expr::Apply {
+        op: &OP_GT,
+        args: [Expr::Binding {
+            var: x.clone(),
+            tuple_pos: None,
+        }]
+        .into(),
+        span: SourceSpan(0, 0),
+    };
+    assert!(bound.extract_bound(&x).is_err());
+    assert!(parse_expressions("regex_matches('a')", &Default::default()).is_err());
+}
+
+#[test]
+fn explain_expr() {
+    let mut expr = parse_expressions(
+        "let(y, x * 2, if(y > 10 && !is_null(z), try(y / z, 0), -1))",
+        &Default::default(),
+    )
+    .unwrap();
+    let binding_map = BTreeMap::from([
+        (Symbol::new("x", SourceSpan(0, 0)), 0),
+        (Symbol::new("z", SourceSpan(0, 0)), 1),
+    ]);
+    expr.fill_binding_indices(&binding_map).unwrap();
+    assert_eq!(
+        expr.explain(),
+        "\
+Let y @2
+  Apply mul
+    Binding x @0
+    Const 2
+  Cond
+    When
+      And
+        Apply gt
+          Binding y @2
+          Const 10
+        Apply negate
+          Apply is_null
+            Binding z @1
+    Then
+      Try
+        Apply div
+          Binding y @2
+          Binding z @1
+        Const 0
+    When
+      Const true
+    Then
+      Apply minus
+        Const 1
+"
+    );
+    let unresolved = parse_expressions("x + 1", &Default::default()).unwrap();
+    assert_eq!(
+        unresolved.explain(),
+        "Apply add\n  Binding x (unresolved)\n  Const 1\n"
+    );
+}
+
+#[test]
+fn depth_limit() {
+    let nested = |depth: usize| {
+        let mut expr = Expr::Binding {
+            var: Symbol::new("x", SourceSpan(0, 0)),
+            tuple_pos: None,
+        };
+        for _ in 0..depth {
+            expr = Expr::Apply {
+                op: &OP_NEGATE,
+                args: [expr].into(),
+                span: SourceSpan(0, 0),
+            };
+        }
+        expr
+    };
+    let mut expr = nested(10000);
+    let err = expr.partial_eval().unwrap_err();
+    assert!(err.to_string().contains("nested"), "{err}");
+    // including when the deep expression is in a branch that folding may skip
+    let mut expr = Expr::Try {
+        clauses: vec![nested(10000), nested(1)],
+        span: SourceSpan(0, 0),
+    };
+    assert!(expr.partial_eval().is_err());
+    let mut expr = Expr::Cond {
+        clauses: vec![(nested(10000), nested(1))],
+        span: SourceSpan(0, 0),
+    };
+    assert!(expr.partial_eval().is_err());
+
+    assert!(nested(MAX_EXPR_DEPTH).partial_eval().is_ok());
+    assert!(nested(MAX_EXPR_DEPTH + 1).partial_eval().is_err());
+    assert!(nested(100).check_depth(99).is_err());
+    assert!(nested(100).check_depth(100).is_ok());
+}
+
+#[test]
+fn deep_eval() {
+    let one = || Expr::Const {
+        val: DataValue::from(1),
+        span: SourceSpan(0, 0),
+    };
+    let mut expr = one();
+    for _ in 0..50000 {
+        expr = Expr::Apply {
+            op: &OP_ADD,
+            args: [expr, one()].into(),
+            span: SourceSpan(0, 0),
+        };
+    }
+    assert_eq!(expr.eval([]).unwrap(), DataValue::from(50001));
+    let first = expr;
+
+    // errors are caught by `try` and `let` bindings are undone, however deep
+    let mut expr = parse_expressions(
+        "try(let(y, x + 1, y * assert(false)), x)",
+        &Default::default(),
+    )
+    .unwrap();
+    let binding_map = BTreeMap::from([(Symbol::new("x", SourceSpan(0, 0)), 0)]);
+    expr.fill_binding_indices(&binding_map).unwrap();
+    for _ in 0..50000 {
+        expr = Expr::Apply {
+            op: &OP_ADD,
+            args: [one(), expr].into(),
+            span: SourceSpan(0, 0),
+        };
+    }
+    assert_eq!(
+        expr.eval([DataValue::from(10)]).unwrap(),
+        DataValue::from(50010)
+    );
+
+    let mut stack = vec![];
+    assert_eq!(
+        eval_bytecode(&first.compile(), [], &mut stack).unwrap(),
+        DataValue::from(50001)
+    );
+    assert_eq!(
+        eval_bytecode(&expr.compile(), [DataValue::from(10)], &mut stack).unwrap(),
+        DataValue::from(50010)
+    );
+    // deep trees are dropped without recursion
+    drop(first);
+    drop(expr);
+}
+
+#[test]
+#[allow(clippy::mutable_key_type)]
+fn memoized_partial_eval() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    struct CountedSquare;
+    impl CustomOp for CountedSquare {
+        fn name(&self) -> &'static str {
+            "counted_square"
+        }
+        fn min_arity(&self) -> usize {
+            1
+        }
+        fn vararg(&self) -> bool {
+            false
+        }
+        fn return_type(&self) -> NullableColType {
+            NullableColType {
+                coltype: ColType::Int,
+                nullable: false,
+            }
+        }
+        fn call(&self, args: &[DataValue]) -> Result<DataValue> {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            let i = args[0].get_int().unwrap();
+            Ok(DataValue::from(i * i))
+        }
+    }
+    register_custom_op(CountedSquare).unwrap();
+
+    let src = "counted_square(1 + 2) + counted_square(3) * counted_square(x)";
+    let mut expr = parse_expressions(src, &Default::default()).unwrap();
+    expr.partial_eval().unwrap();
+    assert_eq!(CALLS.swap(0, Ordering::SeqCst), 2);
+
+    let mut memo = PartialEvalMemo::default();
+    let mut memoized = parse_expressions(src, &Default::default()).unwrap();
+    memoized.partial_eval_memoized(&mut memo).unwrap();
+    assert_eq!(CALLS.swap(0, Ordering::SeqCst), 1);
+    assert_eq!(memoized.to_string(), expr.to_string());
+    assert_eq!(memoized.to_string(), "add(9, mul(9, counted_square(x)))");
+
+    // the memo is shared across expressions, but side effects are not memoized
+    let mut other =
+        parse_expressions("counted_square(3) + rand_int(3, 3)", &Default::default()).unwrap();
+    other.partial_eval_memoized(&mut memo).unwrap();
+    assert_eq!(CALLS.swap(0, Ordering::SeqCst), 0);
+    assert_eq!(other.to_string(), "add(9, rand_int(3, 3))");
+
+    // the predicates of a rule are folded with a shared memo when compiled
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[x] := x in [5, 20], x > counted_square(2), x < counted_square(2) * 2",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res, vec![vec![DataValue::from(5)]]);
+    assert_eq!(CALLS.swap(0, Ordering::SeqCst), 1);
+}
+
+#[test]
+fn binary_encoding() {
+    let mut expr = B::let_in(
+        "y",
+        B::try_clauses(vec![
+            B::apply("to_int", vec![B::var("s")]).unwrap(),
+            B::constant(DataValue::Null),
+        ])
+        .unwrap(),
+        B::higher_order(
+            "filter",
+            B::var("xs"),
+            "x",
+            B::and(vec![
+                B::or(vec![
+                    B::apply("gt", vec![B::var("x"), B::var("y")]).unwrap(),
+                    B::apply("is_null", vec![B::var("y")]).unwrap(),
+                ]),
+                B::cond(vec![(
+                    B::apply("is_string", vec![B::var("x")]).unwrap(),
+                    B::coalesce(vec![B::var("s"), B::constant("default")]),
+                )])
+                .unwrap(),
+            ]),
+        )
+        .unwrap(),
+    );
+    let binding_map = BTreeMap::from([
+        (Symbol::new("s", SourceSpan(0, 0)), 0),
+        (Symbol::new("xs", SourceSpan(0, 0)), 1),
+    ]);
+    expr.fill_binding_indices(&binding_map).unwrap();
+
+    let encoded = expr.encode();
+    let decoded = Expr::decode(&encoded).unwrap();
+    assert_eq!(decoded, expr);
+    assert_eq!(decoded.encode(), encoded);
+    assert!(encoded.len() * 2 < serde_json::to_vec(&expr).unwrap().len());
+
+    let row = [
+        DataValue::from("2"),
+        DataValue::List(vec![DataValue::from(3)]),
+    ];
+    assert_eq!(decoded.eval(row).unwrap(), DataValue::List(vec![]));
+
+    assert!(Expr::decode(&encoded[..encoded.len() / 2]).is_err());
+    assert!(Expr::decode(b"not an expression").is_err());
+}
+
+#[test]
+fn rewrite_expr() {
+    let expr = parse_expressions(
+        "let(y, 0, [y + 0, cond(x == 0, 0, true, 2), map([0, 1], z -> z * 0)])",
+        &Default::default(),
+    )
+    .unwrap();
+    let mut visited = vec![];
+    let rewritten = expr.rewrite(&mut |e| {
+        visited.push(e.to_string());
+        match e {
+            Expr::Const {
+                val: DataValue::Num(Num::Int(0)),
+                span,
+            } => Expr::Const {
+                val: DataValue::from(1),
+                span,
+            },
+            e => e,
+        }
+    });
+    assert_eq!(
+        rewritten.to_string(),
+        parse_expressions(
+            "let(y, 1, [y + 1, cond(x == 1, 1, true, 2), map([1, 1], z -> z * 1)])",
+            &Default::default()
+        )
+        .unwrap()
+        .to_string()
+    );
+    // children are visited before their parents, and the root last
+    assert_eq!(visited.first().unwrap(), "0");
+    assert_eq!(visited.last().unwrap(), &rewritten.to_string());
+    assert_eq!(visited.iter().filter(|s| *s == "0").count(), 6);
+}
+
+#[test]
+fn expr_interning() {
+    let src = (0..50)
+        .map(|i| format!("x == {} || y > x + 1", i % 5))
+        .join(" || ");
+    let mut expr = parse_expressions(&src, &Default::default()).unwrap();
+    let Expr::Or { args, .. } = &mut expr else {
+        panic!("unexpected {expr}")
+    };
+    assert_eq!(args.len(), 100);
+
+    let mut interner = ExprInterner::default();
+    let interned = mem::take(args)
+        .into_iter()
+        .map(|arg| interner.intern(arg))
+        .collect_vec();
+    // equal fragments at different source positions are shared
+    assert!(Arc::ptr_eq(&interned[0], &interned[10]));
+    assert!(Arc::ptr_eq(&interned[1], &interned[99]));
+    assert!(!Arc::ptr_eq(&interned[0], &interned[2]));
+    assert_eq!(interned.iter().unique_by(|e| Arc::as_ptr(e)).count(), 6);
+    assert_eq!(interned[2].to_string(), "eq(x, 1)");
+}
@@ -12,8 +12,9 @@ use regex::Regex;
 use serde_json::json;
 
 use crate::data::functions::*;
-use crate::data::value::{DataValue, RegexWrapper};
+use crate::data::value::{DataValue, JsonData, RegexWrapper};
 use crate::new_cozo_mem;
+use crate::parse::parse_expressions;
 
 #[test]
 fn test_add() {
@@ -33,6 +34,35 @@ fn test_add() {
     );
 }
 
+#[test]
+fn test_numeric_promotion() {
+    let mixed = [DataValue::from(1), DataValue::from(2.0)];
+    assert_eq!(op_add(&mixed).unwrap(), DataValue::from(3.0));
+    with_numeric_promotion(NumericPromotion::Error, || {
+        assert!(op_add(&mixed).is_err());
+        assert!(op_sub(&mixed).is_err());
+        assert!(op_mul(&mixed).is_err());
+        assert!(op_div(&mixed).is_err());
+        assert!(op_mod(&mixed).is_err());
+        assert_eq!(
+            op_add(&[DataValue::from(1), DataValue::from(2)]).unwrap(),
+            DataValue::from(3)
+        );
+        assert_eq!(
+            op_add(&[DataValue::from(1.0), DataValue::from(2.0)]).unwrap(),
+            DataValue::from(3.0)
+        );
+    });
+    assert_eq!(op_add(&mixed).unwrap(), DataValue::from(3.0));
+
+    // the policy is restored when `f` panics
+    let res = std::panic::catch_unwind(|| {
+        with_numeric_promotion(NumericPromotion::Error, || panic!("in f"))
+    });
+    assert!(res.is_err());
+    assert_eq!(op_add(&mixed).unwrap(), DataValue::from(3.0));
+}
+
 #[test]
 fn test_sub() {
     assert_eq!(
@@ -82,6 +112,26 @@ fn test_div() {
     );
 }
 
+#[test]
+fn test_int_div() {
+    assert_eq!(
+        op_int_div(&[DataValue::from(7), DataValue::from(2)]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        op_int_div(&[DataValue::from(-7), DataValue::from(2)]).unwrap(),
+        DataValue::from(-3)
+    );
+    assert_eq!(
+        op_int_div(&[DataValue::from(7), DataValue::from(-2)]).unwrap(),
+        DataValue::from(-3)
+    );
+    assert!(op_int_div(&[DataValue::from(i64::MIN), DataValue::from(-1)]).is_err());
+    assert!(op_int_div(&[DataValue::from(7), DataValue::from(0)]).is_err());
+    assert!(op_int_div(&[DataValue::from(7.0), DataValue::from(2)]).is_err());
+    assert!(op_int_div(&[DataValue::from(7), DataValue::from(2.0)]).is_err());
+}
+
 #[test]
 fn test_eq_neq() {
     assert_eq!(
@@ -106,6 +156,147 @@ fn test_eq_neq() {
     );
 }
 
+#[test]
+fn test_approx_eq() {
+    assert_eq!(
+        op_approx_eq(&[DataValue::from(0.1 + 0.2), DataValue::from(0.3)]).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_approx_eq(&[DataValue::from(1), DataValue::from(1.001)]).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_approx_eq(&[
+            DataValue::from(1),
+            DataValue::from(1.001),
+            DataValue::from(0.01)
+        ])
+        .unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_approx_eq(&[
+            DataValue::from(1),
+            DataValue::from(1.1),
+            DataValue::from(0.01)
+        ])
+        .unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        op_approx_eq(&[DataValue::Null, DataValue::from(1)]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_approx_eq(&[DataValue::from("1"), DataValue::from(1)]).is_err());
+    assert!(op_approx_eq(&[DataValue::from(1), DataValue::from(1), DataValue::from(-1)]).is_err());
+    assert!(op_approx_eq(&[
+        DataValue::from(1),
+        DataValue::from(1),
+        DataValue::from(0.1),
+        DataValue::Null
+    ])
+    .is_err());
+}
+
+#[test]
+fn test_between() {
+    let between = |x: DataValue, bounds: Option<&str>| {
+        let mut args = vec![x, DataValue::from(1), DataValue::from(2.0)];
+        if let Some(b) = bounds {
+            args.push(DataValue::from(b));
+        }
+        op_between(&args).unwrap()
+    };
+    let t = DataValue::from(true);
+    let f = DataValue::from(false);
+    for (bounds, at_lo, at_hi) in [
+        (None, &t, &t),
+        (Some("[]"), &t, &t),
+        (Some("()"), &f, &f),
+        (Some("[)"), &t, &f),
+        (Some("(]"), &f, &t),
+    ] {
+        assert_eq!(&between(DataValue::from(1.0), bounds), at_lo);
+        assert_eq!(&between(DataValue::from(2), bounds), at_hi);
+        assert_eq!(between(DataValue::from(1.5), bounds), t);
+        assert_eq!(between(DataValue::from(0.5), bounds), f);
+        assert_eq!(between(DataValue::from(3), bounds), f);
+        assert_eq!(between(DataValue::Null, bounds), DataValue::Null);
+    }
+    assert!(op_between(&[
+        DataValue::from(1),
+        DataValue::from(0),
+        DataValue::from(2),
+        DataValue::from("[[")
+    ])
+    .is_err());
+    assert!(op_between(&[DataValue::from("a"), DataValue::from(0), DataValue::from(2)]).is_err());
+    assert!(op_between(&[
+        DataValue::from(1),
+        DataValue::from(0),
+        DataValue::from(2),
+        DataValue::from("[]"),
+        DataValue::from("()")
+    ])
+    .is_err());
+}
+
+#[test]
+fn test_deep_eq() {
+    let deep_eq = |a: DataValue, b: DataValue| op_deep_eq(&[a, b]).unwrap();
+    let t = DataValue::from(true);
+    let f = DataValue::from(false);
+    let json = |j: serde_json::Value| DataValue::Json(JsonData(j));
+
+    // ints and floats are equal by numeric value, at any depth
+    assert_eq!(deep_eq(DataValue::from(1), DataValue::from(1.0)), t);
+    assert_eq!(deep_eq(DataValue::from(1), DataValue::from(1.5)), f);
+    assert_eq!(
+        deep_eq(
+            DataValue::List(vec![
+                DataValue::from(1),
+                DataValue::List(vec![DataValue::from(2.0)])
+            ]),
+            DataValue::List(vec![
+                DataValue::from(1.0),
+                DataValue::List(vec![DataValue::from(2)])
+            ])
+        ),
+        t
+    );
+    assert_eq!(
+        deep_eq(
+            DataValue::List(vec![DataValue::from(1)]),
+            DataValue::List(vec![DataValue::from(1), DataValue::from(2)])
+        ),
+        f
+    );
+    assert_eq!(deep_eq(json(json!([1, 2])), json(json!([1.0, 2]))), t);
+    assert_eq!(deep_eq(json(json!([1, 2])), json(json!([2, 1]))), f);
+
+    let mut forward = serde_json::Map::new();
+    forward.insert("a".to_string(), json!(1));
+    forward.insert("b".to_string(), json!({"c": [1, {"d": 2}]}));
+    let mut backward = serde_json::Map::new();
+    backward.insert("b".to_string(), json!({"c": [1.0, {"d": 2}]}));
+    backward.insert("a".to_string(), json!(1.0));
+    assert_eq!(deep_eq(json(forward.into()), json(backward.into())), t);
+    assert_eq!(
+        deep_eq(json(json!({"a": 1})), json(json!({"a": 1, "b": null}))),
+        f
+    );
+    assert_eq!(deep_eq(json(json!({"a": 1})), json(json!({"b": 1}))), f);
+
+    assert_eq!(deep_eq(DataValue::Null, DataValue::Null), t);
+    assert_eq!(deep_eq(DataValue::Null, DataValue::from(0)), f);
+    assert_eq!(deep_eq(DataValue::from("1"), DataValue::from(1)), f);
+    assert_eq!(
+        deep_eq(json(json!([1])), DataValue::List(vec![DataValue::from(1)])),
+        f
+    );
+}
+
 #[test]
 fn test_list() {
     assert_eq!(op_list(&[]).unwrap(), DataValue::List(vec![]));
@@ -141,6 +332,22 @@ fn test_is_in() {
         op_is_in(&[DataValue::from(3), DataValue::List(vec![])]).unwrap(),
         DataValue::from(false)
     );
+    assert_eq!(
+        op_is_in(&[
+            DataValue::from(2),
+            DataValue::Set([DataValue::from(1), DataValue::from(2)].into())
+        ])
+        .unwrap(),
+        DataValue::from(true)
+    );
+    assert!(op_is_in(&[DataValue::from(1), DataValue::from(1)]).is_err());
+
+    // the search stops at the first match
+    let list = DataValue::List((0..1000).map(DataValue::from).collect());
+    let mut visited = 0;
+    let stream = list.stream().unwrap().inspect(|_| visited += 1);
+    assert!(stream_contains(stream, &DataValue::from(2)));
+    assert_eq!(visited, 3);
 }
 
 #[test]
@@ -248,6 +455,70 @@ fn test_comparators() {
     assert!(op_lt(&[DataValue::Null, DataValue::from(true)]).is_err());
 }
 
+#[test]
+fn test_collation() {
+    let apple = DataValue::from("Apple");
+    let banana = DataValue::from("banana");
+    let lt = |a: &DataValue, b: &DataValue| op_lt(&[a.clone(), b.clone()]).unwrap();
+
+    // by code point, upper case sorts before all lower case
+    assert_eq!(lt(&apple, &banana), DataValue::from(true));
+    assert_eq!(
+        lt(&DataValue::from("apple"), &DataValue::from("Banana")),
+        DataValue::from(false)
+    );
+
+    for collation in [Collation::CaseInsensitive, Collation::Normalized] {
+        with_collation(collation, || {
+            assert_eq!(lt(&apple, &banana), DataValue::from(true));
+            assert_eq!(
+                lt(&DataValue::from("apple"), &DataValue::from("Banana")),
+                DataValue::from(true)
+            );
+            // strings differing only in case are still ordered, and not equal
+            assert_eq!(lt(&apple, &DataValue::from("apple")), DataValue::from(true));
+            assert_eq!(
+                op_eq(&[apple.clone(), DataValue::from("apple")]).unwrap(),
+                DataValue::from(false)
+            );
+        });
+    }
+
+    // full-width letters only sort with their plain forms after normalization
+    let full_width = DataValue::from("\u{FF42}");
+    let c = DataValue::from("c");
+    with_collation(Collation::CaseInsensitive, || {
+        assert_eq!(lt(&full_width, &c), DataValue::from(false));
+    });
+    with_collation(Collation::Normalized, || {
+        assert_eq!(lt(&full_width, &c), DataValue::from(true));
+        assert_eq!(
+            op_ge(&[DataValue::from("B"), DataValue::from("a")]).unwrap(),
+            DataValue::from(true)
+        );
+    });
+    assert_eq!(
+        op_ge(&[DataValue::from("B"), DataValue::from("a")]).unwrap(),
+        DataValue::from(false)
+    );
+
+    // the collation is restored when `f` panics
+    let res = std::panic::catch_unwind(|| with_collation(Collation::Normalized, || panic!("in f")));
+    assert!(res.is_err());
+    assert_eq!(lt(&full_width, &c), DataValue::from(false));
+
+    // comparisons with stored keys are not turned into codepoint range scans
+    let db = new_cozo_mem().unwrap();
+    db.run_script(r"?[k] <- [['a'], ['C']] :create s {k}", Default::default())
+        .unwrap();
+    let query = |q: &str| db.run_script(q, Default::default()).unwrap().into_json()["rows"].clone();
+    assert_eq!(query("?[k] := *s{k}, k > 'b'"), json!([]));
+    with_collation(Collation::CaseInsensitive, || {
+        assert_eq!(query("?[k] := *s{k}, k > 'b'"), json!([["C"]]));
+        assert_eq!(query("?[k] := *s{k}, 'b' >= k"), json!([["a"]]));
+    });
+}
+
 #[test]
 fn test_max_min() {
     assert_eq!(op_max(&[DataValue::from(1),]).unwrap(), DataValue::from(1));
@@ -335,6 +606,12 @@ fn test_minus() {
         op_minus(&[DataValue::from(f64::NEG_INFINITY)]).unwrap(),
         DataValue::from(f64::INFINITY)
     );
+    assert_eq!(
+        op_minus(&[DataValue::from(i64::MAX)]).unwrap(),
+        DataValue::from(-i64::MAX)
+    );
+    let err = op_minus(&[DataValue::from(i64::MIN)]).unwrap_err();
+    assert!(err.to_string().contains("overflowed"));
 }
 
 #[test]
@@ -526,10 +803,113 @@ fn test_inv_trig() {
 
 #[test]
 fn test_pow() {
+    let pow = |a: DataValue, b: DataValue| op_pow(&[a, b]);
+    assert_eq!(
+        pow(DataValue::from(2), DataValue::from(10)).unwrap(),
+        DataValue::from(1024)
+    );
+    assert_eq!(
+        pow(DataValue::from(-3), DataValue::from(3)).unwrap(),
+        DataValue::from(-27)
+    );
     assert_eq!(
-        op_pow(&[DataValue::from(2), DataValue::from(10)]).unwrap(),
+        pow(DataValue::from(3), DataValue::from(39)).unwrap(),
+        DataValue::from(4052555153018976267)
+    );
+    assert_eq!(
+        pow(DataValue::from(0), DataValue::from(0)).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        pow(DataValue::from(2), DataValue::from(-1)).unwrap(),
+        DataValue::from(0.5)
+    );
+    assert_eq!(
+        pow(DataValue::from(2.0), DataValue::from(10)).unwrap(),
         DataValue::from(1024.0)
     );
+    assert_eq!(
+        pow(DataValue::from(4), DataValue::from(0.5)).unwrap(),
+        DataValue::from(2.0)
+    );
+    assert!(pow(DataValue::from(0), DataValue::from(-1)).is_err());
+    assert!(pow(DataValue::from(0.0), DataValue::from(-0.5)).is_err());
+    assert!(pow(DataValue::from(2), DataValue::from(63)).is_err());
+    assert!(pow(DataValue::from(3), DataValue::from(40)).is_err());
+    assert!(pow(DataValue::from(2), DataValue::from(i64::MAX)).is_err());
+    assert_eq!(
+        pow(DataValue::from(1), DataValue::from(i64::MAX)).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        pow(DataValue::from(-1), DataValue::from(i64::MAX)).unwrap(),
+        DataValue::from(-1)
+    );
+}
+
+#[test]
+fn test_round_sig() {
+    let round_sig = |a: DataValue, n: i64| op_round_sig(&[a, DataValue::from(n)]);
+    assert_eq!(
+        round_sig(DataValue::from(123456), 3).unwrap(),
+        DataValue::from(123000.0)
+    );
+    assert_eq!(
+        round_sig(DataValue::from(0.0012345), 2).unwrap(),
+        DataValue::from(0.0012)
+    );
+    assert_eq!(
+        round_sig(DataValue::from(-987.65), 2).unwrap(),
+        DataValue::from(-990.0)
+    );
+    assert_eq!(
+        round_sig(DataValue::from(9.96), 2).unwrap(),
+        DataValue::from(10.0)
+    );
+    assert_eq!(
+        round_sig(DataValue::from(0), 3).unwrap(),
+        DataValue::from(0.0)
+    );
+    assert_eq!(
+        round_sig(DataValue::from(1.5), 20).unwrap(),
+        DataValue::from(1.5)
+    );
+    assert_eq!(round_sig(DataValue::Null, 2).unwrap(), DataValue::Null);
+    assert!(round_sig(DataValue::from(123), 0).is_err());
+    assert!(round_sig(DataValue::from(123), -1).is_err());
+    assert!(op_round_sig(&[DataValue::from(123), DataValue::from(2.0)]).is_err());
+    assert!(round_sig(DataValue::from("123"), 2).is_err());
+}
+
+#[test]
+fn test_nth_root() {
+    let nth_root = |a: DataValue, n: i64| op_nth_root(&[a, DataValue::from(n)]);
+    assert_eq!(
+        nth_root(DataValue::from(-8), 3).unwrap(),
+        DataValue::from(-2.0)
+    );
+    assert_eq!(
+        nth_root(DataValue::from(27.0), 3).unwrap(),
+        DataValue::from(3.0)
+    );
+    assert_eq!(
+        nth_root(DataValue::from(16), 2).unwrap(),
+        DataValue::from(4.0)
+    );
+    assert!(nth_root(DataValue::from(-32), 5)
+        .unwrap()
+        .get_float()
+        .unwrap()
+        .abs_diff_eq(&-2.0, 1e-12));
+    assert_eq!(
+        nth_root(DataValue::from(5), 1).unwrap(),
+        DataValue::from(5.0)
+    );
+    assert!(nth_root(DataValue::from(-4), 2).is_err());
+    assert!(nth_root(DataValue::from(-16), 4).is_err());
+    assert!(nth_root(DataValue::from(8), 0).is_err());
+    assert!(op_nth_root(&[DataValue::from(8), DataValue::from(3.0)]).is_err());
+    assert!(nth_root(DataValue::from("8"), 3).is_err());
 }
 
 #[test]
@@ -538,6 +918,93 @@ fn test_mod() {
         op_mod(&[DataValue::from(-10), DataValue::from(7)]).unwrap(),
         DataValue::from(-3)
     );
+    assert_eq!(
+        op_mod(&[DataValue::from(-7), DataValue::from(3)]).unwrap(),
+        DataValue::from(-1)
+    );
+    assert_eq!(
+        op_mod(&[DataValue::from(7), DataValue::from(-3)]).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        op_mod(&[DataValue::from(-7.5), DataValue::from(2)]).unwrap(),
+        DataValue::from(-1.5)
+    );
+    assert_eq!(
+        op_mod_floor(&[DataValue::from(-7), DataValue::from(3)]).unwrap(),
+        DataValue::from(2)
+    );
+    assert_eq!(
+        op_mod_floor(&[DataValue::from(7), DataValue::from(-3)]).unwrap(),
+        DataValue::from(-2)
+    );
+    assert_eq!(
+        op_mod_floor(&[DataValue::from(7), DataValue::from(3)]).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        op_mod_floor(&[DataValue::from(-6), DataValue::from(3)]).unwrap(),
+        DataValue::from(0)
+    );
+    assert_eq!(
+        op_mod_floor(&[DataValue::from(-7.5), DataValue::from(2)]).unwrap(),
+        DataValue::from(0.5)
+    );
+    assert!(op_mod(&[DataValue::from(1), DataValue::from(0)]).is_err());
+    assert!(op_mod(&[DataValue::from(1.), DataValue::from(0.)]).is_err());
+    assert!(op_mod_floor(&[DataValue::from(1), DataValue::from(0)]).is_err());
+    assert!(op_mod_floor(&[DataValue::from(1), DataValue::from(0.)]).is_err());
+}
+
+#[test]
+fn test_bucket() {
+    let bounds = DataValue::List(vec![
+        DataValue::from(0),
+        DataValue::from(10),
+        DataValue::from(20.5),
+    ]);
+    let bucket = |v: DataValue| op_bucket(&[v, bounds.clone()]).unwrap();
+    assert_eq!(bucket(DataValue::from(15)), DataValue::from(2));
+    assert_eq!(bucket(DataValue::from(-1)), DataValue::from(0));
+    assert_eq!(bucket(DataValue::from(100.0)), DataValue::from(3));
+    // bins include their lower bound
+    assert_eq!(bucket(DataValue::from(0)), DataValue::from(1));
+    assert_eq!(bucket(DataValue::from(10.0)), DataValue::from(2));
+    assert_eq!(bucket(DataValue::from(20.4)), DataValue::from(2));
+    assert_eq!(bucket(DataValue::Null), DataValue::Null);
+    assert_eq!(
+        op_bucket(&[DataValue::from(1), DataValue::List(vec![])]).unwrap(),
+        DataValue::from(0)
+    );
+
+    let unsorted = DataValue::List(vec![DataValue::from(10), DataValue::from(0)]);
+    assert!(op_bucket(&[DataValue::from(5), unsorted]).is_err());
+    assert!(op_bucket(&[DataValue::from("5"), bounds.clone()]).is_err());
+    assert!(op_bucket(&[DataValue::from(5), DataValue::from(5)]).is_err());
+}
+
+#[test]
+fn test_gcd_lcm() {
+    let gcd = |a: i64, b: i64| op_gcd(&[DataValue::from(a), DataValue::from(b)]);
+    let lcm = |a: i64, b: i64| op_lcm(&[DataValue::from(a), DataValue::from(b)]);
+    assert_eq!(gcd(9, 28).unwrap(), DataValue::from(1));
+    assert_eq!(lcm(9, 28).unwrap(), DataValue::from(252));
+    assert_eq!(gcd(12, 18).unwrap(), DataValue::from(6));
+    assert_eq!(lcm(12, 18).unwrap(), DataValue::from(36));
+    // absolute values are used
+    assert_eq!(gcd(-12, 18).unwrap(), DataValue::from(6));
+    assert_eq!(lcm(12, -18).unwrap(), DataValue::from(36));
+    assert_eq!(gcd(0, 0).unwrap(), DataValue::from(0));
+    assert_eq!(gcd(0, -5).unwrap(), DataValue::from(5));
+    assert_eq!(lcm(0, 5).unwrap(), DataValue::from(0));
+
+    assert!(lcm(i64::MAX, i64::MAX - 1).is_err());
+    assert!(gcd(i64::MIN, 0).is_err());
+    assert_eq!(
+        op_gcd(&[DataValue::Null, DataValue::from(1)]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_lcm(&[DataValue::from(1.0), DataValue::from(1)]).is_err());
 }
 
 #[test]
@@ -558,6 +1025,49 @@ fn test_boolean() {
     );
 }
 
+#[test]
+fn test_xor() {
+    let t = DataValue::from(true);
+    let f = DataValue::from(false);
+    let n = DataValue::Null;
+    for (a, b, expected) in [
+        (&t, &t, &f),
+        (&t, &f, &t),
+        (&t, &n, &n),
+        (&f, &t, &t),
+        (&f, &f, &f),
+        (&f, &n, &n),
+        (&n, &t, &n),
+        (&n, &f, &n),
+        (&n, &n, &n),
+    ] {
+        assert_eq!(&op_xor(&[a.clone(), b.clone()]).unwrap(), expected);
+    }
+    assert!(op_xor(&[t, DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn test_implies() {
+    let t = DataValue::from(true);
+    let f = DataValue::from(false);
+    let n = DataValue::Null;
+    for (a, b, expected) in [
+        (&t, &t, &t),
+        (&t, &f, &f),
+        (&t, &n, &n),
+        (&f, &t, &t),
+        (&f, &f, &t),
+        (&f, &n, &t),
+        (&n, &t, &t),
+        (&n, &f, &n),
+        (&n, &n, &n),
+    ] {
+        assert_eq!(&op_implies(&[a.clone(), b.clone()]).unwrap(), expected);
+    }
+    assert!(op_implies(&[t, DataValue::from(1)]).is_err());
+    assert!(op_implies(&[DataValue::from(1), f]).is_err());
+}
+
 #[test]
 fn test_bits() {
     assert_eq!(
@@ -590,6 +1100,38 @@ fn test_bits() {
     );
 }
 
+#[test]
+fn test_bit_counts() {
+    let int_op = |op: fn(&[DataValue]) -> miette::Result<DataValue>, i: i64| {
+        op(&[DataValue::from(i)]).unwrap()
+    };
+    assert_eq!(int_op(op_popcount, 7), DataValue::from(3));
+    assert_eq!(int_op(op_popcount, 0), DataValue::from(0));
+    assert_eq!(int_op(op_popcount, -1), DataValue::from(64));
+    assert_eq!(int_op(op_trailing_zeros, 1 << 10), DataValue::from(10));
+    assert_eq!(int_op(op_trailing_zeros, 0), DataValue::from(64));
+    assert_eq!(int_op(op_leading_zeros, 1), DataValue::from(63));
+    assert_eq!(int_op(op_leading_zeros, -1), DataValue::from(0));
+
+    let bytes = [DataValue::Bytes(vec![
+        0b0000_0000,
+        0b0010_1000,
+        0b0000_0000,
+    ])];
+    assert_eq!(op_popcount(&bytes).unwrap(), DataValue::from(2));
+    assert_eq!(op_leading_zeros(&bytes).unwrap(), DataValue::from(10));
+    assert_eq!(op_trailing_zeros(&bytes).unwrap(), DataValue::from(11));
+    assert_eq!(
+        op_trailing_zeros(&[DataValue::Bytes(vec![0, 0])]).unwrap(),
+        DataValue::from(16)
+    );
+
+    assert_eq!(op_popcount(&[DataValue::Null]).unwrap(), DataValue::Null);
+    assert!(op_popcount(&[DataValue::from(1.0)]).is_err());
+    assert!(op_leading_zeros(&[DataValue::from("1")]).is_err());
+    assert!(op_trailing_zeros(&[DataValue::from(true)]).is_err());
+}
+
 #[test]
 fn test_pack_bits() {
     assert_eq!(
@@ -648,6 +1190,57 @@ fn test_str_includes() {
     );
 }
 
+#[test]
+fn test_byte_slice() {
+    let slice = |s: &str, start: i64, end: i64| {
+        op_byte_slice(&[
+            DataValue::from(s),
+            DataValue::from(start),
+            DataValue::from(end),
+        ])
+        .unwrap()
+    };
+    assert_eq!(slice("abcdef", 1, 4), DataValue::from("bcd"));
+    // 'é' occupies bytes 1 and 2, '日' bytes 4 to 6
+    let s = "héx日y";
+    assert_eq!(slice(s, 0, 3), DataValue::from("hé"));
+    assert_eq!(slice(s, 2, 8), DataValue::from("x日y"));
+    assert_eq!(slice(s, 1, 6), DataValue::from("éx"));
+    assert_eq!(slice(s, 5, 6), DataValue::from(""));
+    // out of range offsets are clamped
+    assert_eq!(slice(s, -5, 100), DataValue::from(s));
+    assert_eq!(slice(s, 6, 2), DataValue::from(""));
+
+    assert_eq!(
+        op_byte_slice(&[DataValue::Null, DataValue::from(0), DataValue::from(1)]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_byte_slice(&[
+        DataValue::from("abc"),
+        DataValue::from(0.0),
+        DataValue::from(1)
+    ])
+    .is_err());
+}
+
+#[test]
+fn test_index_of() {
+    let index_of =
+        |h: &str, n: &str| op_index_of(&[DataValue::from(h), DataValue::from(n)]).unwrap();
+    assert_eq!(index_of("abcdef", "cd"), DataValue::from(2));
+    assert_eq!(index_of("abcabc", "bc"), DataValue::from(1));
+    assert_eq!(index_of("abcdef", "x"), DataValue::from(-1));
+    assert_eq!(index_of("abcdef", ""), DataValue::from(0));
+    // character offsets, not byte offsets
+    assert_eq!(index_of("héllo wörld", "wö"), DataValue::from(6));
+    assert_eq!(index_of("日本語", "語"), DataValue::from(2));
+    assert_eq!(
+        op_index_of(&[DataValue::Null, DataValue::from("a")]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_index_of(&[DataValue::from("a"), DataValue::from(1)]).is_err());
+}
+
 #[test]
 fn test_casings() {
     assert_eq!(
@@ -704,6 +1297,40 @@ fn test_starts_ends_with() {
     );
 }
 
+#[test]
+fn test_edit_distance() {
+    let dist = |a: &str, b: &str| op_edit_distance(&[DataValue::from(a), DataValue::from(b)]);
+    assert_eq!(dist("kitten", "kitten").unwrap(), DataValue::from(0));
+    assert_eq!(dist("kitten", "sitten").unwrap(), DataValue::from(1));
+    assert_eq!(dist("kitten", "sitting").unwrap(), DataValue::from(3));
+    assert_eq!(dist("", "abc").unwrap(), DataValue::from(3));
+    // characters, not bytes
+    assert_eq!(dist("naïve", "naive").unwrap(), DataValue::from(1));
+    assert_eq!(dist("日本語", "日本").unwrap(), DataValue::from(1));
+
+    let capped = |a: &str, b: &str, cap: i64| {
+        op_edit_distance(&[DataValue::from(a), DataValue::from(b), DataValue::from(cap)])
+    };
+    assert_eq!(capped("kitten", "sitting", 2).unwrap(), DataValue::from(2));
+    assert_eq!(capped("kitten", "sitting", 5).unwrap(), DataValue::from(3));
+    assert_eq!(capped("a", "abcdefgh", 3).unwrap(), DataValue::from(3));
+    assert_eq!(capped("abc", "xyz", 0).unwrap(), DataValue::from(0));
+    assert!(capped("abc", "xyz", -1).is_err());
+
+    assert_eq!(
+        op_edit_distance(&[DataValue::Null, DataValue::from("a")]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_edit_distance(&[DataValue::from(1), DataValue::from("a")]).is_err());
+    assert!(op_edit_distance(&[
+        DataValue::from("a"),
+        DataValue::from("b"),
+        DataValue::from(1),
+        DataValue::from(1)
+    ])
+    .is_err());
+}
+
 #[test]
 fn test_regex() {
     assert_eq!(
@@ -942,6 +1569,25 @@ fn test_length() {
         op_length(&[DataValue::Bytes([].into())]).unwrap(),
         DataValue::from(0)
     );
+    assert_eq!(
+        op_length(&[DataValue::Str("ü日本".into())]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        op_length(&[DataValue::List(vec![DataValue::Null, DataValue::from(1)])]).unwrap(),
+        DataValue::from(2)
+    );
+    assert_eq!(
+        op_length(&[DataValue::Json(JsonData(json!({"a": 1, "b": null})))]).unwrap(),
+        DataValue::from(2)
+    );
+    assert_eq!(
+        op_length(&[DataValue::Json(JsonData(json!([1, 2, 3])))]).unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(op_length(&[DataValue::Null]).unwrap(), DataValue::Null);
+    assert!(op_length(&[DataValue::from(1)]).is_err());
+    assert!(op_length(&[DataValue::Json(JsonData(json!(1)))]).is_err());
 }
 
 #[test]
@@ -953,6 +1599,38 @@ fn test_unicode_normalize() {
     )
 }
 
+#[test]
+fn test_clean_text() {
+    let clean =
+        |s: &str, mode: &str| op_clean_text(&[DataValue::from(s), DataValue::from(mode)]).unwrap();
+    let dirty = "a\tb\u{0}c\r\n";
+    assert_eq!(
+        op_clean_text(&[DataValue::from(dirty)]).unwrap(),
+        DataValue::from("abc")
+    );
+    assert_eq!(clean(dirty, "strip"), DataValue::from("abc"));
+    assert_eq!(clean(dirty, "space"), DataValue::from("a b c  "));
+
+    // 'e' followed by a combining acute accent composes to a single character
+    let decomposed = "caf\u{65}\u{301}\u{7}";
+    assert_eq!(clean(decomposed, "strip"), DataValue::from("cafe\u{301}"));
+    assert_eq!(clean(decomposed, "strip_nfc"), DataValue::from("caf\u{e9}"));
+    assert_eq!(
+        clean(decomposed, "space_nfc"),
+        DataValue::from("caf\u{e9} ")
+    );
+
+    assert_eq!(op_clean_text(&[DataValue::Null]).unwrap(), DataValue::Null);
+    assert!(op_clean_text(&[DataValue::from(dirty), DataValue::from("nfc")]).is_err());
+    assert!(op_clean_text(&[DataValue::from(1)]).is_err());
+    assert!(op_clean_text(&[
+        DataValue::Null,
+        DataValue::from("strip"),
+        DataValue::from("space")
+    ])
+    .is_err());
+}
+
 #[test]
 fn test_sort_reverse() {
     assert_eq!(
@@ -1165,6 +1843,248 @@ fn test_get() {
     );
 }
 
+#[test]
+fn test_maybe_get_on_mixed_data() {
+    // strict access errors on the wrong type, safe access gives null
+    assert!(op_get(&[DataValue::from(1), DataValue::from(0)]).is_err());
+    assert_eq!(
+        op_maybe_get(&[DataValue::from(1), DataValue::from(0)]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_maybe_get(&[DataValue::from(1), DataValue::from("a")]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_maybe_get(&[DataValue::Null, DataValue::from(0)]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_maybe_get(&[DataValue::List(vec![]), DataValue::from(1.5)]).is_err());
+
+    let rows = DataValue::List(vec![
+        DataValue::Json(JsonData(json!({"tags": ["x", {"k": 1}]}))),
+        DataValue::Json(JsonData(json!({"tags": "none"}))),
+        DataValue::from(3),
+        DataValue::List(vec![DataValue::from("a")]),
+    ]);
+    let chain = |i: i64| {
+        let row = op_maybe_get(&[rows.clone(), DataValue::from(i)]).unwrap();
+        let tags = op_maybe_get(&[row, DataValue::from("tags")]).unwrap();
+        let tag = op_maybe_get(&[tags, DataValue::from(-1)]).unwrap();
+        op_maybe_get(&[tag, DataValue::from("k")]).unwrap()
+    };
+    assert_eq!(chain(0), DataValue::from(1));
+    assert_eq!(chain(1), DataValue::Null);
+    assert_eq!(chain(2), DataValue::Null);
+    assert_eq!(chain(3), DataValue::Null);
+    assert_eq!(chain(4), DataValue::Null);
+}
+
+#[test]
+fn test_reduce() {
+    let reduce = |l: Vec<DataValue>, init: DataValue, name: &str| {
+        op_reduce(&[DataValue::List(l), init, DataValue::from(name)])
+    };
+    let nums = || vec![DataValue::from(1), DataValue::from(2), DataValue::from(3)];
+    assert_eq!(
+        reduce(nums(), DataValue::from(0), "add").unwrap(),
+        DataValue::from(6)
+    );
+    assert_eq!(
+        reduce(nums(), DataValue::from(1), "mul").unwrap(),
+        DataValue::from(6)
+    );
+    assert_eq!(
+        reduce(nums(), DataValue::from(10), "sub").unwrap(),
+        DataValue::from(4)
+    );
+    assert_eq!(
+        reduce(vec![], DataValue::from("init"), "add").unwrap(),
+        DataValue::from("init")
+    );
+    assert!(reduce(nums(), DataValue::from(0), "no_such_function").is_err());
+    assert!(reduce(nums(), DataValue::from(0), "negate").is_err());
+    assert!(reduce(nums(), DataValue::from(0), "rand_int").is_err());
+    assert!(op_reduce(&[
+        DataValue::from(1),
+        DataValue::from(0),
+        DataValue::from("add")
+    ])
+    .is_err());
+    // sets are reduced in ascending order
+    assert_eq!(
+        op_reduce(&[
+            DataValue::Set(nums().into_iter().collect()),
+            DataValue::from(10),
+            DataValue::from("sub")
+        ])
+        .unwrap(),
+        DataValue::from(4)
+    );
+}
+
+#[test]
+fn test_zip() {
+    let ints = |v: &[i64]| DataValue::List(v.iter().map(|i| DataValue::from(*i)).collect());
+    let zip = |a: DataValue, b: DataValue| op_zip(&[a, b]);
+    assert_eq!(
+        zip(ints(&[1, 2]), ints(&[3, 4])).unwrap(),
+        DataValue::List(vec![ints(&[1, 3]), ints(&[2, 4])])
+    );
+    assert_eq!(
+        zip(ints(&[1, 2, 5]), ints(&[3])).unwrap(),
+        DataValue::List(vec![ints(&[1, 3])])
+    );
+    assert_eq!(zip(ints(&[]), ints(&[1])).unwrap(), ints(&[]));
+    assert_eq!(zip(ints(&[]), ints(&[])).unwrap(), ints(&[]));
+    assert_eq!(zip(DataValue::Null, ints(&[1])).unwrap(), DataValue::Null);
+    assert!(zip(ints(&[1]), DataValue::from(1)).is_err());
+}
+
+#[test]
+fn test_has_key() {
+    let obj = DataValue::Json(JsonData(json!({"a": null, "b": 1})));
+    let has_key = |d: &DataValue, k: DataValue| op_has_key(&[d.clone(), k]);
+    // a present key mapping to null is still present
+    assert_eq!(
+        has_key(&obj, DataValue::from("a")).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        op_maybe_get(&[obj.clone(), DataValue::from("a")]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        has_key(&obj, DataValue::from("b")).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        has_key(&obj, DataValue::from("c")).unwrap(),
+        DataValue::from(false)
+    );
+    assert_eq!(
+        has_key(&DataValue::Null, DataValue::from("a")).unwrap(),
+        DataValue::Null
+    );
+    assert!(has_key(&obj, DataValue::from(1)).is_err());
+    assert!(has_key(&DataValue::List(vec![]), DataValue::from("a")).is_err());
+
+    let mut folded = parse_expressions(
+        r#"has_key(from_json('{"a": null}'), 'a')"#,
+        &Default::default(),
+    )
+    .unwrap();
+    folded.partial_eval().unwrap();
+    assert_eq!(folded.get_const(), Some(&DataValue::from(true)));
+}
+
+#[test]
+fn test_value_in() {
+    let obj = DataValue::Json(JsonData(json!({"a": 1, "b": "x", "c": [1, 2]})));
+    let with_null = DataValue::Json(JsonData(json!({"a": 1, "b": null})));
+    let value_in = |needle: DataValue, d: &DataValue| op_value_in(&[needle, d.clone()]);
+    assert_eq!(
+        value_in(DataValue::from("x"), &obj).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        value_in(DataValue::from(1), &obj).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        value_in(DataValue::Json(JsonData(json!([1, 2]))), &obj).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        value_in(DataValue::from("a"), &obj).unwrap(),
+        DataValue::from(false)
+    );
+    // nulls
+    assert_eq!(
+        value_in(DataValue::from(1), &with_null).unwrap(),
+        DataValue::from(true)
+    );
+    assert_eq!(
+        value_in(DataValue::from(2), &with_null).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(value_in(DataValue::Null, &obj).unwrap(), DataValue::Null);
+    assert_eq!(
+        value_in(DataValue::from(1), &DataValue::Null).unwrap(),
+        DataValue::Null
+    );
+    assert!(value_in(DataValue::from(1), &DataValue::List(vec![])).is_err());
+}
+
+#[test]
+fn test_project_and_omit() {
+    let obj = DataValue::Json(JsonData(json!({"a": 1, "b": [2], "c": null})));
+    let keys = |ks: &[&str]| DataValue::List(ks.iter().map(|k| DataValue::from(*k)).collect());
+    assert_eq!(
+        op_project(&[obj.clone(), keys(&["a", "c"])]).unwrap(),
+        DataValue::Json(JsonData(json!({"a": 1, "c": null})))
+    );
+    // missing keys are ignored
+    assert_eq!(
+        op_project(&[obj.clone(), keys(&["b", "d"])]).unwrap(),
+        DataValue::Json(JsonData(json!({"b": [2]})))
+    );
+    assert_eq!(
+        op_project(&[obj.clone(), keys(&["d"])]).unwrap(),
+        DataValue::Json(JsonData(json!({})))
+    );
+    assert_eq!(
+        op_omit(&[obj.clone(), keys(&["a", "c", "d"])]).unwrap(),
+        DataValue::Json(JsonData(json!({"b": [2]})))
+    );
+    assert_eq!(op_omit(&[obj.clone(), keys(&[])]).unwrap(), obj.clone());
+    assert_eq!(
+        op_project(&[DataValue::Null, keys(&["a"])]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_omit(&[DataValue::Null, keys(&["a"])]).unwrap(),
+        DataValue::Null
+    );
+    assert!(op_project(&[obj.clone(), DataValue::from("a")]).is_err());
+    assert!(op_project(&[obj.clone(), DataValue::List(vec![DataValue::from(1)])]).is_err());
+    assert!(op_omit(&[DataValue::List(vec![]), keys(&["a"])]).is_err());
+}
+
+#[test]
+fn test_get_path() {
+    let data = DataValue::Json(JsonData(json!({"a": {"b": [{"c": 1}, {"c": [2, 3]}]}})));
+    let get_path = |val: &DataValue, path: &str| op_get_path(&[val.clone(), DataValue::from(path)]);
+    assert_eq!(get_path(&data, "a.b[1].c[0]").unwrap(), DataValue::from(2));
+    assert_eq!(
+        get_path(&data, "a.b[-1].c[-1]").unwrap(),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        get_path(&data, "a.b[0]").unwrap(),
+        DataValue::Json(JsonData(json!({"c": 1})))
+    );
+    assert_eq!(get_path(&data, "").unwrap(), data);
+    assert_eq!(get_path(&data, "a.x.c").unwrap(), DataValue::Null);
+    assert_eq!(get_path(&data, "a.b[2]").unwrap(), DataValue::Null);
+    // indexing into a non-list gives null
+    assert_eq!(get_path(&data, "a[0]").unwrap(), DataValue::Null);
+    assert_eq!(get_path(&data, "a.b[0].c[0]").unwrap(), DataValue::Null);
+    assert_eq!(
+        get_path(&DataValue::from(1), "[0]").unwrap(),
+        DataValue::Null
+    );
+
+    let list = DataValue::List(vec![DataValue::from(1), data]);
+    assert_eq!(get_path(&list, "[1].a.b[0].c").unwrap(), DataValue::from(1));
+    assert_eq!(get_path(&list, "[0].a").unwrap(), DataValue::Null);
+
+    for path in ["a..b", ".a", "a.", "a[", "a[x]", "a[0]b", "a.[0]"] {
+        assert!(get_path(&list, path).is_err(), "{path}");
+    }
+    assert!(op_get_path(&[list, DataValue::from(0)]).is_err());
+}
+
 #[test]
 fn test_slice() {
     assert!(op_slice(&[
@@ -1218,6 +2138,110 @@ fn test_to_string() {
     );
 }
 
+#[test]
+fn test_json_conversion() {
+    let nested = DataValue::List(vec![
+        DataValue::from(1),
+        DataValue::from(2.5),
+        DataValue::Null,
+        DataValue::List(vec![DataValue::from(true), DataValue::from("a \"b\"")]),
+        DataValue::Json(JsonData(json!({"x": [1, {"y": null}]}))),
+    ]);
+    let text = op_to_json(std::slice::from_ref(&nested)).unwrap();
+    assert_eq!(
+        text,
+        DataValue::from(r#"[1,2.5,null,[true,"a \"b\""],{"x":[1,{"y":null}]}]"#)
+    );
+    assert_eq!(op_from_json(&[text]).unwrap(), nested);
+    assert_eq!(
+        op_to_json(&[DataValue::from("null")]).unwrap(),
+        DataValue::from(r#""null""#)
+    );
+    assert_eq!(
+        op_from_json(&[DataValue::from(r#""null""#)]).unwrap(),
+        DataValue::from("null")
+    );
+    assert_eq!(op_from_json(&[DataValue::Null]).unwrap(), DataValue::Null);
+    assert!(op_from_json(&[DataValue::from("[1, 2")]).is_err());
+    assert!(op_from_json(&[DataValue::from("{'a': 1}")]).is_err());
+    assert!(op_from_json(&[DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn test_format() {
+    let format = |template: &str, vals: &[DataValue]| {
+        let mut args = vec![DataValue::from(template)];
+        args.extend_from_slice(vals);
+        op_format(&args)
+    };
+    assert_eq!(
+        format(
+            "{} has {} items: {}",
+            &[
+                DataValue::from("cart"),
+                DataValue::from(2),
+                DataValue::List(vec![DataValue::from(1.5), DataValue::Null])
+            ]
+        )
+        .unwrap(),
+        DataValue::from("cart has 2 items: [1.5,null]")
+    );
+    assert_eq!(format("nothing", &[]).unwrap(), DataValue::from("nothing"));
+    assert_eq!(
+        format("{}", &[DataValue::Null]).unwrap(),
+        DataValue::from("null")
+    );
+    assert_eq!(
+        format("{{{}}} {{}}", &[DataValue::from(true)]).unwrap(),
+        DataValue::from("{true} {}")
+    );
+    assert!(format("{} and {}", &[DataValue::from(1)]).is_err());
+    assert!(format("{}", &[DataValue::from(1), DataValue::from(2)]).is_err());
+    assert!(format("{", &[]).is_err());
+    assert!(format("a } b", &[]).is_err());
+    assert!(op_format(&[DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn test_hash() {
+    let hash = |v: DataValue| op_hash(&[v]).unwrap().get_int().unwrap();
+    let nested = || {
+        DataValue::List(vec![
+            DataValue::from("a"),
+            DataValue::Json(JsonData(json!({"x": 1, "y": [true, null]}))),
+        ])
+    };
+    assert_eq!(hash(nested()), hash(nested()));
+    assert_eq!(
+        hash(DataValue::Json(JsonData(
+            serde_json::from_str(r#"{"y": [true, null], "x": 1}"#).unwrap()
+        ))),
+        hash(DataValue::Json(JsonData(
+            json!({"x": 1, "y": [true, null]})
+        )))
+    );
+    // fixed across runs
+    assert_eq!(hash(DataValue::Null), hash(DataValue::Null));
+    assert_eq!(hash(DataValue::Null), 5473300916088427717);
+
+    let values = [
+        DataValue::Null,
+        DataValue::from(false),
+        DataValue::from(1),
+        DataValue::from(1.),
+        DataValue::from("1"),
+        DataValue::Bytes(vec![1]),
+        DataValue::List(vec![DataValue::from(1)]),
+        DataValue::List(vec![DataValue::from(1), DataValue::Null]),
+        nested(),
+    ];
+    for (i, a) in values.iter().enumerate() {
+        for b in &values[i + 1..] {
+            assert_ne!(hash(a.clone()), hash(b.clone()), "{a} {b}");
+        }
+    }
+}
+
 #[test]
 fn test_to_unity() {
     assert_eq!(op_to_unity(&[DataValue::Null]).unwrap(), DataValue::from(0));
@@ -1305,6 +2329,28 @@ fn test_to_float() {
     );
 }
 
+#[test]
+fn test_parse_numbers() {
+    let parse_int = |s: &str| op_parse_int(&[DataValue::from(s)]);
+    let parse_float = |s: &str| op_parse_float(&[DataValue::from(s)]);
+    assert_eq!(parse_int("42").unwrap(), DataValue::from(42));
+    assert_eq!(parse_int("-42").unwrap(), DataValue::from(-42));
+    assert_eq!(parse_int(" +7\n").unwrap(), DataValue::from(7));
+    assert_eq!(parse_float("2.5").unwrap(), DataValue::from(2.5));
+    assert_eq!(parse_float("\t-1e3 ").unwrap(), DataValue::from(-1000.));
+    assert_eq!(parse_float("3").unwrap(), DataValue::from(3.));
+    for s in ["", "1.5", "12a", "1 2", "--1"] {
+        assert!(parse_int(s).is_err(), "{s}");
+    }
+    for s in ["", "1.5.2", "abc", "1 .5"] {
+        assert!(parse_float(s).is_err(), "{s}");
+    }
+    assert_eq!(op_parse_int(&[DataValue::Null]).unwrap(), DataValue::Null);
+    assert_eq!(op_parse_float(&[DataValue::Null]).unwrap(), DataValue::Null);
+    assert!(op_parse_int(&[DataValue::from(1)]).is_err());
+    assert!(op_parse_float(&[DataValue::from(1.)]).is_err());
+}
+
 #[test]
 fn test_rand() {
     let n = op_rand_float(&[]).unwrap().get_float().unwrap();
@@ -1333,6 +2379,35 @@ fn test_rand() {
         op_rand_choose(&[DataValue::List(vec![DataValue::from(123)])]).unwrap(),
         DataValue::from(123)
     );
+    assert!(op_rand_int(&[DataValue::from(2), DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn test_seeded_rand() {
+    let draw = || {
+        (0..10)
+            .map(|_| {
+                vec![
+                    op_rand_float(&[]).unwrap(),
+                    op_rand_int(&[DataValue::from(0), DataValue::from(1000000)]).unwrap(),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+    let unseeded = draw();
+    assert_ne!(unseeded[0], unseeded[1]);
+    assert_eq!(with_seeded_rng(42, draw), with_seeded_rng(42, draw));
+    assert_ne!(with_seeded_rng(42, draw), with_seeded_rng(43, draw));
+    // the node of a v1 uuid is random, but not its timestamp
+    let node = || match op_rand_uuid_v1(&[]).unwrap() {
+        DataValue::Uuid(id) => id.0.as_bytes()[10..].to_vec(),
+        v => panic!("unexpected {v:?}"),
+    };
+    assert_eq!(with_seeded_rng(42, node), with_seeded_rng(42, node));
+    assert_ne!(with_seeded_rng(42, node), with_seeded_rng(43, node));
+    assert!(OP_RAND_FLOAT.has_side_effect());
+    assert!(OP_NOW.has_side_effect());
+    assert!(!OP_ADD.has_side_effect());
 }
 
 #[test]
@@ -1458,6 +2533,68 @@ fn test_coalesce() {
     assert_eq!(res[0][0], DataValue::from(2));
 }
 
+#[test]
+fn test_coalesce_empty() {
+    assert_eq!(op_coalesce_empty(&[]).unwrap(), DataValue::Null);
+    assert_eq!(
+        op_coalesce_empty(&[DataValue::Null, DataValue::from(""), DataValue::from("a")]).unwrap(),
+        DataValue::from("a")
+    );
+    assert_eq!(
+        op_coalesce_empty(&[DataValue::from(""), DataValue::Null, DataValue::from(1)]).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        op_coalesce_empty(&[DataValue::from("a"), DataValue::Null, DataValue::from("")]).unwrap(),
+        DataValue::from("a")
+    );
+    assert_eq!(
+        op_coalesce_empty(&[DataValue::from(""), DataValue::Null]).unwrap(),
+        DataValue::Null
+    );
+    // other falsy values are kept
+    assert_eq!(
+        op_coalesce_empty(&[DataValue::from(""), DataValue::from(false)]).unwrap(),
+        DataValue::from(false)
+    );
+    // plain coalesce still returns the empty string
+    assert_eq!(
+        op_coalesce(&[DataValue::Null, DataValue::from(""), DataValue::from("a")]).unwrap(),
+        DataValue::from("")
+    );
+}
+
+#[test]
+fn test_null_if_empty() {
+    let null_if_empty = |v: DataValue| op_null_if_empty(&[v]).unwrap();
+    assert_eq!(null_if_empty(DataValue::from("")), DataValue::Null);
+    assert_eq!(null_if_empty(DataValue::List(vec![])), DataValue::Null);
+    assert_eq!(
+        null_if_empty(DataValue::Set(Default::default())),
+        DataValue::Null
+    );
+    assert_eq!(
+        null_if_empty(DataValue::Json(JsonData(json!({})))),
+        DataValue::Null
+    );
+    assert_eq!(
+        null_if_empty(DataValue::Json(JsonData(json!([])))),
+        DataValue::Null
+    );
+    assert_eq!(null_if_empty(DataValue::Null), DataValue::Null);
+
+    let non_empty = [
+        DataValue::from("a"),
+        DataValue::List(vec![DataValue::Null]),
+        DataValue::Json(JsonData(json!({"a": 1}))),
+        DataValue::from(0),
+        DataValue::from(false),
+    ];
+    for v in non_empty {
+        assert_eq!(null_if_empty(v.clone()), v);
+    }
+}
+
 #[test]
 fn test_range() {
     let db = new_cozo_mem().unwrap();
@@ -1476,4 +2613,34 @@ fn test_range() {
         .unwrap()
         .into_json();
     assert_eq!(res["rows"][0][0], json!([15, 13, 11, 9, 7, 5]));
-}
\ No newline at end of file
+
+    let range =
+        |args: &[i64]| op_int_range(&args.iter().map(|i| DataValue::from(*i)).collect::<Vec<_>>());
+    let ints = |v: &[i64]| DataValue::List(v.iter().map(|i| DataValue::from(*i)).collect());
+    assert_eq!(range(&[0, 10, 3]).unwrap(), ints(&[0, 3, 6, 9]));
+    assert_eq!(range(&[10, 0, -3]).unwrap(), ints(&[10, 7, 4, 1]));
+    assert_eq!(range(&[3, 3]).unwrap(), ints(&[]));
+    assert_eq!(range(&[3, 3, -1]).unwrap(), ints(&[]));
+    assert_eq!(range(&[0, 3, -1]).unwrap(), ints(&[]));
+    assert!(range(&[0, 3, 0]).is_err());
+    assert!(range(&[3, 0, 0]).is_err());
+    assert_eq!(
+        range(&[i64::MAX - 1, i64::MAX, 10]).unwrap(),
+        ints(&[i64::MAX - 1])
+    );
+    assert!(range(&[i64::MIN, i64::MAX]).is_err());
+    with_max_int_range_len(1000, || {
+        assert!(range(&[1000]).is_ok());
+        assert!(range(&[0, 2002, 2]).is_err());
+        assert!(range(&[1001]).is_err());
+    });
+    assert!(range(&[1001]).is_ok());
+
+    // the limit is restored when `f` panics
+    let res = std::panic::catch_unwind(|| with_max_int_range_len(0, || panic!("in f")));
+    assert!(res.is_err());
+    assert_eq!(
+        MAX_INT_RANGE_LEN.with(|l| l.get()),
+        DEFAULT_MAX_INT_RANGE_LEN
+    );
+}
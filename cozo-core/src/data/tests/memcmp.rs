@@ -7,10 +7,13 @@
  *
  */
 
+use std::cmp::Reverse;
+
+use serde_json::json;
 use uuid::Uuid;
 
 use crate::data::memcmp::{decode_bytes, MemCmpEncoder};
-use crate::data::value::{DataValue, Num, UuidWrapper};
+use crate::data::value::{DataValue, JsonData, Num, UuidWrapper, Validity, ValidityTs};
 
 #[test]
 fn encode_decode_num() {
@@ -61,6 +64,88 @@ fn test_encode_decode_uuid() {
     assert!(remaining.is_empty());
 }
 
+#[test]
+fn test_encode_decode_json() {
+    let json = DataValue::List(vec![
+        DataValue::Json(JsonData(json!({"a": [1, 2.5, null], "b": {"c": "d"}}))),
+        DataValue::from(1),
+    ]);
+    let mut encoder = vec![];
+    encoder.encode_datavalue(&json);
+    let (decoded, remaining) = DataValue::decode_from_key(&encoder);
+    assert_eq!(decoded, json);
+    assert!(remaining.is_empty());
+
+    // encoded keys sort in the same order as the values
+    let vld = DataValue::Validity(Validity {
+        timestamp: ValidityTs(Reverse(0)),
+        is_assert: Reverse(true),
+    });
+    let ordered = [vld, DataValue::Json(JsonData(json!(null))), DataValue::Bot];
+    let encoded = ordered
+        .iter()
+        .map(|v| {
+            let mut encoder = vec![];
+            encoder.encode_datavalue(v);
+            encoder
+        })
+        .collect::<Vec<_>>();
+    for i in 1..ordered.len() {
+        assert!(ordered[i - 1] < ordered[i]);
+        assert!(encoded[i - 1] < encoded[i]);
+    }
+}
+
+#[test]
+fn test_json_order() {
+    let ordered = [
+        json!(null),
+        json!(false),
+        json!(true),
+        json!(-10),
+        json!(-1.5),
+        json!(1),
+        json!(1.0),
+        json!(9),
+        json!(10),
+        json!(i64::MAX),
+        json!(i64::MAX as u64 + 1),
+        json!(u64::MAX),
+        json!(1e300),
+        json!(""),
+        json!("a"),
+        json!("ab"),
+        json!("b"),
+        json!([]),
+        json!([1]),
+        json!([1, null]),
+        json!([2]),
+        json!({}),
+        json!({"a": 1}),
+        json!({"a": 1, "b": 0}),
+        json!({"a": 2}),
+        json!({"b": 0}),
+    ]
+    .map(|j| DataValue::Json(JsonData(j)));
+    let encoded = ordered
+        .iter()
+        .map(|v| {
+            let mut encoder = vec![];
+            encoder.encode_datavalue(v);
+            encoder
+        })
+        .collect::<Vec<_>>();
+    for (i, l) in ordered.iter().enumerate() {
+        let (decoded, remaining) = DataValue::decode_from_key(&encoded[i]);
+        assert_eq!(&decoded, l);
+        assert!(remaining.is_empty());
+        for (j, r) in ordered.iter().enumerate() {
+            assert_eq!(l.cmp(r), i.cmp(&j), "{:?} vs {:?}", l, r);
+            assert_eq!(encoded[i].cmp(&encoded[j]), i.cmp(&j), "{:?} vs {:?}", l, r);
+        }
+    }
+}
+
 #[test]
 fn encode_decode_bytes() {
     let target = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit...";
@@ -7,6 +7,8 @@
  */
 
 mod aggrs;
+mod contexts;
+mod expr_build;
 mod exprs;
 mod functions;
 mod json;
@@ -7,11 +7,21 @@
  *
  */
 
-use std::collections::{BTreeMap, HashMap};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::mem::size_of;
 
+use ndarray::Array1;
+use regex::Regex;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::data::functions::{op_gt, op_lt};
 use crate::data::symb::Symbol;
-use crate::data::value::DataValue;
+use crate::data::value::{
+    DataValue, JsonData, RegexWrapper, UuidWrapper, Validity, ValidityTs, Vector,
+};
+use crate::parse::{parse_expressions, SourceSpan};
 
 #[test]
 fn show_size() {
@@ -55,3 +65,199 @@ fn display_datavalues() {
         ])
     );
 }
+
+#[test]
+fn ordering_across_types() {
+    let ordered = vec![
+        DataValue::Null,
+        DataValue::from(false),
+        DataValue::from(true),
+        DataValue::from(f64::NEG_INFINITY),
+        DataValue::from(i64::MIN),
+        DataValue::from(-1.5),
+        DataValue::from(-1),
+        DataValue::from(0),
+        DataValue::from(0.5),
+        DataValue::from(1),
+        DataValue::from(1.0),
+        DataValue::from(2),
+        DataValue::from(i64::MAX),
+        DataValue::from(f64::INFINITY),
+        DataValue::from(""),
+        DataValue::from("a"),
+        DataValue::from("b"),
+        DataValue::Bytes(vec![]),
+        DataValue::Bytes(vec![0]),
+        DataValue::Uuid(UuidWrapper(Uuid::nil())),
+        DataValue::Regex(RegexWrapper(Regex::new("a").unwrap())),
+        DataValue::List(vec![]),
+        DataValue::List(vec![DataValue::Null]),
+        DataValue::List(vec![DataValue::from(1)]),
+        DataValue::Set(BTreeSet::new()),
+        DataValue::Vec(Vector::F32(Array1::from(vec![1.0]))),
+        DataValue::Validity(Validity {
+            timestamp: ValidityTs(Reverse(0)),
+            is_assert: Reverse(true),
+        }),
+        DataValue::Json(JsonData(json!(null))),
+        DataValue::Bot,
+    ];
+    for (i, l) in ordered.iter().enumerate() {
+        for (j, r) in ordered.iter().enumerate() {
+            assert_eq!(l.cmp(r), i.cmp(&j), "{:?} vs {:?}", l, r);
+        }
+    }
+    let mut shuffled = ordered.clone();
+    shuffled.reverse();
+    shuffled.sort();
+    assert_eq!(shuffled, ordered);
+}
+
+#[test]
+fn decode_values_serialized_before_json() {
+    // `[Validity(1700000000000000, true), Bot]`, as stored before JSON values were added
+    let bytes = [
+        146, 129, 168, 86, 97, 108, 105, 100, 105, 116, 121, 146, 207, 0, 6, 10, 36, 24, 30, 64, 0,
+        195, 163, 66, 111, 116,
+    ];
+    let expected = vec![
+        DataValue::Validity(Validity {
+            timestamp: ValidityTs(Reverse(1_700_000_000_000_000)),
+            is_assert: Reverse(true),
+        }),
+        DataValue::Bot,
+    ];
+    let decoded: Vec<DataValue> = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(decoded, expected);
+    assert_eq!(rmp_serde::to_vec(&expected).unwrap(), bytes);
+}
+
+#[test]
+fn comparison_ops_within_types() {
+    let pairs = vec![
+        (DataValue::from(1), DataValue::from(1.5)),
+        (DataValue::from(-0.5), DataValue::from(0)),
+        (
+            DataValue::Uuid(UuidWrapper(Uuid::nil())),
+            DataValue::Uuid(UuidWrapper(Uuid::from_u128(u128::MAX))),
+        ),
+        (
+            DataValue::Vec(Vector::F64(Array1::from(vec![1.0]))),
+            DataValue::Vec(Vector::F64(Array1::from(vec![2.0]))),
+        ),
+        (
+            DataValue::Json(JsonData(json!(1))),
+            DataValue::Json(JsonData(json!(2))),
+        ),
+        (
+            DataValue::Validity(Validity {
+                timestamp: ValidityTs(Reverse(2)),
+                is_assert: Reverse(true),
+            }),
+            DataValue::Validity(Validity {
+                timestamp: ValidityTs(Reverse(1)),
+                is_assert: Reverse(true),
+            }),
+        ),
+    ];
+    for (l, r) in pairs {
+        let args = [l.clone(), r.clone()];
+        assert_eq!(
+            op_lt(&args).unwrap(),
+            DataValue::from(true),
+            "{:?} < {:?}",
+            l,
+            r
+        );
+        assert_eq!(
+            op_gt(&args).unwrap(),
+            DataValue::from(false),
+            "{:?} > {:?}",
+            l,
+            r
+        );
+    }
+    assert!(op_lt(&[DataValue::from(1), DataValue::from("1")]).is_err());
+    assert!(op_lt(&[DataValue::Null, DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn heap_sizes() {
+    assert_eq!(DataValue::from(1).heap_size(), 0);
+    assert_eq!(DataValue::Null.heap_size(), 0);
+    // short strings are stored inline
+    assert_eq!(DataValue::from("short").heap_size(), 0);
+    let long = "a".repeat(100);
+    let s = DataValue::from(long.as_str());
+    assert!(s.heap_size() >= 100);
+
+    let list = DataValue::List(vec![DataValue::from(1), s.clone()]);
+    assert!(list.heap_size() >= 2 * size_of::<DataValue>() + s.heap_size());
+    let nested = DataValue::List(vec![list.clone(), DataValue::Set([s.clone()].into())]);
+    assert!(nested.heap_size() > list.heap_size() + s.heap_size());
+
+    let json = DataValue::Json(JsonData(json!({"key": [long, {"inner": 1}]})));
+    assert!(json.heap_size() > 100 + "key".len() + "inner".len());
+    assert_eq!(DataValue::Json(JsonData(json!(1))).heap_size(), 0);
+}
+
+#[test]
+fn canonical_strings() {
+    let canonical = |v: DataValue| v.to_canonical_string();
+    assert_eq!(canonical(DataValue::Null), "null");
+    assert_eq!(canonical(DataValue::from(1)), "1");
+    assert_eq!(canonical(DataValue::from(1.0)), "1.0");
+    assert_eq!(canonical(DataValue::from(-0.0)), "-0.0");
+    assert_eq!(canonical(DataValue::from(f64::NAN)), r#"to_float("NAN")"#);
+    for f in [0.1, 1.0 / 3.0, 1e300, f64::MIN_POSITIVE, 123456789.12345679] {
+        let s = canonical(DataValue::from(f));
+        assert_eq!(s.parse::<f64>().unwrap(), f, "{s}");
+    }
+    assert_eq!(canonical(DataValue::from("a\"b\n")), r#""a\"b\n""#);
+    assert_eq!(
+        canonical(DataValue::List(vec![
+            DataValue::from(1),
+            DataValue::List(vec![DataValue::from(2.5), DataValue::from("x")]),
+        ])),
+        r#"[1, [2.5, "x"]]"#
+    );
+
+    // structurally equal values have the same canonical form, whatever the order of insertion
+    let mut forward = serde_json::Map::new();
+    forward.insert("b".to_string(), json!([1, 2.0]));
+    forward.insert("a".to_string(), json!({"y": null, "x": "s"}));
+    let mut backward = serde_json::Map::new();
+    backward.insert("a".to_string(), json!({"x": "s", "y": null}));
+    backward.insert("b".to_string(), json!([1, 2.0]));
+    let forward = canonical(DataValue::Json(JsonData(forward.into())));
+    assert_eq!(
+        forward,
+        canonical(DataValue::Json(JsonData(backward.into())))
+    );
+    assert_eq!(
+        forward,
+        r#"from_json("{\"a\":{\"x\":\"s\",\"y\":null},\"b\":[1,2.0]}")"#
+    );
+}
+
+#[test]
+fn constant_exprs_to_values() {
+    let to_value =
+        |src: &str| DataValue::try_from(parse_expressions(src, &Default::default()).unwrap());
+    assert_eq!(
+        to_value("[1, ['a', null], [], 2.5]").unwrap(),
+        DataValue::List(vec![
+            DataValue::from(1),
+            DataValue::List(vec![DataValue::from("a"), DataValue::Null]),
+            DataValue::List(vec![]),
+            DataValue::from(2.5),
+        ])
+    );
+    assert_eq!(to_value("'s'").unwrap(), DataValue::from("s"));
+
+    // nothing is evaluated
+    let err = to_value("[1, [x, 2]]").unwrap_err();
+    assert_eq!(err.0, SourceSpan(5, 1));
+    assert!(to_value("1 + 2").is_err());
+    assert!(to_value("[1, 1 + 2]").is_err());
+}
@@ -10,11 +10,17 @@ use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use ndarray::Array1;
 use std::cmp::{Ordering, Reverse};
-use std::collections::BTreeSet;
+use std::collections::{btree_set, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
+use std::mem;
+use std::mem::size_of;
 
+use crate::data::expr::Expr;
+use crate::data::functions::OP_LIST;
+use crate::data::json::JsonValue;
 use crate::data::relation::VecElementType;
+use crate::parse::SourceSpan;
 use ordered_float::OrderedFloat;
 use regex::Regex;
 use serde::de::{SeqAccess, Visitor};
@@ -94,6 +100,119 @@ impl PartialOrd for RegexWrapper {
     }
 }
 
+/// JSON data in the database, objects are used as dictionaries with string keys.
+///
+/// JSON values are ordered by kind first: null < bool < number < string < array < object.
+/// Numbers are compared by numeric value, with an int sorting before a float of equal value,
+/// arrays element by element, and objects entry by entry in the order of their keys.
+#[derive(Clone, serde_derive::Deserialize, serde_derive::Serialize)]
+pub struct JsonData(pub JsonValue);
+
+impl JsonData {
+    /// The position of the kind of `j` in the ordering of JSON values
+    fn kind_rank(j: &JsonValue) -> u8 {
+        match j {
+            JsonValue::Null => 0,
+            JsonValue::Bool(_) => 1,
+            JsonValue::Number(_) => 2,
+            JsonValue::String(_) => 3,
+            JsonValue::Array(_) => 4,
+            JsonValue::Object(_) => 5,
+        }
+    }
+    fn cmp_json(l: &JsonValue, r: &JsonValue) -> Ordering {
+        match (l, r) {
+            (JsonValue::Bool(l), JsonValue::Bool(r)) => l.cmp(r),
+            (JsonValue::Number(l), JsonValue::Number(r)) => Self::cmp_number(l, r),
+            (JsonValue::String(l), JsonValue::String(r)) => l.cmp(r),
+            (JsonValue::Array(l), JsonValue::Array(r)) => l
+                .iter()
+                .zip(r)
+                .map(|(l, r)| Self::cmp_json(l, r))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| l.len().cmp(&r.len())),
+            (JsonValue::Object(l), JsonValue::Object(r)) => l
+                .iter()
+                .zip(r)
+                .map(|((lk, lv), (rk, rv))| lk.cmp(rk).then_with(|| Self::cmp_json(lv, rv)))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| l.len().cmp(&r.len())),
+            (l, r) => Self::kind_rank(l).cmp(&Self::kind_rank(r)),
+        }
+    }
+    fn cmp_number(l: &serde_json::Number, r: &serde_json::Number) -> Ordering {
+        // unsigned ints too large for i64 are compared exactly, and not as floats
+        if let (Some(l), Some(r)) = (l.as_u64(), r.as_u64()) {
+            return l.cmp(&r);
+        }
+        json_num(l)
+            .cmp(&json_num(r))
+            .then_with(|| l.is_f64().cmp(&r.is_f64()))
+    }
+    fn hash_json<H: Hasher>(j: &JsonValue, state: &mut H) {
+        Self::kind_rank(j).hash(state);
+        match j {
+            JsonValue::Null => {}
+            JsonValue::Bool(b) => b.hash(state),
+            JsonValue::Number(n) => match n.as_u64() {
+                Some(u) => u.hash(state),
+                None => match json_num(n) {
+                    Num::Int(i) => i.hash(state),
+                    Num::Float(f) => f.to_bits().hash(state),
+                },
+            },
+            JsonValue::String(s) => s.hash(state),
+            JsonValue::Array(a) => {
+                a.len().hash(state);
+                for el in a {
+                    Self::hash_json(el, state);
+                }
+            }
+            JsonValue::Object(o) => {
+                o.len().hash(state);
+                for (k, v) in o {
+                    k.hash(state);
+                    Self::hash_json(v, state);
+                }
+            }
+        }
+    }
+}
+
+/// A JSON number as a number of the database, unsigned ints too large for i64 become floats
+pub(crate) fn json_num(n: &serde_json::Number) -> Num {
+    match n.as_i64() {
+        Some(i) => Num::Int(i),
+        None => Num::Float(n.as_f64().unwrap()),
+    }
+}
+
+impl PartialEq for JsonData {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for JsonData {}
+
+impl PartialOrd for JsonData {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for JsonData {
+    fn cmp(&self, other: &Self) -> Ordering {
+        Self::cmp_json(&self.0, &other.0)
+    }
+}
+
+impl Hash for JsonData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Self::hash_json(&self.0, state)
+    }
+}
+
 /// Timestamp part of validity
 #[derive(
     Copy,
@@ -129,9 +248,14 @@ pub struct Validity {
 }
 
 /// A Value in the database
-#[derive(
-    Clone, PartialEq, Eq, PartialOrd, Ord, serde_derive::Deserialize, serde_derive::Serialize, Hash,
-)]
+///
+/// Values of different types are ordered by the order of the variants below:
+/// null < bool < number < string < bytes < uuid < regex < list < set < vector
+/// < validity < json < bot. Within numbers, ints and floats are compared by
+/// numeric value, with an int sorting before a float of equal value.
+///
+/// New variants must be added at the end, as serialized values refer to variants by position.
+#[derive(Clone, PartialEq, Eq, serde_derive::Deserialize, serde_derive::Serialize, Hash)]
 pub enum DataValue {
     /// null
     Null,
@@ -158,6 +282,54 @@ pub enum DataValue {
     Validity(Validity),
     /// bottom type, used internally only
     Bot,
+    /// JSON data
+    Json(JsonData),
+}
+
+impl DataValue {
+    /// The position of the type of the value in the ordering of values
+    fn type_rank(&self) -> u8 {
+        match self {
+            DataValue::Null => 0,
+            DataValue::Bool(_) => 1,
+            DataValue::Num(_) => 2,
+            DataValue::Str(_) => 3,
+            DataValue::Bytes(_) => 4,
+            DataValue::Uuid(_) => 5,
+            DataValue::Regex(_) => 6,
+            DataValue::List(_) => 7,
+            DataValue::Set(_) => 8,
+            DataValue::Vec(_) => 9,
+            DataValue::Validity(_) => 10,
+            DataValue::Json(_) => 11,
+            DataValue::Bot => 12,
+        }
+    }
+}
+
+impl PartialOrd for DataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DataValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (DataValue::Bool(l), DataValue::Bool(r)) => l.cmp(r),
+            (DataValue::Num(l), DataValue::Num(r)) => l.cmp(r),
+            (DataValue::Str(l), DataValue::Str(r)) => l.cmp(r),
+            (DataValue::Bytes(l), DataValue::Bytes(r)) => l.cmp(r),
+            (DataValue::Uuid(l), DataValue::Uuid(r)) => l.cmp(r),
+            (DataValue::Regex(l), DataValue::Regex(r)) => l.cmp(r),
+            (DataValue::List(l), DataValue::List(r)) => l.cmp(r),
+            (DataValue::Set(l), DataValue::Set(r)) => l.cmp(r),
+            (DataValue::Vec(l), DataValue::Vec(r)) => l.cmp(r),
+            (DataValue::Validity(l), DataValue::Validity(r)) => l.cmp(r),
+            (DataValue::Json(l), DataValue::Json(r)) => l.cmp(r),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
 }
 
 /// Vector of floating numbers
@@ -425,6 +597,31 @@ impl From<bool> for DataValue {
     }
 }
 
+/// The expression given for conversion to a value is not a constant
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("The expression is not a constant")]
+#[diagnostic(code(eval::not_constant))]
+pub struct NotConstantError(#[label] pub SourceSpan);
+
+impl TryFrom<Expr> for DataValue {
+    type Error = NotConstantError;
+
+    /// Succeeds for constants and for list literals of them, without evaluating anything
+    fn try_from(mut expr: Expr) -> Result<Self, Self::Error> {
+        match &mut expr {
+            Expr::Const { val, .. } => Ok(mem::replace(val, DataValue::Null)),
+            Expr::Apply { op, args, .. } if op.name == OP_LIST.name => Ok(DataValue::List(
+                mem::take(args)
+                    .into_vec()
+                    .into_iter()
+                    .map(DataValue::try_from)
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => Err(NotConstantError(expr.span())),
+        }
+    }
+}
+
 /// Representing a number
 #[derive(Copy, Clone, serde_derive::Deserialize, serde_derive::Serialize)]
 pub enum Num {
@@ -539,6 +736,71 @@ impl Debug for DataValue {
     }
 }
 
+fn write_canonical_seq<'a>(items: impl Iterator<Item = &'a DataValue>, out: &mut String) {
+    out.push('[');
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        item.write_canonical(out);
+    }
+    out.push(']');
+}
+
+fn write_canonical_json(j: &JsonValue, out: &mut String) {
+    match j {
+        JsonValue::Array(a) => {
+            out.push('[');
+            for (i, v) in a.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(v, out);
+            }
+            out.push(']');
+        }
+        JsonValue::Object(o) => {
+            let mut entries = o.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&JsonValue::from(k.as_str()).to_string());
+                out.push(':');
+                write_canonical_json(v, out);
+            }
+            out.push('}');
+        }
+        // numbers keep whether they are floats, and are printed without loss of precision
+        v => out.push_str(&v.to_string()),
+    }
+}
+
+/// Rough per-entry bookkeeping of the B-trees backing sets and JSON objects
+const MAP_ENTRY_OVERHEAD: usize = size_of::<usize>();
+
+fn json_heap_size(j: &JsonValue) -> usize {
+    match j {
+        JsonValue::String(s) => s.capacity(),
+        JsonValue::Array(a) => {
+            a.capacity() * size_of::<JsonValue>() + a.iter().map(json_heap_size).sum::<usize>()
+        }
+        JsonValue::Object(o) => o
+            .iter()
+            .map(|(k, v)| {
+                size_of::<String>()
+                    + size_of::<JsonValue>()
+                    + MAP_ENTRY_OVERHEAD
+                    + k.capacity()
+                    + json_heap_size(v)
+            })
+            .sum(),
+        _ => 0,
+    }
+}
+
 impl Display for DataValue {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -573,11 +835,105 @@ impl Display for DataValue {
                     write!(f, "vec({:?}, \"F64\")", a.to_vec())
                 }
             },
+            DataValue::Json(j) => {
+                write!(f, "from_json({:?})", j.0.to_string())
+            }
+        }
+    }
+}
+
+/// The elements of a list-like value, yielded by reference one at a time. Operators that
+/// consume lists iterate over this instead of requiring a slice, so that they can stop as
+/// soon as the result is known.
+pub enum ValueStream<'a> {
+    /// Elements of a list, in order
+    List(std::slice::Iter<'a, DataValue>),
+    /// Elements of a set, in ascending order
+    Set(btree_set::Iter<'a, DataValue>),
+}
+
+impl<'a> Iterator for ValueStream<'a> {
+    type Item = &'a DataValue;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ValueStream::List(it) => it.next(),
+            ValueStream::Set(it) => it.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            ValueStream::List(it) => it.size_hint(),
+            ValueStream::Set(it) => it.size_hint(),
         }
     }
 }
 
 impl DataValue {
+    /// A deterministic rendering for golden tests and logs. It differs from the `Display` form
+    /// in that floats are always distinguishable from ints and are printed without loss of
+    /// precision, and that the keys of JSON objects are always sorted.
+    pub fn to_canonical_string(&self) -> String {
+        let mut out = String::new();
+        self.write_canonical(&mut out);
+        out
+    }
+    fn write_canonical(&self, out: &mut String) {
+        use std::fmt::Write;
+
+        match self {
+            DataValue::Num(Num::Float(f)) if f.is_finite() => write!(out, "{f:?}").unwrap(),
+            DataValue::List(l) => write_canonical_seq(l.iter(), out),
+            DataValue::Set(s) => write_canonical_seq(s.iter(), out),
+            DataValue::Json(j) => {
+                let mut json = String::new();
+                write_canonical_json(&j.0, &mut json);
+                write!(out, "from_json({json:?})").unwrap()
+            }
+            v => write!(out, "{v}").unwrap(),
+        }
+    }
+    /// An estimate of the bytes this value owns on the heap, excluding the value itself.
+    /// Collections count their elements and whatever those own in turn.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            DataValue::Str(s) => {
+                if s.is_inline() {
+                    0
+                } else {
+                    s.capacity()
+                }
+            }
+            DataValue::Bytes(b) => b.capacity(),
+            DataValue::Regex(r) => r.0.as_str().len(),
+            DataValue::List(l) => {
+                l.capacity() * size_of::<DataValue>()
+                    + l.iter().map(|v| v.heap_size()).sum::<usize>()
+            }
+            DataValue::Set(s) => s
+                .iter()
+                .map(|v| size_of::<DataValue>() + MAP_ENTRY_OVERHEAD + v.heap_size())
+                .sum(),
+            DataValue::Vec(Vector::F32(v)) => v.len() * size_of::<f32>(),
+            DataValue::Vec(Vector::F64(v)) => v.len() * size_of::<f64>(),
+            DataValue::Json(j) => json_heap_size(&j.0),
+            DataValue::Null
+            | DataValue::Bool(_)
+            | DataValue::Num(_)
+            | DataValue::Uuid(_)
+            | DataValue::Validity(_)
+            | DataValue::Bot => 0,
+        }
+    }
+    /// Returns the elements if this one is a List or a Set, to be consumed one at a time
+    pub fn stream(&self) -> Option<ValueStream<'_>> {
+        match self {
+            DataValue::List(l) => Some(ValueStream::List(l.iter())),
+            DataValue::Set(s) => Some(ValueStream::Set(s.iter())),
+            _ => None,
+        }
+    }
     /// Returns a slice of DataValues if this one is a List
     pub fn get_slice(&self) -> Option<&[DataValue]> {
         match self {
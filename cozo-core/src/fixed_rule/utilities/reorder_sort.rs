@@ -35,7 +35,7 @@ impl FixedRule for ReorderSort {
 
         let mut out_list = match payload.expr_option("out", None)? {
             Expr::Const {
-                val: DataValue::List(l),
+                val: DataValue::List(ref l),
                 span,
             } => l
                 .iter()
@@ -44,7 +44,7 @@ impl FixedRule for ReorderSort {
                     span,
                 })
                 .collect_vec(),
-            Expr::Apply { op, args, .. } if *op == OP_LIST => args.to_vec(),
+            Expr::Apply { op, ref args, .. } if *op == OP_LIST => args.to_vec(),
             _ => {
                 bail!(WrongFixedRuleOptionError {
                     name: "out".to_string(),
@@ -48,7 +48,11 @@ use miette::{
 };
 use serde_json::json;
 
-pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+pub use data::json::JsonValue;
+pub use data::value::{
+    DataValue, JsonData, NotConstantError, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs,
+    ValueStream,
+};
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
@@ -65,8 +69,19 @@ pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
 pub use storage::{Storage, StoreTx};
 
-pub use crate::data::expr::Expr;
-use crate::data::json::JsonValue;
+pub use crate::data::eval::{
+    ChainedContext, ExprEvalContext, MapRowContext, RowEvalContext, SortKey, SortOrder,
+    WithDefaults,
+};
+pub use crate::data::expr::{
+    all_builtin_ops, register_custom_op, with_strict_floats, CustomOp, Expr, ExprInterner, OpInfo,
+    MAX_DNF_TERMS, MAX_EXPR_DEPTH,
+};
+pub use crate::data::expr_build::ExprBuilder;
+pub use crate::data::functions::{
+    with_collation, with_max_int_range_len, with_numeric_promotion, with_seeded_rng, Collation,
+    NumericPromotion,
+};
 pub use crate::data::symb::Symbol;
 pub use crate::data::value::Vector;
 pub use crate::fixed_rule::SimpleFixedRule;
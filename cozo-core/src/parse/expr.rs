@@ -7,6 +7,7 @@
  */
 
 use std::collections::BTreeMap;
+use std::mem;
 
 use itertools::Itertools;
 use lazy_static::lazy_static;
@@ -15,10 +16,10 @@ use pest::pratt_parser::{Op, PrattParser};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::{get_op, Bytecode, Expr};
+use crate::data::expr::{get_op, Bytecode, Expr, HigherOrderOp, Lambda};
 use crate::data::functions::{
-    OP_ADD, OP_AND, OP_COALESCE, OP_CONCAT, OP_DIV, OP_EQ, OP_GE, OP_GT, OP_LE, OP_LIST, OP_LT,
-    OP_MINUS, OP_MOD, OP_MUL, OP_NEGATE, OP_NEQ, OP_OR, OP_POW, OP_SUB,
+    OP_ADD, OP_CONCAT, OP_DIV, OP_EQ, OP_GE, OP_GT, OP_IMPLIES, OP_LE, OP_LIST, OP_LT, OP_MINUS,
+    OP_MOD, OP_MUL, OP_NEGATE, OP_NEQ, OP_POW, OP_SUB,
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
@@ -53,57 +54,224 @@ lazy_static! {
 #[diagnostic(code(parser::invalid_expression))]
 pub(crate) struct InvalidExpression(#[label] pub(crate) SourceSpan);
 
-pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) {
+/// A step in compiling expressions to bytecode
+enum CompileStep<'a> {
+    /// Compiles the expression, which pushes its value when run
+    Expr(&'a Expr),
+    Emit(Bytecode),
+    /// Emits a jump to the end of the enclosing expression, set by a later `PatchExits`
+    EmitExit(Bytecode),
+    /// Emits a jump past the current clause, set by the next `PatchSkip`
+    EmitSkip(Bytecode),
+    /// Points the latest pending skip at the next instruction
+    PatchSkip,
+    /// Points the latest `n` pending exits at the next instruction
+    PatchExits(usize),
+    /// Compiles what follows into a separate list, up to the matching `EmitHigherOrder`
+    BeginLambda,
+    EmitHigherOrder {
+        op: HigherOrderOp,
+        slot: usize,
+        span: SourceSpan,
+    },
+}
+
+fn set_jump_target(code: &mut Bytecode, target: usize) {
+    match code {
+        Bytecode::JumpIfFalse { jump_to, .. }
+        | Bytecode::Goto { jump_to, .. }
+        | Bytecode::ShortCircuit { jump_to, .. }
+        | Bytecode::JumpIfNotNull { jump_to, .. }
+        | Bytecode::TryBegin {
+            catch_to: jump_to, ..
+        } => *jump_to = target,
+        _ => unreachable!(),
+    }
+}
+
+/// The steps to compile `expr`, in order
+fn compile_steps(expr: &Expr) -> Vec<CompileStep<'_>> {
     match expr {
-        Expr::Binding { var, tuple_pos } => collector.push(Bytecode::Binding {
+        Expr::Binding { var, tuple_pos } => vec![CompileStep::Emit(Bytecode::Binding {
             var: var.clone(),
             tuple_pos: *tuple_pos,
-        }),
-        Expr::Const { val, span } => collector.push(Bytecode::Const {
+        })],
+        Expr::Const { val, span } => vec![CompileStep::Emit(Bytecode::Const {
             val: val.clone(),
             span: *span,
-        }),
+        })],
         Expr::Apply { op, args, span } => {
-            let arity = args.len();
-            for arg in args.iter() {
-                expr2bytecode(arg, collector);
-            }
-            collector.push(Bytecode::Apply {
+            let mut steps = args.iter().map(CompileStep::Expr).collect_vec();
+            // -n, +1
+            steps.push(CompileStep::Emit(Bytecode::Apply {
                 op,
-                arity,
+                arity: args.len(),
                 span: *span,
-            })
+            }));
+            steps
         }
         Expr::Cond { clauses, span } => {
-            let mut return_jump_pos = vec![];
+            let mut steps = vec![];
             for (cond, val) in clauses {
                 // +1
-                expr2bytecode(cond, collector);
+                steps.push(CompileStep::Expr(cond));
                 // -1
-                collector.push(Bytecode::JumpIfFalse {
+                steps.push(CompileStep::EmitSkip(Bytecode::JumpIfFalse {
                     jump_to: 0,
-                    span: *span,
-                });
-                let false_jump_amend_pos = collector.len() - 1;
+                    span: cond.span(),
+                }));
                 // +1 in this branch
-                expr2bytecode(val, collector);
-                collector.push(Bytecode::Goto {
+                steps.push(CompileStep::Expr(val));
+                steps.push(CompileStep::EmitExit(Bytecode::Goto {
                     jump_to: 0,
                     span: *span,
-                });
-                return_jump_pos.push(collector.len() - 1);
-                collector[false_jump_amend_pos] = Bytecode::JumpIfFalse {
-                    jump_to: collector.len(),
+                }));
+                steps.push(CompileStep::PatchSkip);
+            }
+            steps.push(CompileStep::PatchExits(clauses.len()));
+            steps
+        }
+        Expr::Try { clauses, span } => {
+            let (last, rest) = clauses.split_last().unwrap();
+            let mut steps = vec![];
+            for clause in rest {
+                steps.push(CompileStep::EmitSkip(Bytecode::TryBegin {
+                    catch_to: 0,
+                    span: *span,
+                }));
+                // +1 if successful, unchanged if caught
+                steps.push(CompileStep::Expr(clause));
+                steps.push(CompileStep::Emit(Bytecode::TryEnd { span: *span }));
+                steps.push(CompileStep::EmitExit(Bytecode::Goto {
+                    jump_to: 0,
                     span: *span,
-                };
+                }));
+                steps.push(CompileStep::PatchSkip);
             }
-            let total_len = collector.len();
-            for pos in return_jump_pos {
-                collector[pos] = Bytecode::Goto {
-                    jump_to: total_len,
+            // +1, errors are propagated
+            steps.push(CompileStep::Expr(last));
+            steps.push(CompileStep::PatchExits(rest.len()));
+            steps
+        }
+        Expr::And { args, span } | Expr::Or { args, span } => {
+            let on = matches!(expr, Expr::Or { .. });
+            // +1, the accumulated value
+            let mut steps = vec![CompileStep::Emit(Bytecode::Const {
+                val: DataValue::from(!on),
+                span: *span,
+            })];
+            for arg in args {
+                // +1
+                steps.push(CompileStep::Expr(arg));
+                // -1
+                steps.push(CompileStep::EmitExit(Bytecode::ShortCircuit {
+                    on,
+                    jump_to: 0,
+                    span: arg.span(),
+                }));
+            }
+            steps.push(CompileStep::PatchExits(args.len()));
+            steps
+        }
+        Expr::Coalesce { args, span } => {
+            let Some((last, rest)) = args.split_last() else {
+                return vec![CompileStep::Emit(Bytecode::Const {
+                    val: DataValue::Null,
                     span: *span,
+                })];
+            };
+            let mut steps = vec![];
+            for arg in rest {
+                // +1
+                steps.push(CompileStep::Expr(arg));
+                // unchanged if not null, -1 otherwise
+                steps.push(CompileStep::EmitExit(Bytecode::JumpIfNotNull {
+                    jump_to: 0,
+                    span: *span,
+                }));
+            }
+            // +1
+            steps.push(CompileStep::Expr(last));
+            steps.push(CompileStep::PatchExits(rest.len()));
+            steps
+        }
+        Expr::Let {
+            value,
+            body,
+            slot,
+            span,
+            ..
+        } => vec![
+            // +1
+            CompileStep::Expr(value),
+            // -1
+            CompileStep::Emit(Bytecode::Bind {
+                slot: slot.unwrap_or(usize::MAX),
+                span: *span,
+            }),
+            // +1
+            CompileStep::Expr(body),
+        ],
+        Expr::HigherOrder {
+            op,
+            list,
+            lambda,
+            span,
+        } => vec![
+            // +1
+            CompileStep::Expr(list),
+            CompileStep::BeginLambda,
+            CompileStep::Expr(&lambda.body),
+            // -1, +1
+            CompileStep::EmitHigherOrder {
+                op: *op,
+                slot: lambda.slot.unwrap_or(usize::MAX),
+                span: *span,
+            },
+        ],
+    }
+}
+
+/// Compiles without recursion, so that deeply nested expressions cannot overflow the stack
+pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) {
+    let mut steps = vec![CompileStep::Expr(expr)];
+    // positions of the jumps waiting for their targets
+    let mut exits = vec![];
+    let mut skips = vec![];
+    // the code around the lambda bodies being compiled
+    let mut enclosing = vec![];
+    while let Some(step) = steps.pop() {
+        match step {
+            CompileStep::Expr(expr) => steps.extend(compile_steps(expr).into_iter().rev()),
+            CompileStep::Emit(code) => collector.push(code),
+            CompileStep::EmitExit(code) => {
+                exits.push(collector.len());
+                collector.push(code);
+            }
+            CompileStep::EmitSkip(code) => {
+                skips.push(collector.len());
+                collector.push(code);
+            }
+            CompileStep::PatchSkip => {
+                let target = collector.len();
+                set_jump_target(&mut collector[skips.pop().unwrap()], target);
+            }
+            CompileStep::PatchExits(n) => {
+                let target = collector.len();
+                for pos in exits.split_off(exits.len() - n) {
+                    set_jump_target(&mut collector[pos], target);
                 }
             }
+            CompileStep::BeginLambda => enclosing.push(mem::take(collector)),
+            CompileStep::EmitHigherOrder { op, slot, span } => {
+                let body = mem::replace(collector, enclosing.pop().unwrap());
+                collector.push(Bytecode::HigherOrder {
+                    op,
+                    slot,
+                    body,
+                    span,
+                });
+            }
         }
     }
 }
@@ -115,12 +283,12 @@ pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue
     );
 
     PRATT_PARSER
-        .map_primary(|v| build_term(v, param_pool))
+        .map_primary(|v| Ok((build_term(v, param_pool)?, None)))
         .map_infix(build_expr_infix)
         .map_prefix(|op, rhs| {
-            let rhs = rhs?;
+            let (rhs, _) = rhs?;
             let rhs_span = rhs.span();
-            Ok(match op.as_rule() {
+            let expr = match op.as_rule() {
                 Rule::minus => Expr::Apply {
                     op: &OP_MINUS,
                     args: [rhs].into(),
@@ -132,13 +300,129 @@ pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue
                     span: op.extract_span().merge(rhs_span),
                 },
                 _ => unreachable!(),
-            })
+            };
+            Ok((expr, None))
         })
         .parse(pair.into_inner())
+        .map(|(expr, _)| expr)
+}
+
+/// Comparisons not enclosed in parentheses, e.g. `a <= b < c`,
+/// which stand for the conjunction of the comparisons of adjacent operands
+struct ComparisonChain {
+    operands: Vec<Expr>,
+    ops: Vec<&'static crate::data::expr::Op>,
+}
+
+impl ComparisonChain {
+    fn lower(&self) -> Expr {
+        let span = self
+            .operands
+            .first()
+            .unwrap()
+            .span()
+            .merge(self.operands.last().unwrap().span());
+        if self.ops.len() == 1 {
+            return Expr::Apply {
+                op: self.ops[0],
+                args: self.operands.clone().into(),
+                span,
+            };
+        }
+        // middle operands are used twice, bind them to locals so that they are computed once
+        let mut operands = self.operands.clone();
+        let n_operands = operands.len();
+        let mut locals = vec![];
+        for (i, operand) in operands[1..n_operands - 1].iter_mut().enumerate() {
+            if !matches!(operand, Expr::Binding { .. } | Expr::Const { .. }) {
+                let var = Symbol::new(format!("~chain{i}"), operand.span());
+                let value = std::mem::replace(
+                    operand,
+                    Expr::Binding {
+                        var: var.clone(),
+                        tuple_pos: None,
+                    },
+                );
+                locals.push((var, value));
+            }
+        }
+        let comparisons = self
+            .ops
+            .iter()
+            .zip(operands.iter().tuple_windows())
+            .map(|(op, (l, r))| Expr::Apply {
+                op,
+                args: [l.clone(), r.clone()].into(),
+                span: l.span().merge(r.span()),
+            })
+            .collect_vec();
+        let mut ret = Expr::build_and(comparisons, span);
+        for (var, value) in locals.into_iter().rev() {
+            ret = Expr::Let {
+                var,
+                value: Box::new(value),
+                body: Box::new(ret),
+                slot: None,
+                span,
+            };
+        }
+        ret
+    }
 }
 
-fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Result<Expr> {
-    let args = vec![lhs?, rhs?];
+/// Nested applications of `coalesce`, as in `a ~ b ~ c`, are flattened
+pub(crate) fn lower_coalesce(args: Vec<Expr>, span: SourceSpan) -> Expr {
+    let mut args = args
+        .into_iter()
+        .flat_map(|mut arg| match &mut arg {
+            Expr::Coalesce { args, .. } => mem::take(args),
+            _ => vec![arg],
+        })
+        .collect_vec();
+    match args.len() {
+        0 => Expr::Const {
+            val: DataValue::Null,
+            span,
+        },
+        1 => args.pop().unwrap(),
+        _ => Expr::Coalesce { args, span },
+    }
+}
+
+type ExprWithChain = (Expr, Option<ComparisonChain>);
+
+fn build_expr_infix(
+    lhs: Result<ExprWithChain>,
+    op: Pair<'_>,
+    rhs: Result<ExprWithChain>,
+) -> Result<ExprWithChain> {
+    let (lhs, lhs_chain) = lhs?;
+    let (rhs, _) = rhs?;
+    if let Some(op) = match op.as_rule() {
+        Rule::op_gt => Some(&OP_GT),
+        Rule::op_ge => Some(&OP_GE),
+        Rule::op_lt => Some(&OP_LT),
+        Rule::op_le => Some(&OP_LE),
+        _ => None,
+    } {
+        let mut chain = lhs_chain.unwrap_or_else(|| ComparisonChain {
+            operands: vec![lhs],
+            ops: vec![],
+        });
+        chain.ops.push(op);
+        chain.operands.push(rhs);
+        return Ok((chain.lower(), Some(chain)));
+    }
+    let args = vec![lhs, rhs];
+    let start = args[0].span().0;
+    let end = args[1].span().0 + args[1].span().1;
+    let length = end - start;
+    match op.as_rule() {
+        Rule::op_coalesce => return Ok((lower_coalesce(args, SourceSpan(start, length)), None)),
+        Rule::op_and => return Ok((Expr::build_and(args, SourceSpan(start, length)), None)),
+        Rule::op_or => return Ok((Expr::build_or(args, SourceSpan(start, length)), None)),
+        _ => {}
+    }
     let op = match op.as_rule() {
         Rule::op_add => &OP_ADD,
         Rule::op_sub => &OP_SUB,
@@ -148,24 +432,17 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
         Rule::op_pow => &OP_POW,
         Rule::op_eq => &OP_EQ,
         Rule::op_ne => &OP_NEQ,
-        Rule::op_gt => &OP_GT,
-        Rule::op_ge => &OP_GE,
-        Rule::op_lt => &OP_LT,
-        Rule::op_le => &OP_LE,
         Rule::op_concat => &OP_CONCAT,
-        Rule::op_or => &OP_OR,
-        Rule::op_and => &OP_AND,
-        Rule::op_coalesce => &OP_COALESCE,
         _ => unreachable!(),
     };
-    let start = args[0].span().0;
-    let end = args[1].span().0 + args[1].span().1;
-    let length = end - start;
-    Ok(Expr::Apply {
-        op,
-        args: args.into(),
-        span: SourceSpan(start, length),
-    })
+    Ok((
+        Expr::Apply {
+            op,
+            args: args.into(),
+            span: SourceSpan(start, length),
+        },
+        None,
+    ))
 }
 
 fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
@@ -274,17 +551,44 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             let mut p = pair.into_inner();
             let ident_p = p.next().unwrap();
             let ident = ident_p.as_str();
-            let mut args: Vec<_> = p
-                .next()
-                .unwrap()
-                .into_inner()
-                .map(|v| build_expr(v, param_pool))
-                .try_collect()?;
+            let mut args = vec![];
+            let mut lambdas = vec![];
+            for v in p.next().unwrap().into_inner() {
+                match v.as_rule() {
+                    Rule::lambda => lambdas.push((args.len(), build_lambda(v, param_pool)?)),
+                    _ => args.push(build_expr(v, param_pool)?),
+                }
+            }
             #[derive(Error, Diagnostic, Debug)]
             #[error("Named function '{0}' not found")]
             #[diagnostic(code(parser::func_not_function))]
             struct FuncNotFoundError(String, #[label] SourceSpan);
 
+            if let Some(op) = HigherOrderOp::from_name(ident) {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("'{0}' requires a list and a lambda")]
+                #[diagnostic(code(parser::bad_higher_order))]
+                #[diagnostic(help("e.g. {0}(xs, x -> x > 1)"))]
+                struct WrongArgsToHigherOrder(&'static str, #[label] SourceSpan);
+
+                ensure!(
+                    args.len() == 1 && lambdas.len() == 1 && lambdas[0].0 == 1,
+                    WrongArgsToHigherOrder(op.name(), span)
+                );
+                return Ok(Expr::HigherOrder {
+                    op,
+                    list: Box::new(args.pop().unwrap()),
+                    lambda: lambdas.pop().unwrap().1,
+                    span,
+                });
+            }
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("A lambda can only be passed to a higher-order function such as 'map'")]
+            #[diagnostic(code(parser::unexpected_lambda))]
+            struct UnexpectedLambda(#[label] SourceSpan);
+
+            ensure!(lambdas.is_empty(), UnexpectedLambda(span));
+
             match ident {
                 "cond" => {
                     if args.is_empty() {
@@ -329,6 +633,95 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                     }
                     Expr::Cond { clauses, span }
                 }
+                "try" => {
+                    #[derive(Error, Diagnostic, Debug)]
+                    #[error("'try' requires at least one argument")]
+                    #[diagnostic(code(parser::empty_try))]
+                    struct EmptyTry(#[label] SourceSpan);
+
+                    ensure!(!args.is_empty(), EmptyTry(span));
+                    Expr::Try {
+                        clauses: args,
+                        span,
+                    }
+                }
+                "let" => {
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("'let' requires a variable, a value and a body")]
+                    #[diagnostic(code(parser::bad_let))]
+                    struct WrongArgsToLet(#[label] SourceSpan);
+
+                    ensure!(args.len() == 3, WrongArgsToLet(span));
+                    let mut args = args.into_iter();
+                    let var = match args.next().unwrap() {
+                        Expr::Binding { ref var, .. } => var.clone(),
+                        _ => bail!(WrongArgsToLet(span)),
+                    };
+                    Expr::Let {
+                        var,
+                        value: Box::new(args.next().unwrap()),
+                        body: Box::new(args.next().unwrap()),
+                        slot: None,
+                        span,
+                    }
+                }
+                "implies" => {
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("'implies' requires two arguments")]
+                    #[diagnostic(code(parser::bad_implies))]
+                    struct WrongArgsToImplies(#[label] SourceSpan);
+
+                    ensure!(args.len() == 2, WrongArgsToImplies(span));
+                    let mut args = args.into_iter();
+                    let antecedent = args.next().unwrap();
+                    let consequent = args.next().unwrap();
+                    // the consequent is not evaluated if the antecedent is false
+                    let var = Symbol::new("~implies", antecedent.span());
+                    let local = Expr::Binding {
+                        var: var.clone(),
+                        tuple_pos: None,
+                    };
+                    let is_false = Expr::build_equate(
+                        vec![
+                            local.clone(),
+                            Expr::Const {
+                                val: DataValue::from(false),
+                                span,
+                            },
+                        ],
+                        span,
+                    );
+                    let clauses = vec![
+                        (
+                            is_false,
+                            Expr::Const {
+                                val: DataValue::from(true),
+                                span,
+                            },
+                        ),
+                        (
+                            Expr::Const {
+                                val: DataValue::from(true),
+                                span,
+                            },
+                            Expr::Apply {
+                                op: &OP_IMPLIES,
+                                args: [local, consequent].into(),
+                                span,
+                            },
+                        ),
+                    ];
+                    Expr::Let {
+                        var,
+                        value: Box::new(antecedent),
+                        body: Box::new(Expr::Cond { clauses, span }),
+                        slot: None,
+                        span,
+                    }
+                }
+                "coalesce" => lower_coalesce(args, span),
+                "and" => Expr::build_and(args, span),
+                "or" => Expr::build_or(args, span),
                 "if" => {
                     #[derive(Debug, Error, Diagnostic)]
                     #[error("wrong number of arguments to if: 2 or 3 required")]
@@ -358,32 +751,21 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                     let op = get_op(ident).ok_or_else(|| {
                         FuncNotFoundError(ident.to_string(), ident_p.extract_span())
                     })?;
-                    op.post_process_args(&mut args);
-
                     #[derive(Error, Diagnostic, Debug)]
                     #[error("Wrong number of arguments for function '{0}'")]
                     #[diagnostic(code(parser::func_wrong_num_args))]
                     struct WrongNumArgsError(String, #[label] SourceSpan, #[help] String);
 
-                    if op.vararg {
-                        ensure!(
-                            op.min_arity <= args.len(),
-                            WrongNumArgsError(
-                                ident.to_string(),
-                                span,
-                                format!("Need at least {} argument(s)", op.min_arity)
-                            )
-                        );
-                    } else {
-                        ensure!(
-                            op.min_arity == args.len(),
-                            WrongNumArgsError(
-                                ident.to_string(),
-                                span,
-                                format!("Need exactly {} argument(s)", op.min_arity)
-                            )
-                        );
-                    }
+                    let arity = op.arity();
+                    ensure!(
+                        arity.accepts(args.len()),
+                        WrongNumArgsError(
+                            ident.to_string(),
+                            span,
+                            format!("Need {arity} argument(s)")
+                        )
+                    );
+                    op.post_process_args(&mut args);
                     Expr::Apply {
                         op,
                         args: args.into(),
@@ -397,6 +779,17 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
     })
 }
 
+fn build_lambda(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Lambda> {
+    let mut inner = pair.into_inner();
+    let var = inner.next().unwrap();
+    let body = build_expr(inner.next().unwrap(), param_pool)?;
+    Ok(Lambda {
+        var: Symbol::new(var.as_str(), var.extract_span()),
+        body: Box::new(body),
+        slot: None,
+    })
+}
+
 pub(crate) fn parse_int(s: &str, radix: u32) -> i64 {
     i64::from_str_radix(&s[2..].replace('_', ""), radix).unwrap()
 }
@@ -203,6 +203,25 @@ pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     parse_nullable_type(parsed.into_inner().next().unwrap())
 }
 
+#[cfg(test)]
+pub(crate) fn parse_expressions(
+    src: &str,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<crate::data::expr::Expr> {
+    let parsed = CozoScriptParser::parse(Rule::expression_script, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap();
+
+    expr::build_expr(parsed.into_inner().next().unwrap(), param_pool)
+}
+
 pub(crate) fn parse_script(
     src: &str,
     param_pool: &BTreeMap<String, DataValue>,
@@ -17,6 +17,7 @@ use miette::Result;
 use rayon::prelude::*;
 
 use crate::data::aggr::Aggregation;
+use crate::data::eval::EvalModes;
 use crate::data::program::{MagicSymbol, NoEntryError};
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::tuple::Tuple;
@@ -123,6 +124,8 @@ impl<'a> SessionTx<'a> {
         };
 
         let used_limiter: AtomicBool = false.into();
+        // rules may be evaluated on other threads, which must see the modes of this one
+        let modes = EvalModes::current();
 
         for epoch in 0u32.. {
             debug!("epoch {}", epoch);
@@ -131,6 +134,7 @@ impl<'a> SessionTx<'a> {
             if epoch == 0 {
                 #[allow(clippy::needless_borrow)]
                 let execution = |(k, compiled_ruleset): (_, &CompiledRuleSet)| -> Result<_> {
+                    let _modes = modes.install();
                     let new_store = match compiled_ruleset {
                         CompiledRuleSet::Rules(ruleset) => match compiled_ruleset.aggr_kind() {
                             AggrKind::None => {
@@ -214,6 +218,7 @@ impl<'a> SessionTx<'a> {
             } else {
                 #[allow(clippy::needless_borrow)]
                 let execution = |(k, compiled_ruleset): (_, &CompiledRuleSet)| -> Result<_> {
+                    let _modes = modes.install();
                     let new_store = match compiled_ruleset {
                         CompiledRuleSet::Rules(ruleset) => {
                             match compiled_ruleset.aggr_kind() {
@@ -421,11 +426,10 @@ impl<'a> SessionTx<'a> {
                     Entry::Occupied(mut ent) => {
                         let aggr_ops = ent.get_mut();
                         for (aggr_idx, (tuple_idx, _)) in val_indices_and_aggrs.iter().enumerate() {
-                            aggr_ops[aggr_idx]
-                                .normal_op
-                                .as_mut()
-                                .unwrap()
-                                .set(&item[*tuple_idx])?;
+                            let op = aggr_ops[aggr_idx].normal_op.as_mut().unwrap();
+                            if op.wants_more() {
+                                op.set(&item[*tuple_idx])?;
+                            }
                         }
                     }
                     Entry::Vacant(ent) => {
@@ -439,6 +443,17 @@ impl<'a> SessionTx<'a> {
                         ent.insert(aggr_ops);
                     }
                 }
+                // without grouping keys there is only one group, and once none of its
+                // aggregates can change the remaining rows need not be produced
+                if keys_indices.is_empty()
+                    && aggr_work.values().all(|aggr_ops| {
+                        aggr_ops
+                            .iter()
+                            .all(|aggr| !aggr.normal_op.as_ref().unwrap().wants_more())
+                    })
+                {
+                    break;
+                }
             }
             poison.check()?;
         }
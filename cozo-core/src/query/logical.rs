@@ -6,13 +6,13 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result};
 use thiserror::Error;
 
-use crate::data::expr::Expr;
+use crate::data::expr::{Expr, PartialEvalMemo};
 use crate::data::program::{
     InputAtom, InputNamedFieldRelationApplyAtom, InputRelationApplyAtom, InputRuleApplyAtom,
     NormalFormAtom, NormalFormRelationApplyAtom, NormalFormRuleApplyAtom, TempSymbGen, Unification,
@@ -129,10 +129,13 @@ impl InputAtom {
         })
     }
 
+    #[allow(clippy::mutable_key_type)]
     pub(crate) fn disjunctive_normal_form(self, tx: &SessionTx<'_>) -> Result<Disjunction> {
         let neg_form = self.negation_normal_form()?;
         let mut gen = TempSymbGen::default();
-        neg_form.do_disjunctive_normal_form(&mut gen, tx)
+        // predicates of generated rules often repeat the same constant subexpressions
+        let mut memo = PartialEvalMemo::default();
+        neg_form.do_disjunctive_normal_form(&mut gen, &mut memo, tx)
     }
 
     fn convert_named_field_relation(
@@ -180,9 +183,12 @@ impl InputAtom {
         })
     }
 
+    // `DataValue` only has interior mutability inside regex caches, which does not affect ordering
+    #[allow(clippy::mutable_key_type)]
     fn do_disjunctive_normal_form(
         self,
         gen: &mut TempSymbGen,
+        memo: &mut PartialEvalMemo,
         tx: &SessionTx<'_>,
     ) -> Result<Disjunction> {
         // invariants: the input is already in negation normal form
@@ -191,7 +197,7 @@ impl InputAtom {
             InputAtom::Disjunction { inner: args, .. } => {
                 let mut ret = vec![];
                 for arg in args {
-                    for a in arg.do_disjunctive_normal_form(gen, tx)?.inner {
+                    for a in arg.do_disjunctive_normal_form(gen, memo, tx)?.inner {
                         ret.push(a);
                     }
                 }
@@ -200,7 +206,7 @@ impl InputAtom {
             InputAtom::Conjunction { inner: args, .. } => {
                 let mut args = args
                     .into_iter()
-                    .map(|a| a.do_disjunctive_normal_form(gen, tx));
+                    .map(|a| a.do_disjunctive_normal_form(gen, memo, tx));
                 let mut result = args.next().unwrap()?;
                 for a in args {
                     result = result.conjunctive_to_disjunctive_de_morgen(a?)
@@ -213,8 +219,8 @@ impl InputAtom {
                 r.normalize(false, gen)
             }
             InputAtom::Relation { inner: v } => v.normalize(false, gen),
-            InputAtom::Predicate { inner: mut p } => {
-                p.partial_eval()?;
+            InputAtom::Predicate { inner: p } => {
+                let p = p.prepare(&BTreeMap::new(), memo)?;
                 Disjunction::singlet(NormalFormAtom::Predicate(p))
             }
             InputAtom::Negation { inner: n, .. } => match *n {
@@ -241,7 +247,8 @@ impl InputRuleApplyAtom {
         let mut seen_variables = BTreeSet::new();
         for arg in self.args {
             match arg {
-                Expr::Binding { var, .. } => {
+                Expr::Binding { ref var, .. } => {
+                    let var = var.clone();
                     if var.is_ignored_symbol() {
                         let dup = gen.next_ignored(var.span);
                         args.push(dup);
@@ -301,7 +308,8 @@ impl InputRelationApplyAtom {
         let mut seen_variables = BTreeSet::new();
         for arg in self.args {
             match arg {
-                Expr::Binding { var, .. } => {
+                Expr::Binding { ref var, .. } => {
+                    let var = var.clone();
                     if var.is_ignored_symbol() {
                         args.push(gen.next_ignored(var.span));
                     } else if seen_variables.insert(var.clone()) {
@@ -15,7 +15,11 @@ use log::debug;
 use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 
-use crate::data::expr::Expr;
+use crate::data::expr::{with_strict_floats, Expr};
+use crate::data::functions::{
+    with_collation, with_max_int_range_len, with_numeric_promotion, with_seeded_rng, Collation,
+    NumericPromotion,
+};
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::fixed_rule::FixedRulePayload;
@@ -58,6 +62,80 @@ fn test_limit_offset() {
     assert_eq!(res["rows"], json!([]));
 }
 #[test]
+fn test_strict_floats_in_rules() {
+    let db = new_cozo_mem().unwrap();
+    let script = "r[y] := x in [0.0, 1.0], y = x / 0.0
+                  s[y] := x in [2.0], y = exp(x)
+                  ?[y] := r[y] or s[y]";
+    assert!(db.run_script(script, Default::default()).is_ok());
+    // rules evaluated on worker threads follow the mode of the calling thread
+    with_strict_floats(|| {
+        let err = db.run_script(script, Default::default()).unwrap_err();
+        assert!(format!("{err:?}").contains("invalid float"));
+    });
+    assert!(db.run_script(script, Default::default()).is_ok());
+}
+#[test]
+fn test_numeric_promotion_in_rules() {
+    let db = new_cozo_mem().unwrap();
+    let script = "r[y] := x in [1, 2], y = x + 0.5
+                  s[y] := x in [3], y = x * 1.5
+                  ?[y] := r[y] or s[y]";
+    assert!(db.run_script(script, Default::default()).is_ok());
+    with_numeric_promotion(NumericPromotion::Error, || {
+        let err = db.run_script(script, Default::default()).unwrap_err();
+        assert!(format!("{err:?}").contains("cannot mix integers and floats"));
+    });
+    assert!(db.run_script(script, Default::default()).is_ok());
+}
+#[test]
+fn test_collation_in_rules() {
+    let db = new_cozo_mem().unwrap();
+    let script = "r[x] := x in ['B', 'c'], x < 'a'
+                  s[x] := x in ['A', 'd'], x < 'b'
+                  ?[x] := r[x] or s[x]";
+    let res = db.run_script(script, Default::default()).unwrap().rows;
+    assert_eq!(
+        res,
+        vec![vec![DataValue::from("A")], vec![DataValue::from("B")]]
+    );
+    with_collation(Collation::CaseInsensitive, || {
+        let res = db.run_script(script, Default::default()).unwrap().rows;
+        assert_eq!(res, vec![vec![DataValue::from("A")]]);
+    });
+}
+#[test]
+fn test_seeded_rng_in_rules() {
+    let db = new_cozo_mem().unwrap();
+    let script = "r[x, y] := x in [1, 2, 3], y = rand_int(0, 1000000)
+                  s[x, y] := x in [4, 5], y = rand_float()
+                  c[choice_rand(x)] := x in int_range(1000)
+                  ?[x, y] := r[x, y] or s[x, y] or (c[x], y = 0)";
+    let run = || db.run_script(script, Default::default()).unwrap().rows;
+    let seeded = with_seeded_rng(42, run);
+    assert_eq!(seeded.len(), 6);
+    assert_eq!(seeded, with_seeded_rng(42, run));
+    assert_ne!(seeded, with_seeded_rng(43, run));
+    assert_ne!(run(), run());
+
+    // the seed is dropped when `f` panics
+    let res = std::panic::catch_unwind(|| with_seeded_rng(42, || panic!("in f")));
+    assert!(res.is_err());
+    assert_ne!(run(), run());
+}
+#[test]
+fn test_max_int_range_len_in_rules() {
+    let db = new_cozo_mem().unwrap();
+    let script = "r[x] := x in int_range(10)
+                  s[x] := x in int_range(20)
+                  ?[count(x)] := r[x] or s[x]";
+    let res = db.run_script(script, Default::default()).unwrap().rows;
+    assert_eq!(res, vec![vec![DataValue::from(30)]]);
+    with_max_int_range_len(15, || {
+        assert!(db.run_script(script, Default::default()).is_err());
+    });
+}
+#[test]
 fn test_normal_aggr_empty() {
     let db = new_cozo_mem().unwrap();
     let res = db
@@ -65,6 +143,71 @@ fn test_normal_aggr_empty() {
         .unwrap()
         .rows;
     assert_eq!(res, vec![vec![DataValue::from(0)]]);
+
+    let res = db
+        .run_script(
+            "?[array_agg(a), array_agg_distinct(a, 'desc')] := a in []",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(
+        res,
+        vec![vec![DataValue::List(vec![]), DataValue::List(vec![])]]
+    );
+}
+#[test]
+fn test_normal_aggr_stops_early() {
+    let db = new_cozo_mem().unwrap();
+    // `first` needs no more rows once it has a value, so the failing row is never produced
+    let res = db
+        .run_script(
+            "?[first(a)] := a in [null, 1, 2, 0], assert(a != 0)",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(res, vec![vec![DataValue::from(1)]]);
+    // unless another aggregate needs them
+    assert!(db
+        .run_script(
+            "?[first(a), count(a)] := a in [null, 1, 2, 0], assert(a != 0)",
+            Default::default(),
+        )
+        .is_err());
+    // with grouping keys, aggregates that are done skip their remaining values
+    let res = db
+        .run_script(
+            r#"
+            data[k, v] <- [[1, 'a'], [2, null], [1, 'b'], [2, 'c']]
+            ?[k, first(v), count(v)] := data[k, v]
+            "#,
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(
+        res,
+        vec![
+            vec![DataValue::from(1), DataValue::from("a"), DataValue::from(2)],
+            vec![DataValue::from(2), DataValue::from("c"), DataValue::from(2)]
+        ]
+    );
+}
+#[test]
+fn test_array_agg_groups() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+            data[k, v] <- [[1, 'a'], [2, null], [1, 'b']]
+            ?[k, array_agg(v)] := data[k, v]
+            "#,
+            Default::default(),
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, ["a", "b"]], [2, []]]));
 }
 #[test]
 fn test_meet_aggr_empty() {
@@ -144,6 +144,32 @@ fn value2js<'a>(cx: &mut impl Context<'a>, val: &DataValue) -> JsResult<'a, JsVa
             }
             target_l.as_value(cx)
         }
+        DataValue::Json(j) => json2js(cx, &j.0)?,
+    })
+}
+
+fn json2js<'a>(cx: &mut impl Context<'a>, val: &JsonValue) -> JsResult<'a, JsValue> {
+    Ok(match val {
+        JsonValue::Null => cx.null().as_value(cx),
+        JsonValue::Bool(b) => cx.boolean(*b).as_value(cx),
+        JsonValue::Number(n) => cx.number(n.as_f64().unwrap_or(f64::NAN)).as_value(cx),
+        JsonValue::String(s) => cx.string(s).as_value(cx),
+        JsonValue::Array(a) => {
+            let target_l = cx.empty_array();
+            for (i, el) in a.iter().enumerate() {
+                let el = json2js(cx, el)?;
+                target_l.set(cx, i as u32, el)?;
+            }
+            target_l.as_value(cx)
+        }
+        JsonValue::Object(o) => {
+            let target_o = cx.empty_object();
+            for (k, v) in o {
+                let v = json2js(cx, v)?;
+                target_o.set(cx, k as &str, v)?;
+            }
+            target_o.as_value(cx)
+        }
     })
 }
 
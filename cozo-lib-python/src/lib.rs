@@ -131,6 +131,30 @@ fn value_to_py(val: DataValue, py: Python<'_>) -> PyObject {
                 }
             }
         }
+        DataValue::Json(j) => json_to_py(j.0, py),
+    }
+}
+
+fn json_to_py(val: JsonValue, py: Python<'_>) -> PyObject {
+    match val {
+        JsonValue::Null => py.None(),
+        JsonValue::Bool(b) => b.into_py(py),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => i.into_py(py),
+            None => n.as_f64().into_py(py),
+        },
+        JsonValue::String(s) => s.into_py(py),
+        JsonValue::Array(a) => {
+            let vs: Vec<_> = a.into_iter().map(|v| json_to_py(v, py)).collect();
+            vs.into_py(py)
+        }
+        JsonValue::Object(o) => {
+            let ret = PyDict::new(py);
+            for (k, v) in o {
+                ret.set_item(k, json_to_py(v, py)).unwrap();
+            }
+            ret.into()
+        }
     }
 }
 